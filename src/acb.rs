@@ -0,0 +1,335 @@
+//! Canadian adjusted cost base (ACB) calculation.
+//!
+//! Each asset is a single pool, acquisitions add their cost plus fees to
+//! the pool's ACB and a disposal removes the average ACB of the units
+//! disposed. A loss is superficial, and denied, to the extent the same
+//! asset was acquired in the 30 days before or after the disposal and is
+//! still held 30 days after it. The denied loss is added to the pool.
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, TimeZone, Utc};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use serde_utc_time_ms::se_time_ms_to_utc_string;
+
+use crate::error::Error;
+use crate::events::{tax_events, TaxEvent, TaxEventKind};
+use crate::jurisdiction::{Canada, Jurisdiction};
+use crate::price::PriceOracle;
+use crate::TokenTaxRec;
+
+pub const SUPERFICIAL_LOSS_WINDOW_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AcbPool {
+    pub quantity: Decimal,
+    pub acb: Decimal,
+}
+
+impl AcbPool {
+    /// The ACB of `quantity` units at the pool's average, nothing from an
+    /// empty pool
+    fn acb_of(&self, quantity: Decimal) -> Decimal {
+        if self.quantity.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.acb * quantity / self.quantity
+        }
+    }
+}
+
+/// A disposal as reported on Schedule 3, amounts are in the home currency
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Schedule3Row {
+    #[serde(rename = "Date")]
+    #[serde(serialize_with = "se_time_ms_to_utc_string")]
+    pub time: i64,
+
+    pub asset: String,
+    pub quantity: Decimal,
+    pub proceeds: Decimal,
+    pub acb: Decimal,
+    pub outlays: Decimal,
+
+    /// Portion of the loss denied by the superficial loss rule
+    pub superficial_loss: Decimal,
+
+    /// proceeds - acb - outlays + superficial_loss
+    pub gain: Decimal,
+
+    #[serde(skip)]
+    pub rec_idx: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AcbReport {
+    pub rows: Vec<Schedule3Row>,
+
+    /// Pools remaining after the last record
+    pub pools: BTreeMap<String, AcbPool>,
+}
+
+impl AcbReport {
+    pub fn rows_for_year(&self, year: i32) -> impl Iterator<Item = &Schedule3Row> {
+        self.rows
+            .iter()
+            .filter(move |row| Utc.timestamp_millis_opt(row.time).unwrap().year() == year)
+    }
+
+    pub fn total_gain(&self, year: i32) -> Decimal {
+        self.rows_for_year(year).map(|row| row.gain).sum()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AcbCalculator<J: Jurisdiction = Canada> {
+    pub jurisdiction: J,
+}
+
+impl AcbCalculator<Canada> {
+    pub fn new() -> AcbCalculator<Canada> {
+        AcbCalculator {
+            jurisdiction: Canada::new(),
+        }
+    }
+}
+
+impl<J: Jurisdiction> AcbCalculator<J> {
+    pub fn with_jurisdiction(jurisdiction: J) -> AcbCalculator<J> {
+        AcbCalculator { jurisdiction }
+    }
+
+    pub fn calculate<O: PriceOracle + ?Sized>(
+        &self,
+        recs: &[TokenTaxRec],
+        oracle: &O,
+    ) -> Result<AcbReport, Error> {
+        let mut events = tax_events(recs, &self.jurisdiction, oracle)?;
+        events.sort_by_key(|e| e.time);
+
+        let mut report = AcbReport::default();
+        for event in events.iter() {
+            let pool = report.pools.entry(event.asset.clone()).or_default();
+            match event.kind {
+                TaxEventKind::Acquire => {
+                    pool.quantity += event.quantity;
+                    pool.acb += event.value + event.fee;
+                }
+                TaxEventKind::Remove => {
+                    if event.quantity > pool.quantity {
+                        return Err(Error::InsufficientHoldings {
+                            asset: event.asset.clone(),
+                            rec_idx: event.rec_idx,
+                        });
+                    }
+                    pool.acb -= pool.acb_of(event.quantity);
+                    pool.quantity -= event.quantity;
+                }
+                TaxEventKind::Dispose => {
+                    if event.quantity > pool.quantity {
+                        return Err(Error::InsufficientHoldings {
+                            asset: event.asset.clone(),
+                            rec_idx: event.rec_idx,
+                        });
+                    }
+                    let acb = pool.acb_of(event.quantity);
+                    pool.acb -= acb;
+                    pool.quantity -= event.quantity;
+
+                    let mut gain = event.value - acb - event.fee;
+                    let mut superficial_loss = Decimal::ZERO;
+                    if gain < Decimal::ZERO {
+                        superficial_loss = -gain * superficial_fraction(&events, event);
+                        gain += superficial_loss;
+                        pool.acb += superficial_loss;
+                    }
+
+                    report.rows.push(Schedule3Row {
+                        time: event.time,
+                        asset: event.asset.clone(),
+                        quantity: event.quantity,
+                        proceeds: event.value,
+                        acb,
+                        outlays: event.fee,
+                        superficial_loss,
+                        gain,
+                        rec_idx: event.rec_idx,
+                    });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl Default for AcbCalculator<Canada> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fraction of the disposal's loss that is superficial
+fn superficial_fraction(events: &[TaxEvent], disposal: &TaxEvent) -> Decimal {
+    let start = disposal.time - SUPERFICIAL_LOSS_WINDOW_MS;
+    let end = disposal.time + SUPERFICIAL_LOSS_WINDOW_MS;
+
+    let mut acquired = Decimal::ZERO;
+    let mut held_at_end = Decimal::ZERO;
+    for e in events.iter().filter(|e| e.asset == disposal.asset) {
+        if e.time > end {
+            break;
+        }
+        match e.kind {
+            TaxEventKind::Acquire => {
+                held_at_end += e.quantity;
+                if e.time >= start {
+                    acquired += e.quantity;
+                }
+            }
+            TaxEventKind::Dispose | TaxEventKind::Remove => held_at_end -= e.quantity,
+        }
+    }
+
+    let denied = disposal.quantity.min(acquired).min(held_at_end);
+    if denied <= Decimal::ZERO {
+        Decimal::ZERO
+    } else {
+        denied / disposal.quantity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_acb_pooling() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,1000,CAD,10,CAD,kraken,,,2021-01-01 00:00:00
+Trade,1,ETH,2000,CAD,,,kraken,,,2021-02-01 00:00:00
+Trade,3000,CAD,1,ETH,15,CAD,kraken,,,2021-06-01 00:00:00
+Trade,1500,CAD,1,ETH,,,kraken,,,2022-06-01 00:00:00
+",
+        );
+        let report = AcbCalculator::new()
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+
+        assert_eq!(report.rows.len(), 2);
+        let row = &report.rows[0];
+        assert_eq!(row.asset, "ETH");
+        assert_eq!(row.proceeds, dec!(3000));
+        assert_eq!(row.acb, dec!(1505));
+        assert_eq!(row.outlays, dec!(15));
+        assert_eq!(row.gain, dec!(1480));
+        assert_eq!(row.rec_idx, 2);
+
+        // Loss with no repurchase isn't superficial
+        let row = &report.rows[1];
+        assert_eq!(row.acb, dec!(1505));
+        assert_eq!(row.superficial_loss, dec!(0));
+        assert_eq!(row.gain, dec!(-5));
+
+        assert_eq!(report.total_gain(2021), dec!(1480));
+        assert_eq!(report.total_gain(2022), dec!(-5));
+        assert_eq!(report.pools["ETH"], AcbPool::default());
+    }
+
+    #[test]
+    fn test_acb_superficial_loss() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,2,ETH,4000,CAD,,,kraken,,,2021-01-01 00:00:00
+Trade,2000,CAD,2,ETH,,,kraken,,,2021-03-01 00:00:00
+Trade,1,ETH,1100,CAD,,,kraken,,,2021-03-15 00:00:00
+",
+        );
+        let report = AcbCalculator::new()
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+
+        // 1 of the 2 ETH was reacquired, so half the loss is denied
+        // and added to the ACB of the reacquired ETH.
+        let row = &report.rows[0];
+        assert_eq!(row.gain, dec!(-1000));
+        assert_eq!(row.superficial_loss, dec!(1000));
+        assert_eq!(
+            report.pools["ETH"],
+            AcbPool {
+                quantity: dec!(1),
+                acb: dec!(2100),
+            }
+        );
+    }
+
+    #[test]
+    fn test_acb_priced_in_other_currency() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1000,USD,1200,CAD,,,binance.us,,,2021-01-01 00:00:00
+Trade,1,ETH,1000,USD,,,binance.us,,,2021-01-02 00:00:00
+Spend,,,1,ETH,,,,,,2021-02-01 00:00:00
+",
+        );
+        let mut pt = PriceTable::new();
+        pt.insert("USD", "CAD", 0, dec!(1.25));
+        pt.insert("ETH", "CAD", 0, dec!(2000));
+
+        let report = AcbCalculator::new().calculate(&recs, &pt).unwrap();
+
+        // Foreign currency is property too
+        let row = &report.rows[0];
+        assert_eq!(row.asset, "USD");
+        assert_eq!(row.acb, dec!(1200));
+        assert_eq!(row.proceeds, dec!(1250));
+        assert_eq!(row.gain, dec!(50));
+
+        let row = &report.rows[1];
+        assert_eq!(row.asset, "ETH");
+        assert_eq!(row.acb, dec!(1250));
+        assert_eq!(row.proceeds, dec!(2000));
+        assert_eq!(row.gain, dec!(750));
+    }
+
+    #[test]
+    fn test_acb_insufficient_holdings() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1000,CAD,1,ETH,,,kraken,,,2021-01-01 00:00:00
+",
+        );
+        assert!(matches!(
+            AcbCalculator::new().calculate(&recs, &PriceTable::new()),
+            Err(Error::InsufficientHoldings { rec_idx: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_acb_zero_quantity() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Lost,,,0,ETH,,,,,,2021-01-01 00:00:00
+Trade,0,CAD,0,BTC,,,kraken,,,2021-01-02 00:00:00
+",
+        );
+        let report = AcbCalculator::new()
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+        assert_eq!(report.rows.len(), 2);
+        assert!(report
+            .rows
+            .iter()
+            .all(|r| r.acb.is_zero() && r.gain.is_zero()));
+    }
+}
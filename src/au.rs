@@ -0,0 +1,198 @@
+//! Australian capital gains tax.
+//!
+//! The financial year runs from 1 July to 30 June and gains on assets held
+//! at least 12 months are discountable. Capital losses, including losses
+//! carried forward from earlier years, are applied to non-discountable
+//! gains first and the discount is applied to what remains of the
+//! discountable gains.
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, TimeZone, Utc};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::error::Error;
+use crate::events::tax_events;
+use crate::jurisdiction::{Australia, Jurisdiction};
+use crate::lots::{LotDisposal, LotEngine};
+use crate::price::PriceOracle;
+use crate::TokenTaxRec;
+
+/// The financial year containing `time`, identified by the year it ends
+/// in, so 2022 is 1 July 2021 to 30 June 2022.
+pub fn financial_year(time: i64) -> i32 {
+    let dt = Utc.timestamp_millis_opt(time).unwrap();
+    if dt.month() >= 7 {
+        dt.year() + 1
+    } else {
+        dt.year()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuDisposal {
+    pub lot: LotDisposal,
+    pub financial_year: i32,
+
+    /// Held longer than the profile's long term period, 12 months, so a
+    /// gain is eligible for the discount
+    pub discountable: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CgtSummary {
+    pub discountable_gains: Decimal,
+    pub non_discountable_gains: Decimal,
+    pub losses: Decimal,
+
+    /// Losses from earlier years applied this year
+    pub prior_losses_applied: Decimal,
+
+    pub discount: Decimal,
+    pub net_capital_gain: Decimal,
+
+    /// Losses remaining to carry into the next year
+    pub carried_forward_loss: Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuReport {
+    pub disposals: Vec<AuDisposal>,
+    pub years: BTreeMap<i32, CgtSummary>,
+}
+
+/// Lots are consumed in the order of the profile's cost basis and the
+/// holding period for the discount is its long term period
+#[derive(Clone, Debug)]
+pub struct AuCalculator<J: Jurisdiction = Australia> {
+    pub jurisdiction: J,
+    pub discount_rate: Decimal,
+}
+
+impl AuCalculator<Australia> {
+    pub fn new() -> AuCalculator<Australia> {
+        AuCalculator::with_jurisdiction(Australia::new())
+    }
+}
+
+impl<J: Jurisdiction> AuCalculator<J> {
+    pub fn with_jurisdiction(jurisdiction: J) -> AuCalculator<J> {
+        AuCalculator {
+            jurisdiction,
+            discount_rate: dec!(0.5),
+        }
+    }
+
+    pub fn calculate<O: PriceOracle + ?Sized>(
+        &self,
+        recs: &[TokenTaxRec],
+        oracle: &O,
+    ) -> Result<AuReport, Error> {
+        let events = tax_events(recs, &self.jurisdiction, oracle)?;
+        let method = self.jurisdiction.cost_basis().lot_method();
+        let long_term_months = self.jurisdiction.long_term_months();
+        let lots = LotEngine::new(method).process(&events)?;
+
+        let mut report = AuReport::default();
+        for lot in lots {
+            let financial_year = self.jurisdiction.tax_year(lot.disposed_time);
+            let discountable = long_term_months.is_some_and(|m| lot.held_longer_than(m));
+            let gain = lot.gain();
+
+            let summary = report.years.entry(financial_year).or_default();
+            if gain < Decimal::ZERO {
+                summary.losses -= gain;
+            } else if discountable {
+                summary.discountable_gains += gain;
+            } else {
+                summary.non_discountable_gains += gain;
+            }
+            report.disposals.push(AuDisposal {
+                lot,
+                financial_year,
+                discountable,
+            });
+        }
+
+        let mut carried = Decimal::ZERO;
+        for summary in report.years.values_mut() {
+            let available = summary.losses + carried;
+
+            let applied = available.min(summary.non_discountable_gains);
+            let non_discountable = summary.non_discountable_gains - applied;
+            let remaining = available - applied;
+
+            let applied = remaining.min(summary.discountable_gains);
+            let discountable = summary.discountable_gains - applied;
+            let remaining = remaining - applied;
+
+            summary.prior_losses_applied =
+                (available - remaining - summary.losses).max(Decimal::ZERO);
+            summary.discount = discountable * self.discount_rate;
+            summary.net_capital_gain = non_discountable + discountable - summary.discount;
+            summary.carried_forward_loss = remaining;
+            carried = remaining;
+        }
+
+        Ok(report)
+    }
+}
+
+impl Default for AuCalculator<Australia> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::price::PriceTable;
+    use crate::test_utils::{recs_from_csv, time_ms};
+
+    #[test]
+    fn test_financial_year() {
+        assert_eq!(financial_year(time_ms("2021-06-30 23:59:59")), 2021);
+        assert_eq!(financial_year(time_ms("2021-07-01 00:00:00")), 2022);
+        assert_eq!(financial_year(time_ms("2022-01-01 00:00:00")), 2022);
+    }
+
+    #[test]
+    fn test_au_cgt() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,BTC,10000,AUD,,,coinspot,,,2020-01-01 00:00:00
+Trade,1,ETH,3000,AUD,,,coinspot,,,2020-12-01 00:00:00
+Trade,2000,AUD,1,ETH,,,coinspot,,,2021-03-01 00:00:00
+Trade,1,ETH,2000,AUD,,,coinspot,,,2021-08-01 00:00:00
+Trade,4000,AUD,1,ETH,,,coinspot,,,2021-09-01 00:00:00
+Trade,20000,AUD,1,BTC,,,coinspot,,,2021-10-01 00:00:00
+",
+        );
+        let report = AuCalculator::new()
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+
+        assert_eq!(report.disposals.len(), 3);
+        assert!(!report.disposals[0].discountable);
+        assert_eq!(report.disposals[0].financial_year, 2021);
+        assert!(report.disposals[2].discountable);
+
+        // FY2021 has only a loss which is carried forward
+        let fy2021 = &report.years[&2021];
+        assert_eq!(fy2021.losses, dec!(1000));
+        assert_eq!(fy2021.net_capital_gain, dec!(0));
+        assert_eq!(fy2021.carried_forward_loss, dec!(1000));
+
+        // FY2022 applies the loss to the non-discountable ETH gain then
+        // discounts the BTC gain.
+        let fy2022 = &report.years[&2022];
+        assert_eq!(fy2022.non_discountable_gains, dec!(2000));
+        assert_eq!(fy2022.discountable_gains, dec!(10000));
+        assert_eq!(fy2022.prior_losses_applied, dec!(1000));
+        assert_eq!(fy2022.discount, dec!(5000));
+        assert_eq!(fy2022.net_capital_gain, dec!(6000));
+        assert_eq!(fy2022.carried_forward_loss, dec!(0));
+    }
+}
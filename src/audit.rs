@@ -0,0 +1,291 @@
+//! Provenance of records, where each came from and how it was changed
+//! since, so the path from an exchange's data to a report can be shown.
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::{field_changes, FieldChange, TokenTaxRec};
+
+/// The file and line a record was read from
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Source {
+    pub file: String,
+    pub line: u64,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuditKind {
+    /// Created from an exchange's or another tool's format
+    Conversion,
+    Reclassification,
+
+    /// Duplicates were merged into the record
+    Merge,
+    ManualEdit,
+}
+
+impl Display for AuditKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for AuditKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Conversion" => Ok(AuditKind::Conversion),
+            "Reclassification" => Ok(AuditKind::Reclassification),
+            "Merge" => Ok(AuditKind::Merge),
+            "ManualEdit" => Ok(AuditKind::ManualEdit),
+            _ => Err(format!("Unknown audit kind {s}")),
+        }
+    }
+}
+
+/// One transformation of a record
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditStep {
+    pub kind: AuditKind,
+
+    /// The rule or converter responsible, if any
+    pub rule: Option<String>,
+
+    /// The fields changed with their previous values
+    pub changes: Vec<FieldChange>,
+
+    /// The sources of records merged into this one
+    pub merged: Vec<Source>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Provenance {
+    pub source: Option<Source>,
+    pub steps: Vec<AuditStep>,
+}
+
+impl Provenance {
+    /// Names of the rules applied, in order
+    pub fn rules(&self) -> Vec<&str> {
+        self.steps
+            .iter()
+            .filter_map(|s| s.rule.as_deref())
+            .collect()
+    }
+}
+
+impl TokenTaxRec {
+    /// Apply `edit` and record the fields it changed, returns true if any
+    /// changed.
+    pub fn edit<F>(&mut self, kind: AuditKind, rule: Option<&str>, edit: F) -> bool
+    where
+        F: FnOnce(&mut TokenTaxRec),
+    {
+        let before = self.clone();
+        edit(self);
+        let changes = field_changes(&before, self);
+        if changes.is_empty() {
+            return false;
+        }
+        self.provenance.steps.push(AuditStep {
+            kind,
+            rule: rule.map(|r| r.to_owned()),
+            changes,
+            merged: Vec::new(),
+        });
+        true
+    }
+}
+
+/// Read a TokenTax csv file recording `file` and the line of each record
+pub fn read_csv_with_source<R: Read>(rdr: R, file: &str) -> Result<Vec<TokenTaxRec>, Error> {
+    let mut reader = csv::Reader::from_reader(rdr);
+    let headers = reader.headers()?.clone();
+    let mut record = csv::StringRecord::new();
+    let mut recs = Vec::new();
+    while reader.read_record(&mut record)? {
+        let mut rec: TokenTaxRec = record.deserialize(Some(&headers))?;
+        rec.provenance.source = Some(Source {
+            file: file.to_owned(),
+            line: record.position().map_or(0, |p| p.line()),
+        });
+        recs.push(rec);
+    }
+    Ok(recs)
+}
+
+/// Header of the audit log written by `write_audit_log`
+pub const AUDIT_LOG_HEADER: [&str; 9] = [
+    "Fingerprint",
+    "Source",
+    "Step",
+    "Kind",
+    "Rule",
+    "Field",
+    "Old",
+    "New",
+    "Merged",
+];
+
+/// Write the provenance of `recs` as csv, a row per changed field of each
+/// step and a row with just the source for records never changed.
+pub fn write_audit_log<'a, W, I>(recs: I, wtr: W) -> Result<(), Error>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a TokenTaxRec>,
+{
+    let mut writer = csv::Writer::from_writer(wtr);
+    writer.write_record(AUDIT_LOG_HEADER)?;
+    for rec in recs {
+        let fingerprint = rec.fingerprint();
+        let source = rec
+            .provenance
+            .source
+            .as_ref()
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        if rec.provenance.steps.is_empty() {
+            writer.write_record([fingerprint.as_str(), &source, "", "", "", "", "", "", ""])?;
+        }
+        for (idx, step) in rec.provenance.steps.iter().enumerate() {
+            let step_no = (idx + 1).to_string();
+            let kind = step.kind.to_string();
+            let rule = step.rule.as_deref().unwrap_or("");
+            let merged = step
+                .merged
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            if step.changes.is_empty() {
+                writer.write_record([
+                    fingerprint.as_str(),
+                    &source,
+                    &step_no,
+                    &kind,
+                    rule,
+                    "",
+                    "",
+                    "",
+                    &merged,
+                ])?;
+            }
+            for change in step.changes.iter() {
+                writer.write_record([
+                    fingerprint.as_str(),
+                    &source,
+                    &step_no,
+                    &kind,
+                    rule,
+                    change.field,
+                    &change.old,
+                    &change.new,
+                    &merged,
+                ])?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rules::RuleSet;
+    use crate::{dedup, TokenTaxRecType};
+
+    const CSV: &str = "Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,0.01,ETH,,,,,coinbase,,\"Staking reward\",2022-01-01 00:00:00
+Deposit,1,ETH,,,,,coinbase,,\"Two
+lines\",2022-01-02 00:00:00
+Deposit,0.01,ETH,,,,,coinbase,,\"Staking reward\",2022-01-01 00:00:00
+";
+
+    #[test]
+    fn test_provenance() {
+        let mut recs = read_csv_with_source(CSV.as_bytes(), "coinbase.csv").unwrap();
+        let lines: Vec<u64> = recs
+            .iter()
+            .map(|r| r.provenance.source.as_ref().unwrap().line)
+            .collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+
+        assert_eq!(dedup(&mut recs), 1);
+        let step = &recs[0].provenance.steps[0];
+        assert_eq!(step.kind, AuditKind::Merge);
+        assert_eq!(step.merged[0].to_string(), "coinbase.csv:5");
+
+        let rules = RuleSet::parse(
+            "[[rule]]\nname = \"staking\"\ncomment = \"Staking\"\nset_type = \"Income\"",
+        )
+        .unwrap();
+        rules.apply(&mut recs).unwrap();
+        let gift = |r: &mut TokenTaxRec| r.comment = "Gift".to_owned();
+        assert!(recs[1].edit(AuditKind::ManualEdit, None, gift));
+        assert!(!recs[1].edit(AuditKind::ManualEdit, None, gift));
+
+        assert_eq!(recs[0].type_txs, TokenTaxRecType::Income);
+        assert_eq!(recs[0].provenance.rules(), vec!["staking"]);
+        let step = &recs[0].provenance.steps[1];
+        assert_eq!(step.kind, AuditKind::Reclassification);
+        assert_eq!(step.changes[0].old, "Deposit");
+
+        // Provenance isn't part of a record's identity
+        let mut plain = recs[0].clone();
+        plain.provenance = Provenance::default();
+        assert_eq!(plain, recs[0]);
+        assert_eq!(plain.fingerprint(), recs[0].fingerprint());
+
+        let mut buf = Vec::new();
+        write_audit_log(&recs, &mut buf).unwrap();
+        let mut reader = csv::Reader::from_reader(buf.as_slice());
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|r| r.unwrap().iter().skip(1).map(|f| f.to_owned()).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    "coinbase.csv:2",
+                    "1",
+                    "Merge",
+                    "",
+                    "",
+                    "",
+                    "",
+                    "coinbase.csv:5"
+                ],
+                vec![
+                    "coinbase.csv:2",
+                    "2",
+                    "Reclassification",
+                    "staking",
+                    "type",
+                    "Deposit",
+                    "Income",
+                    ""
+                ],
+                vec![
+                    "coinbase.csv:3",
+                    "1",
+                    "ManualEdit",
+                    "",
+                    "comment",
+                    "Two\nlines",
+                    "Gift",
+                    ""
+                ],
+            ]
+        );
+    }
+}
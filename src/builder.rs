@@ -0,0 +1,332 @@
+//! Typed constructors for `TokenTaxRec`, e.g.
+//!
+//! ```ignore
+//! let rec = TokenTaxRec::trade(Amount::new(dec!(1), "ETH"), Amount::new(dec!(3000), "USD"))
+//!     .fee(Amount::new(dec!(0.001), "BNB"))
+//!     .exchange("binance.us")
+//!     .at(time)?;
+//! ```
+//!
+//! The record is validated when `at` builds it.
+use rust_decimal::prelude::*;
+
+use crate::audit::Provenance;
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::{GroupType, TokenTaxRec, TokenTaxRecType};
+
+#[derive(Clone, Debug)]
+pub struct RecBuilder {
+    type_txs: TokenTaxRecType,
+    buy: Option<Amount>,
+    sell: Option<Amount>,
+    fee: Option<Amount>,
+    exchange: String,
+    group: Option<GroupType>,
+    comment: String,
+}
+
+impl RecBuilder {
+    fn new(type_txs: TokenTaxRecType, buy: Option<Amount>, sell: Option<Amount>) -> RecBuilder {
+        RecBuilder {
+            type_txs,
+            buy,
+            sell,
+            fee: None,
+            exchange: String::new(),
+            group: None,
+            comment: String::new(),
+        }
+    }
+
+    pub fn fee(mut self, fee: Amount) -> RecBuilder {
+        self.fee = Some(fee);
+        self
+    }
+
+    pub fn exchange(mut self, exchange: &str) -> RecBuilder {
+        self.exchange = exchange.to_owned();
+        self
+    }
+
+    pub fn comment(mut self, comment: &str) -> RecBuilder {
+        self.comment = comment.to_owned();
+        self
+    }
+
+    /// Only trades may be margin trades
+    pub fn margin(mut self) -> RecBuilder {
+        self.group = Some(GroupType::Margin);
+        self
+    }
+
+    /// True if every leg bought or sold is zero, e.g. a dust conversion
+    pub(crate) fn moves_nothing(&self) -> bool {
+        [&self.buy, &self.sell]
+            .into_iter()
+            .flatten()
+            .all(|a| a.amount.is_zero())
+    }
+
+    /// Build the record at `time`, ms since the epoch
+    pub fn at(self, time: i64) -> Result<TokenTaxRec, Error> {
+        let invalid = |msg: String| Err(Error::InvalidRecord { msg });
+        for (name, leg) in [("buy", &self.buy), ("sell", &self.sell)] {
+            if let Some(a) = leg {
+                if a.amount <= Decimal::ZERO {
+                    return invalid(format!("{name} amount {} must be positive", a.amount));
+                }
+            }
+        }
+        if let Some(fee) = &self.fee {
+            if fee.amount < Decimal::ZERO {
+                return invalid(format!("fee amount {} is negative", fee.amount));
+            }
+        }
+        for leg in [&self.buy, &self.sell, &self.fee].into_iter().flatten() {
+            if leg.currency.is_empty() || leg.currency.contains(char::is_whitespace) {
+                return invalid(format!("bad currency \"{}\"", leg.currency));
+            }
+        }
+        if let (Some(buy), Some(sell)) = (&self.buy, &self.sell) {
+            if buy.currency == sell.currency {
+                return invalid(format!("trade buys and sells {}", buy.currency));
+            }
+        }
+        if self.group.is_some() && self.type_txs != TokenTaxRecType::Trade {
+            return invalid(format!("{} can't be a margin trade", self.type_txs));
+        }
+
+        let split = |a: Option<Amount>| match a {
+            Some(a) => (Some(a.amount), a.currency),
+            None => (None, String::new()),
+        };
+        let (buy_amount, buy_currency) = split(self.buy);
+        let (sell_amount, sell_currency) = split(self.sell);
+        let (fee_amount, fee_currency) = split(self.fee);
+        Ok(TokenTaxRec {
+            type_txs: self.type_txs,
+            buy_amount,
+            buy_currency,
+            sell_amount,
+            sell_currency,
+            fee_amount,
+            fee_currency,
+            exchange: self.exchange,
+            group: self.group,
+            comment: self.comment,
+            time,
+            provenance: Provenance::default(),
+        })
+    }
+}
+
+impl TokenTaxRec {
+    pub fn trade(buy: Amount, sell: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Trade, Some(buy), Some(sell))
+    }
+
+    pub fn deposit(buy: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Deposit, Some(buy), None)
+    }
+
+    pub fn withdrawal(sell: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Withdrawal, None, Some(sell))
+    }
+
+    pub fn income(buy: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Income, Some(buy), None)
+    }
+
+    pub fn mining(buy: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Mining, Some(buy), None)
+    }
+
+    pub fn spend(sell: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Spend, None, Some(sell))
+    }
+
+    pub fn gift(sell: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Gift, None, Some(sell))
+    }
+
+    pub fn lost(sell: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Lost, None, Some(sell))
+    }
+
+    pub fn stolen(sell: Amount) -> RecBuilder {
+        RecBuilder::new(TokenTaxRecType::Stolen, None, Some(sell))
+    }
+
+    /// Check the record is one the builder could have made, with the legs
+    /// its type uses and no others
+    pub fn validate(&self, rec_idx: usize) -> Result<(), Error> {
+        let buy = || {
+            self.buy_leg().ok_or(Error::MissingField {
+                rec_idx,
+                field: "buy_amount",
+            })
+        };
+        let sell = || {
+            self.sell_leg().ok_or(Error::MissingField {
+                rec_idx,
+                field: "sell_amount",
+            })
+        };
+        let (mut builder, unused) = match self.type_txs {
+            TokenTaxRecType::Unknown => return Err(Error::UnsupportedRecord { rec_idx }),
+            TokenTaxRecType::Trade => (TokenTaxRec::trade(buy()?, sell()?), None),
+            TokenTaxRecType::Deposit => (TokenTaxRec::deposit(buy()?), Some("sell")),
+            TokenTaxRecType::Income => (TokenTaxRec::income(buy()?), Some("sell")),
+            TokenTaxRecType::Mining => (TokenTaxRec::mining(buy()?), Some("sell")),
+            TokenTaxRecType::Withdrawal => (TokenTaxRec::withdrawal(sell()?), Some("buy")),
+            TokenTaxRecType::Spend => (TokenTaxRec::spend(sell()?), Some("buy")),
+            TokenTaxRecType::Gift => (TokenTaxRec::gift(sell()?), Some("buy")),
+            TokenTaxRecType::Lost => (TokenTaxRec::lost(sell()?), Some("buy")),
+            TokenTaxRecType::Stolen => (TokenTaxRec::stolen(sell()?), Some("buy")),
+        };
+        let has_leg =
+            |amount: Option<Decimal>, currency: &str| amount.is_some() || !currency.is_empty();
+        let unused_leg = match unused {
+            Some("buy") => has_leg(self.buy_amount, &self.buy_currency),
+            Some(_) => has_leg(self.sell_amount, &self.sell_currency),
+            None => false,
+        };
+        let invalid = |msg: String| Error::InvalidRecord {
+            msg: format!("Record {rec_idx}: {msg}"),
+        };
+        if unused_leg {
+            let leg = unused.expect("SNH");
+            return Err(invalid(format!("{} has a {leg} leg", self.type_txs)));
+        }
+        match self.fee_leg() {
+            Some(fee) => builder = builder.fee(fee),
+            None if has_leg(self.fee_amount, &self.fee_currency) => {
+                return Err(Error::MissingField {
+                    rec_idx,
+                    field: match self.fee_amount {
+                        Some(_) => "fee_currency",
+                        None => "fee_amount",
+                    },
+                })
+            }
+            None => {}
+        }
+        if self.group.is_some() {
+            builder = builder.margin();
+        }
+        builder.at(self.time).map(|_| ()).map_err(|e| match e {
+            Error::InvalidRecord { msg } => invalid(msg),
+            e => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_builder() {
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,margin,,1970-01-01 00:00:01
+Deposit,5125,USD,,,,,binance.us,,,1970-01-01 00:00:00
+Spend,,,100,USD,0.01,USD,,,\"Gift for wife\",1970-01-01 00:00:00
+",
+        );
+        let recs = vec![
+            TokenTaxRec::trade(Amount::new(dec!(1), "ETH"), Amount::new(dec!(3123), "USD"))
+                .fee(Amount::new(dec!(0.00124), "BNB"))
+                .exchange("binance.us")
+                .margin()
+                .at(1000)
+                .unwrap(),
+            TokenTaxRec::deposit(Amount::new(dec!(5125), "USD"))
+                .exchange("binance.us")
+                .at(0)
+                .unwrap(),
+            TokenTaxRec::spend(Amount::new(dec!(100), "USD"))
+                .fee(Amount::new(dec!(0.01), "USD"))
+                .comment("Gift for wife")
+                .at(0)
+                .unwrap(),
+        ];
+        assert_eq!(recs, expected);
+        assert_eq!(recs[1].get_asset(), "USD");
+        assert_eq!(
+            TokenTaxRec::stolen(Amount::new(dec!(1), "ETH"))
+                .at(0)
+                .unwrap()
+                .get_quantity(),
+            dec!(1)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3123,USD,0.00124,BNB,binance.us,margin,,1970-01-01 00:00:01
+Trade,1,ETH,,,,,,,,1970-01-01 00:00:00
+Deposit,5125,USD,1,ETH,,,,,,1970-01-01 00:00:00
+Spend,,,100,USD,0.01,,,,,1970-01-01 00:00:00
+Gift,,,-1,ETH,,,,,,1970-01-01 00:00:00
+Income,1,ETH,,,,,,margin,,1970-01-01 00:00:00
+",
+        );
+        let errs: Vec<String> = recs
+            .iter()
+            .enumerate()
+            .map(|(idx, rec)| match rec.validate(idx) {
+                Ok(()) => "ok".to_owned(),
+                Err(e) => e.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            errs,
+            [
+                "ok",
+                "Record 1: missing sell_amount",
+                "Invalid record: Record 2: Deposit has a sell leg",
+                "Record 3: missing fee_currency",
+                "Invalid record: Record 4: sell amount -1 must be positive",
+                "Invalid record: Record 5: Income can't be a margin trade",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let err = |b: RecBuilder| b.at(0).unwrap_err().to_string();
+        let eth = |amount| Amount::new(amount, "ETH");
+        assert_eq!(
+            err(TokenTaxRec::income(eth(dec!(0)))),
+            "Invalid record: buy amount 0 must be positive"
+        );
+        assert_eq!(
+            err(TokenTaxRec::withdrawal(eth(dec!(-1)))),
+            "Invalid record: sell amount -1 must be positive"
+        );
+        assert_eq!(
+            err(TokenTaxRec::gift(eth(dec!(1))).fee(eth(dec!(-0.1)))),
+            "Invalid record: fee amount -0.1 is negative"
+        );
+        assert_eq!(
+            err(TokenTaxRec::mining(Amount::new(dec!(1), ""))),
+            "Invalid record: bad currency \"\""
+        );
+        assert_eq!(
+            err(TokenTaxRec::trade(eth(dec!(1)), eth(dec!(2)))),
+            "Invalid record: trade buys and sells ETH"
+        );
+        assert_eq!(
+            err(TokenTaxRec::lost(eth(dec!(1))).margin()),
+            "Invalid record: Lost can't be a margin trade"
+        );
+    }
+}
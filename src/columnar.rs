@@ -0,0 +1,310 @@
+//! Records as an Arrow `RecordBatch`, and with the `parquet` feature as
+//! Parquet files, for analytics in tools such as DuckDB or Polars.
+//!
+//! Amounts are decimal128(38, 18), times are timestamp[ms, UTC] and the
+//! type, currency and exchange columns are dictionary encoded. Empty fields
+//! are nulls. Provenance isn't carried.
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, AsArray, Decimal128Array, RecordBatch, StringArray, StringDictionaryBuilder,
+    TimestampMillisecondArray,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Decimal128Type, Field, Int32Type, Schema, SchemaRef, TimeUnit,
+    TimestampMillisecondType,
+};
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::{GroupType, TokenTaxRec};
+
+/// Scale of the amount columns, enough for wei
+pub const AMOUNT_SCALE: i8 = 18;
+
+fn dictionary() -> DataType {
+    DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+}
+
+fn timestamp() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
+
+pub fn schema() -> SchemaRef {
+    let amount = DataType::Decimal128(38, AMOUNT_SCALE);
+    Arc::new(Schema::new(vec![
+        Field::new("type", dictionary(), false),
+        Field::new("buy_amount", amount.clone(), true),
+        Field::new("buy_currency", dictionary(), true),
+        Field::new("sell_amount", amount.clone(), true),
+        Field::new("sell_currency", dictionary(), true),
+        Field::new("fee_amount", amount, true),
+        Field::new("fee_currency", dictionary(), true),
+        Field::new("exchange", dictionary(), true),
+        Field::new("group", DataType::Utf8, true),
+        Field::new("comment", DataType::Utf8, true),
+        Field::new("time", timestamp(), false),
+    ]))
+}
+
+fn non_empty(s: &str) -> Option<&str> {
+    Some(s).filter(|s| !s.is_empty())
+}
+
+fn dictionary_array<'a, I: Iterator<Item = Option<&'a str>>>(values: I) -> ArrayRef {
+    let mut builder = StringDictionaryBuilder::<Int32Type>::new();
+    for value in values {
+        builder.append_option(value);
+    }
+    Arc::new(builder.finish())
+}
+
+/// Largest unscaled value of a decimal128(38, _)
+const MAX_AMOUNT: i128 = 10i128.pow(38) - 1;
+
+/// `amount` unscaled at `AMOUNT_SCALE`, `None` if it needs more decimal
+/// places or 38 digits aren't enough
+fn to_unscaled(amount: Decimal) -> Option<i128> {
+    let amount = amount.normalize();
+    let places = (AMOUNT_SCALE as u32).checked_sub(amount.scale())?;
+    amount
+        .mantissa()
+        .checked_mul(10i128.pow(places))
+        .filter(|v| v.abs() <= MAX_AMOUNT)
+}
+
+/// The decimal of unscaled `value` at `scale`, `None` if it doesn't fit a
+/// `Decimal`
+fn from_unscaled(mut value: i128, scale: i8) -> Option<Decimal> {
+    if scale < 0 {
+        value = value.checked_mul(10i128.pow(scale.unsigned_abs() as u32))?;
+    }
+    let mut scale = scale.max(0) as u32;
+    // Trailing zeros would overflow the 96 bit mantissa of large amounts
+    while scale > 0 && value % 10 == 0 {
+        value /= 10;
+        scale -= 1;
+    }
+    Decimal::try_from_i128_with_scale(value, scale).ok()
+}
+
+fn amount_array<I: Iterator<Item = (usize, Option<Decimal>)>>(
+    amounts: I,
+    field: &str,
+) -> Result<ArrayRef, Error> {
+    let mut values = Vec::new();
+    for (rec_idx, amount) in amounts {
+        let Some(amount) = amount else {
+            values.push(None);
+            continue;
+        };
+        let Some(value) = to_unscaled(amount) else {
+            return Err(Error::InvalidRecord {
+                msg: format!("Record {rec_idx}: {field} {amount} doesn't fit decimal128(38, 18)"),
+            });
+        };
+        values.push(Some(value));
+    }
+    let array = Decimal128Array::from(values).with_precision_and_scale(38, AMOUNT_SCALE)?;
+    Ok(Arc::new(array))
+}
+
+/// `recs` as a batch with the columns of `schema()`
+pub fn to_record_batch(recs: &[TokenTaxRec]) -> Result<RecordBatch, Error> {
+    let amounts = |get: fn(&TokenTaxRec) -> Option<Decimal>| recs.iter().map(get).enumerate();
+    let strings = |get: fn(&TokenTaxRec) -> &str| recs.iter().map(move |r| non_empty(get(r)));
+    let types: Vec<String> = recs.iter().map(|r| r.type_txs.to_string()).collect();
+    let columns: Vec<ArrayRef> = vec![
+        dictionary_array(types.iter().map(|t| Some(t.as_str()))),
+        amount_array(amounts(|r| r.buy_amount), "buy_amount")?,
+        dictionary_array(strings(|r| &r.buy_currency)),
+        amount_array(amounts(|r| r.sell_amount), "sell_amount")?,
+        dictionary_array(strings(|r| &r.sell_currency)),
+        amount_array(amounts(|r| r.fee_amount), "fee_amount")?,
+        dictionary_array(strings(|r| &r.fee_currency)),
+        dictionary_array(strings(|r| &r.exchange)),
+        Arc::new(StringArray::from_iter(
+            recs.iter()
+                .map(|r| r.group.as_ref().map(|GroupType::Margin| "margin")),
+        )),
+        Arc::new(StringArray::from_iter(strings(|r| &r.comment))),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(recs.iter().map(|r| r.time))
+                .with_timezone("UTC"),
+        ),
+    ];
+    Ok(RecordBatch::try_new(schema(), columns)?)
+}
+
+/// Records of a batch with the columns of `schema()`. Columns are found
+/// by name and cast when of another type, e.g. plain strings for the
+/// dictionaries or decimals of another scale.
+pub fn from_record_batch(batch: &RecordBatch) -> Result<Vec<TokenTaxRec>, Error> {
+    let column = |name: &str| -> Result<&ArrayRef, Error> {
+        let idx = batch.schema().index_of(name)?;
+        Ok(batch.column(idx))
+    };
+    let strings = |name: &str| -> Result<StringArray, Error> {
+        Ok(cast(column(name)?, &DataType::Utf8)?
+            .as_string::<i32>()
+            .clone())
+    };
+    let amounts = |name: &str| -> Result<Decimal128Array, Error> {
+        let array = column(name)?;
+        let array = match array.data_type() {
+            DataType::Decimal128(_, _) => array.clone(),
+            _ => cast(array, &DataType::Decimal128(38, AMOUNT_SCALE))?,
+        };
+        Ok(array.as_primitive::<Decimal128Type>().clone())
+    };
+
+    let types = strings("type")?;
+    let buy_amounts = amounts("buy_amount")?;
+    let buy_currencies = strings("buy_currency")?;
+    let sell_amounts = amounts("sell_amount")?;
+    let sell_currencies = strings("sell_currency")?;
+    let fee_amounts = amounts("fee_amount")?;
+    let fee_currencies = strings("fee_currency")?;
+    let exchanges = strings("exchange")?;
+    let groups = strings("group")?;
+    let comments = strings("comment")?;
+    let times = cast(column("time")?, &timestamp())?;
+    let times = times.as_primitive::<TimestampMillisecondType>();
+
+    let invalid = |rec_idx: usize, msg: String| Error::InvalidRecord {
+        msg: format!("Record {rec_idx}: {msg}"),
+    };
+    let amount = |array: &Decimal128Array, rec_idx: usize| -> Result<Option<Decimal>, Error> {
+        if array.is_null(rec_idx) {
+            return Ok(None);
+        }
+        let value = array.value(rec_idx);
+        match from_unscaled(value, array.scale()) {
+            Some(amount) => Ok(Some(amount.normalize())),
+            None => Err(invalid(
+                rec_idx,
+                format!("amount {value} at scale {} doesn't fit", array.scale()),
+            )),
+        }
+    };
+    let string = |array: &StringArray, rec_idx: usize| -> String {
+        match array.is_null(rec_idx) {
+            true => String::new(),
+            false => array.value(rec_idx).to_owned(),
+        }
+    };
+
+    let mut recs = Vec::with_capacity(batch.num_rows());
+    for rec_idx in 0..batch.num_rows() {
+        let mut rec = TokenTaxRec::new();
+        let type_txs = string(&types, rec_idx);
+        rec.type_txs = type_txs
+            .parse()
+            .map_err(|_| invalid(rec_idx, format!("unknown type {type_txs}")))?;
+        rec.buy_amount = amount(&buy_amounts, rec_idx)?;
+        rec.buy_currency = string(&buy_currencies, rec_idx);
+        rec.sell_amount = amount(&sell_amounts, rec_idx)?;
+        rec.sell_currency = string(&sell_currencies, rec_idx);
+        rec.fee_amount = amount(&fee_amounts, rec_idx)?;
+        rec.fee_currency = string(&fee_currencies, rec_idx);
+        rec.exchange = string(&exchanges, rec_idx);
+        rec.group = match string(&groups, rec_idx).to_lowercase().as_str() {
+            "" => None,
+            "margin" => Some(GroupType::Margin),
+            g => return Err(invalid(rec_idx, format!("unknown group {g}"))),
+        };
+        rec.comment = string(&comments, rec_idx);
+        if times.is_null(rec_idx) {
+            return Err(invalid(rec_idx, "missing time".to_owned()));
+        }
+        rec.time = times.value(rec_idx);
+        recs.push(rec);
+    }
+    Ok(recs)
+}
+
+/// Write `recs` as a Parquet file of a single batch
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: std::io::Write>(recs: &[TokenTaxRec], mut wtr: W) -> Result<(), Error> {
+    let batch = to_record_batch(recs)?;
+    let mut buf = Vec::new();
+    let mut writer = parquet::arrow::ArrowWriter::try_new(&mut buf, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    wtr.write_all(&buf)?;
+    Ok(())
+}
+
+/// Read the records of a Parquet file
+#[cfg(feature = "parquet")]
+pub fn read_parquet<R: std::io::Read>(mut rdr: R) -> Result<Vec<TokenTaxRec>, Error> {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let mut buf = Vec::new();
+    rdr.read_to_end(&mut buf)?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(buf))?.build()?;
+    let mut recs = Vec::new();
+    for batch in reader {
+        recs.extend(from_record_batch(&batch?)?);
+    }
+    Ok(recs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::recs_from_csv;
+    use arrow::array::DictionaryArray;
+    use rust_decimal_macros::dec;
+
+    const RECS: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,binance.us,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.5,USD,0.00124,BNB,binance.us,margin,\"Limit, buy\",2022-01-02 13:14:15
+Income,0.000000000000000001,ETH,,,,,coinbase,,Staking,2022-01-03 00:00:00
+Withdrawal,,,1,ETH,0.001,ETH,binance.us,,,2022-01-08 00:00:00
+Income,100000000000,SHIB,,,,,coinbase,,Airdrop,2022-01-09 00:00:00
+";
+
+    #[test]
+    fn test_record_batch() {
+        let recs = recs_from_csv(RECS);
+        let batch = to_record_batch(&recs).unwrap();
+        assert_eq!(batch.schema(), schema());
+        assert_eq!(batch.num_rows(), 5);
+        let exchanges = batch
+            .column(7)
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        assert_eq!(exchanges.values().len(), 2);
+        assert!(batch.column(3).is_null(0));
+        assert_eq!(from_record_batch(&batch).unwrap(), recs);
+
+        let mut rec = recs[0].clone();
+        rec.buy_amount = Some(dec!(0.0000000000000000001));
+        let err = to_record_batch(&[rec]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid record: Record 0: buy_amount 0.0000000000000000001 doesn't fit decimal128(38, 18)"
+        );
+        let mut rec = recs[0].clone();
+        rec.buy_amount = Some(dec!(100000000000000000000));
+        let err = to_record_batch(&[rec]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid record: Record 0: buy_amount 100000000000000000000 doesn't fit decimal128(38, 18)"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet() {
+        let recs = recs_from_csv(RECS);
+        let mut buf = Vec::new();
+        write_parquet(&recs, &mut buf).unwrap();
+        assert!(buf.starts_with(b"PAR1"));
+        assert_eq!(read_parquet(buf.as_slice()).unwrap(), recs);
+    }
+}
@@ -0,0 +1,324 @@
+//! Coinbase transaction history and Coinbase Advanced, formerly Pro, fills
+//! and account statements.
+use std::io::Read;
+
+use regex::Regex;
+
+use super::{
+    build, field, parse_amount, parse_error, parse_time, required_amount, with_fee,
+    without_thousands_separators, Converter, Table,
+};
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::TokenTaxRec;
+
+/// The transaction history csv from coinbase.com, with or without the
+/// preamble above the header.
+///
+/// `Buy`, `Sell` and their `Advanced Trade` forms are trades against the
+/// price currency, the fee is the `Fees and/or Spread` column. `Convert`
+/// is a trade of the assets in its notes without a fee, the spread is
+/// already taken from the amount received.
+/// Rewards, staking and learning income are `Income`, `Send` and
+/// `Withdrawal` are `Withdrawal` and `Receive` and `Deposit` are `Deposit`.
+pub struct Coinbase;
+
+impl Converter for Coinbase {
+    fn name(&self) -> &'static str {
+        "coinbase"
+    }
+
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let table = Table::read(rdr, |r| {
+            r.iter().any(|f| f == "Timestamp") && r.iter().any(|f| f == "Transaction Type")
+        })?;
+        let time = table.required(&["Timestamp"])?;
+        let kind = table.required(&["Transaction Type"])?;
+        let asset = table.required(&["Asset"])?;
+        let quantity = table.required(&["Quantity Transacted"])?;
+        let price_currency = table.required(&["Price Currency", "Spot Price Currency"])?;
+        let subtotal = table.column(&["Subtotal"]);
+        let fees = table.column(&["Fees and/or Spread", "Fees"]);
+        let notes = table.column(&["Notes"]);
+        let convert_re = Regex::new(r"^Converted ([\d.,]+) (\S+) to ([\d.,]+) (\S+)").expect("SNH");
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let asset = field(record, asset);
+            let price_currency = field(record, price_currency);
+            let qty = Amount::new(required_amount(record, quantity)?.abs(), asset);
+            let notes = notes.map_or("", |idx| field(record, idx));
+            let subtotal = || -> Result<Amount, Error> {
+                let amount = parse_amount(record, subtotal)?
+                    .ok_or_else(|| parse_error(record, "Missing Subtotal".to_owned()))?;
+                Ok(Amount::new(amount.abs(), price_currency))
+            };
+
+            let builder = match field(record, kind) {
+                "Buy" | "Advanced Trade Buy" => TokenTaxRec::trade(qty, subtotal()?),
+                "Sell" | "Advanced Trade Sell" => TokenTaxRec::trade(subtotal()?, qty),
+                "Convert" => {
+                    let captures = convert_re.captures(notes).ok_or_else(|| {
+                        parse_error(record, format!("Can't parse Convert notes {notes}"))
+                    })?;
+                    let to_amount = &captures[3];
+                    let to_amount = without_thousands_separators(to_amount)
+                        .and_then(|a| a.parse().ok())
+                        .ok_or_else(|| parse_error(record, format!("Bad amount {to_amount}")))?;
+                    TokenTaxRec::trade(Amount::new(to_amount, &captures[4]), qty)
+                }
+                "Rewards Income" | "Staking Income" | "Learning Reward" | "Coinbase Earn"
+                | "Inflation Reward" => TokenTaxRec::income(qty),
+                "Receive" | "Deposit" => TokenTaxRec::deposit(qty),
+                "Send" | "Withdrawal" => TokenTaxRec::withdrawal(qty),
+                t => return Err(parse_error(record, format!("Unknown Transaction Type {t}"))),
+            };
+            // A Convert's spread is already out of the amount received
+            let fee = match field(record, kind) {
+                "Convert" => None,
+                _ => parse_amount(record, fees)?,
+            };
+            let builder = with_fee(builder, fee, price_currency)
+                .exchange("coinbase")
+                .comment(notes);
+            recs.extend(build(
+                builder,
+                parse_time(record, time)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+}
+
+/// Coinbase Advanced fills or account statement csv, detected by header.
+///
+/// Fills become trades of the product's base against its quote currency,
+/// the size is in either as its `size unit` says and the fee is in the
+/// `price/fee/total unit`.
+/// From an account statement only deposits and withdrawals are taken, its
+/// `match` and `fee` rows are the trades covered by the fills.
+pub struct CoinbaseAdvanced;
+
+impl Converter for CoinbaseAdvanced {
+    fn name(&self) -> &'static str {
+        "coinbase-advanced"
+    }
+
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let table = Table::read(rdr, |r| r.iter().any(|f| f == "portfolio"))?;
+        if table.column(&["trade id"]).is_some() && table.column(&["side"]).is_some() {
+            self.fills(&table, source)
+        } else {
+            self.account(&table, source)
+        }
+    }
+}
+
+impl CoinbaseAdvanced {
+    fn fills(&self, table: &Table, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let product = table.required(&["product"])?;
+        let side = table.required(&["side"])?;
+        let time = table.required(&["created at"])?;
+        let size = table.required(&["size"])?;
+        let price = table.required(&["price"])?;
+        let size_unit = table.column(&["size unit"]);
+        let fee = table.column(&["fee"]);
+        let fee_unit = table.column(&["price/fee/total unit"]);
+        let trade_id = table.column(&["trade id"]);
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let (base, quote) = field(record, product)
+                .split_once('-')
+                .ok_or_else(|| parse_error(record, "Bad product".to_owned()))?;
+            let size = required_amount(record, size)?;
+            let price = required_amount(record, price)?;
+            let unit = size_unit.map_or("", |idx| field(record, idx));
+            let (base_size, quote_size) = match unit {
+                "" => (size, size * price),
+                unit if unit == base => (size, size * price),
+                unit if unit == quote && !price.is_zero() => ((size / price).normalize(), size),
+                unit => return Err(parse_error(record, format!("Bad size unit {unit}"))),
+            };
+            let base_amount = Amount::new(base_size, base);
+            let quote_amount = Amount::new(quote_size, quote);
+            let builder = match field(record, side) {
+                "BUY" => TokenTaxRec::trade(base_amount, quote_amount),
+                "SELL" => TokenTaxRec::trade(quote_amount, base_amount),
+                s => return Err(parse_error(record, format!("Unknown side {s}"))),
+            };
+            let comment = trade_id.map_or(String::new(), |idx| {
+                format!("trade id: {}", field(record, idx))
+            });
+            let fee_currency = match fee_unit.map(|idx| field(record, idx)) {
+                Some(unit) if !unit.is_empty() => unit,
+                _ => quote,
+            };
+            let builder = with_fee(builder, parse_amount(record, fee)?, fee_currency)
+                .exchange("coinbase-advanced")
+                .comment(&comment);
+            recs.extend(build(
+                builder,
+                parse_time(record, time)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+
+    fn account(&self, table: &Table, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let kind = table.required(&["type"])?;
+        let time = table.required(&["time"])?;
+        let amount = table.required(&["amount"])?;
+        let unit = table.required(&["amount/balance unit"])?;
+        let transfer_id = table.column(&["transfer id"]);
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let amount = Amount::new(required_amount(record, amount)?.abs(), field(record, unit));
+            let builder = match field(record, kind) {
+                "deposit" => TokenTaxRec::deposit(amount),
+                "withdrawal" => TokenTaxRec::withdrawal(amount),
+                "match" | "fee" | "conversion" => continue,
+                t => return Err(parse_error(record, format!("Unknown type {t}"))),
+            };
+            let comment = transfer_id.map_or(String::new(), |idx| {
+                format!("transfer id: {}", field(record, idx))
+            });
+            let builder = builder.exchange("coinbase-advanced").comment(&comment);
+            recs.extend(build(
+                builder,
+                parse_time(record, time)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::convert::converter;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    fn convert(name: &str, csv: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        converter(name)
+            .unwrap()
+            .convert(&mut csv.trim_start().as_bytes(), "export.csv")
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let csv = r#"
+You can use this transaction report to inform your likely tax obligations.
+Transactions
+User,Someone,abc123
+ID,Timestamp,Transaction Type,Asset,Quantity Transacted,Price Currency,Price at Transaction,Subtotal,Total (inclusive of fees and/or spread),Fees and/or Spread,Notes
+1,2022-01-01 00:00:00 UTC,Buy,ETH,1,USD,$3000.00,"$3,000.00","$3,014.99",$14.99,Bought 1 ETH for 3014.99 USD
+2,2022-01-02 00:00:00 UTC,Sell,ETH,-0.5,USD,$3100.00,"$1,550.00","$1,540.00",$10.00,Sold 0.5 ETH for 1540 USD
+3,2022-01-03 00:00:00 UTC,Convert,ETH,-0.1,USD,$3200.00,$320.00,$322.00,$2.00,"Converted 0.1 ETH to 1,234.5 ALGO"
+4,2022-01-04 00:00:00 UTC,Rewards Income,ALGO,0.25,USD,$0.50,$0.13,$0.13,$0.00,
+5,2022-01-05 00:00:00 UTC,Send,ALGO,-100,USD,$0.50,$50.00,$50.00,$0.00,Sent 100 ALGO to ABC
+6,2022-01-06 00:00:00 UTC,Receive,BTC,0.01,USD,"$40,000.00",$400.00,$400.00,$0.00,Received 0.01 BTC from an external account
+7,2022-01-07 00:00:00 UTC,Advanced Trade Buy,BTC,0.01,USD,"$40,000.00",$400.00,$402.00,$2.00,
+8,2022-01-08 00:00:00 UTC,Rewards Income,ALGO,0,USD,$0.50,$0.00,$0.00,$0.00,
+"#;
+        let recs = convert("coinbase", csv).unwrap();
+        let expected = recs_from_csv(
+            r#"
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3000,USD,14.99,USD,coinbase,,Bought 1 ETH for 3014.99 USD,2022-01-01 00:00:00
+Trade,1550,USD,0.5,ETH,10,USD,coinbase,,Sold 0.5 ETH for 1540 USD,2022-01-02 00:00:00
+Trade,1234.5,ALGO,0.1,ETH,,,coinbase,,"Converted 0.1 ETH to 1,234.5 ALGO",2022-01-03 00:00:00
+Income,0.25,ALGO,,,,,coinbase,,,2022-01-04 00:00:00
+Withdrawal,,,100,ALGO,,,coinbase,,Sent 100 ALGO to ABC,2022-01-05 00:00:00
+Deposit,0.01,BTC,,,,,coinbase,,Received 0.01 BTC from an external account,2022-01-06 00:00:00
+Trade,0.01,BTC,400,USD,2,USD,coinbase,,,2022-01-07 00:00:00
+"#,
+        );
+        assert_eq!(recs, expected);
+        let source = recs[2].provenance.source.as_ref().unwrap();
+        assert_eq!(source.to_string(), "export.csv:7");
+        assert_eq!(recs[2].provenance.rules(), vec!["coinbase"]);
+    }
+
+    #[test]
+    fn test_coinbase_errors() {
+        let header = "Timestamp,Transaction Type,Asset,Quantity Transacted,Spot Price Currency,Spot Price at Transaction,Subtotal,Total (inclusive of fees),Fees,Notes\n";
+        let err = |row: &str| convert("coinbase", &format!("{header}{row}\n")).unwrap_err();
+        assert!(matches!(
+            err("2022-01-01T00:00:00Z,Airdrop,ETH,1,USD,3000,,,,"),
+            Error::Parse { line: 2, .. }
+        ));
+        assert!(matches!(
+            err("2022-01-01T00:00:00Z,Buy,ETH,abc,USD,3000,3000,3000,0,"),
+            Error::Parse { line: 2, .. }
+        ));
+        assert!(matches!(
+            err("2022-01-01T00:00:00Z,Convert,ETH,1,USD,3000,3000,3000,0,Swapped"),
+            Error::Parse { line: 2, .. }
+        ));
+        assert!(matches!(
+            convert("coinbase", "Date,Type\n"),
+            Err(Error::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_coinbase_advanced_fills() {
+        let csv = "
+portfolio,trade id,product,side,created at,size,size unit,price,fee,total,price/fee/total unit
+default,101,BTC-USD,BUY,2022-01-01T00:00:00.000Z,0.01,BTC,40000.00,2.00,-402.00,USD
+default,102,ETH-BTC,SELL,2022-01-02T00:00:00.000Z,1.5,ETH,0.07,0.0001,0.1049,BTC
+default,103,ETH-USD,BUY,2022-01-03T00:00:00.000Z,500,USD,2500.00,1.00,-501.00,USD
+default,104,SOL-USDC,SELL,2022-01-04T00:00:00.000Z,150,USDC,100.00,0.5,149.50,USDC
+";
+        let recs = convert("coinbase-advanced", csv).unwrap();
+        assert_eq!(recs.len(), 4);
+        assert_eq!(recs[0].buy_leg(), Some(Amount::new(dec!(0.01), "BTC")));
+        assert_eq!(recs[0].sell_leg(), Some(Amount::new(dec!(400), "USD")));
+        assert_eq!(recs[0].fee_leg(), Some(Amount::new(dec!(2), "USD")));
+        assert_eq!(recs[0].comment, "trade id: 101");
+        assert_eq!(recs[1].buy_leg(), Some(Amount::new(dec!(0.105), "BTC")));
+        assert_eq!(recs[1].sell_leg(), Some(Amount::new(dec!(1.5), "ETH")));
+        assert_eq!(recs[1].fee_leg(), Some(Amount::new(dec!(0.0001), "BTC")));
+        assert_eq!(recs[1].time, time_ms("2022-01-02 00:00:00"));
+
+        // Orders placed in the quote currency
+        assert_eq!(recs[2].buy_leg(), Some(Amount::new(dec!(0.2), "ETH")));
+        assert_eq!(recs[2].sell_leg(), Some(Amount::new(dec!(500), "USD")));
+        assert_eq!(recs[2].fee_leg(), Some(Amount::new(dec!(1), "USD")));
+        assert_eq!(recs[3].buy_leg(), Some(Amount::new(dec!(150), "USDC")));
+        assert_eq!(recs[3].sell_leg(), Some(Amount::new(dec!(1.5), "SOL")));
+        assert_eq!(recs[3].fee_leg(), Some(Amount::new(dec!(0.5), "USDC")));
+    }
+
+    #[test]
+    fn test_coinbase_advanced_account() {
+        let csv = "
+portfolio,type,time,amount,balance,amount/balance unit,transfer id,trade id,order id
+default,deposit,2022-01-01T00:00:00.000Z,1000.00,1000.00,USD,t1,,
+default,match,2022-01-01T01:00:00.000Z,-400.00,600.00,USD,,101,o1
+default,fee,2022-01-01T01:00:00.000Z,-2.00,598.00,USD,,101,o1
+default,withdrawal,2022-01-03T00:00:00.000Z,-0.01,0.00,BTC,t2,,
+";
+        let recs = convert("coinbase-advanced", csv).unwrap();
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,1000,USD,,,,,coinbase-advanced,,transfer id: t1,2022-01-01 00:00:00
+Withdrawal,,,0.01,BTC,,,coinbase-advanced,,transfer id: t2,2022-01-03 00:00:00
+",
+        );
+        assert_eq!(recs, expected);
+    }
+}
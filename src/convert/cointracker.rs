@@ -0,0 +1,191 @@
+//! CoinTracker's csv import format, read and written.
+//!
+//! The format has no exchange, group or comment, times are to the second
+//! and, lacking better tags, `Income` and `Spend` are both tagged `payment`.
+//! Writing reports the records whose exchange or comment is lost, stolen
+//! records and margin trades can't be written.
+use std::io::{Read, Write};
+
+use super::{
+    build, field, format_amount, parse_amount, parse_error, parse_leg, parse_time, with_fee,
+    Converter, Table,
+};
+use crate::error::Error;
+use crate::{TokenTaxRec, TokenTaxRecType};
+
+pub const HEADER: [&str; 8] = [
+    "Date",
+    "Received Quantity",
+    "Received Currency",
+    "Sent Quantity",
+    "Sent Currency",
+    "Fee Amount",
+    "Fee Currency",
+    "Tag",
+];
+
+pub struct CoinTracker;
+
+impl Converter for CoinTracker {
+    fn name(&self) -> &'static str {
+        "cointracker"
+    }
+
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let table = Table::read(rdr, |r| r.iter().any(|f| f == "Received Quantity"))?;
+        let date = table.required(&["Date"])?;
+        let received_quantity = table.required(&["Received Quantity"])?;
+        let received_currency = table.required(&["Received Currency"])?;
+        let sent_quantity = table.required(&["Sent Quantity"])?;
+        let sent_currency = table.required(&["Sent Currency"])?;
+        let fee_amount = table.column(&["Fee Amount"]);
+        let fee_currency = table.column(&["Fee Currency"]);
+        let tag = table.column(&["Tag"]);
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let received = parse_leg(record, received_quantity, received_currency)?;
+            let sent = parse_leg(record, sent_quantity, sent_currency)?;
+            let tag = tag.map_or("", |idx| field(record, idx)).to_lowercase();
+            let builder = match (received, sent) {
+                (Some(received), Some(sent)) => TokenTaxRec::trade(received, sent),
+                (Some(received), None) => match tag.as_str() {
+                    "" => TokenTaxRec::deposit(received),
+                    "mined" => TokenTaxRec::mining(received),
+                    "airdrop" | "fork" | "payment" | "stake" => TokenTaxRec::income(received),
+                    t => return Err(parse_error(record, format!("Unsupported tag {t}"))),
+                },
+                (None, Some(sent)) => match tag.as_str() {
+                    "" => TokenTaxRec::withdrawal(sent),
+                    "payment" => TokenTaxRec::spend(sent),
+                    "gift" | "donation" => TokenTaxRec::gift(sent),
+                    "lost" => TokenTaxRec::lost(sent),
+                    t => return Err(parse_error(record, format!("Unsupported tag {t}"))),
+                },
+                (None, None) => {
+                    return Err(parse_error(record, "Nothing sent or received".to_owned()))
+                }
+            };
+            let fee_currency = fee_currency.map_or("", |idx| field(record, idx));
+            let builder = with_fee(builder, parse_amount(record, fee_amount)?, fee_currency);
+            recs.extend(build(
+                builder,
+                parse_time(record, date)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+}
+
+fn tag(rec: &TokenTaxRec) -> Option<&'static str> {
+    match rec.type_txs {
+        TokenTaxRecType::Trade | TokenTaxRecType::Deposit | TokenTaxRecType::Withdrawal => Some(""),
+        TokenTaxRecType::Income | TokenTaxRecType::Spend => Some("payment"),
+        TokenTaxRecType::Mining => Some("mined"),
+        TokenTaxRecType::Gift => Some("gift"),
+        TokenTaxRecType::Lost => Some("lost"),
+        TokenTaxRecType::Stolen | TokenTaxRecType::Unknown => None,
+    }
+}
+
+/// Write `recs` in CoinTracker's import format, returns the indices of
+/// the records whose exchange or comment wasn't written
+pub fn write_csv<'a, W, I>(recs: I, wtr: W) -> Result<Vec<usize>, Error>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a TokenTaxRec>,
+{
+    let mut writer = csv::Writer::from_writer(wtr);
+    writer.write_record(HEADER)?;
+    let mut dropped = Vec::new();
+    for (rec_idx, rec) in recs.into_iter().enumerate() {
+        let tag = tag(rec).ok_or(Error::UnsupportedRecord { rec_idx })?;
+        if rec.group.is_some() {
+            return Err(Error::UnsupportedRecord { rec_idx });
+        }
+        if !rec.exchange.is_empty() || !rec.comment.is_empty() {
+            dropped.push(rec_idx);
+        }
+        let date = chrono::DateTime::from_timestamp_millis(rec.time).expect("SNH");
+        writer.write_record([
+            date.format("%m/%d/%Y %H:%M:%S").to_string(),
+            format_amount(rec.buy_amount),
+            rec.buy_currency.clone(),
+            format_amount(rec.sell_amount),
+            rec.sell_currency.clone(),
+            format_amount(rec.fee_amount),
+            rec.fee_currency.clone(),
+            tag.to_owned(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(dropped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::convert::converter;
+    use crate::test_utils::recs_from_csv;
+
+    const RECS: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.5,USD,0.00124,BNB,,,,2022-01-02 13:14:15
+Income,0.01,ETH,,,,,,,,2022-01-03 00:00:00
+Mining,0.5,ETH,,,,,,,,2022-01-04 00:00:00
+Spend,,,0.1,ETH,0.001,ETH,,,,2022-01-05 00:00:00
+Gift,,,0.1,ETH,,,,,,2022-01-06 00:00:00
+Lost,,,0.1,ETH,,,,,,2022-01-07 00:00:00
+Withdrawal,,,1,ETH,0.001,ETH,,,,2022-01-08 00:00:00
+";
+
+    fn convert(csv: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        converter("cointracker")
+            .unwrap()
+            .convert(&mut csv.trim_start().as_bytes(), "cointracker.csv")
+    }
+
+    #[test]
+    fn test_cointracker() {
+        let csv = "
+Date,Received Quantity,Received Currency,Sent Quantity,Sent Currency,Fee Amount,Fee Currency,Tag
+01/01/2022 00:00:00,5125,USD,,,,,
+01/02/2022 13:14:15,1,ETH,3123.5,USD,0.00124,BNB,
+01/03/2022 00:00:00,0.01,ETH,,,,,stake
+01/04/2022 00:00:00,0.5,ETH,,,,,mined
+01/05/2022 00:00:00,,,0.1,ETH,0.001,ETH,payment
+01/06/2022 00:00:00,,,0.1,ETH,,,donation
+01/07/2022 00:00:00,,,0.1,ETH,,,lost
+01/08/2022 00:00:00,,,1,ETH,0.001,ETH,
+01/09/2022 00:00:00,0,ETH,,,,,stake
+";
+        assert_eq!(convert(csv).unwrap(), recs_from_csv(RECS));
+
+        let header = HEADER.join(",");
+        let err = convert(&format!("{header}\n01/01/2022 00:00:00,,,1,ETH,,,mined\n")).unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: Unsupported tag mined");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut recs = recs_from_csv(RECS);
+        let mut buf = Vec::new();
+        assert!(write_csv(&recs, &mut buf).unwrap().is_empty());
+        assert_eq!(convert(std::str::from_utf8(&buf).unwrap()).unwrap(), recs);
+
+        recs[1].exchange = "kraken".to_owned();
+        recs[5].comment = "Birthday".to_owned();
+        assert_eq!(write_csv(&recs, &mut Vec::new()).unwrap(), vec![1, 5]);
+        recs[6].type_txs = TokenTaxRecType::Stolen;
+        let err = write_csv(&recs, &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedRecord { rec_idx: 6 }));
+        recs[6].type_txs = TokenTaxRecType::Lost;
+        recs[1].group = Some(crate::GroupType::Margin);
+        let err = write_csv(&recs, &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedRecord { rec_idx: 1 }));
+    }
+}
@@ -0,0 +1,199 @@
+//! CoinTracking's csv format, read from either its trade table export or
+//! its import format and written in the import format.
+//!
+//! It has every field of a record so all survive a round trip, times to
+//! the second. Trade groups other than `margin` aren't kept.
+use std::io::{Read, Write};
+
+use super::{
+    build, field, format_amount, parse_amount, parse_error, parse_leg, parse_time, with_fee,
+    Converter, Table,
+};
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::{GroupType, TokenTaxRec, TokenTaxRecType};
+
+pub const HEADER: [&str; 11] = [
+    "Type",
+    "Buy Amount",
+    "Buy Currency",
+    "Sell Amount",
+    "Sell Currency",
+    "Fee",
+    "Fee Currency",
+    "Exchange",
+    "Trade-Group",
+    "Comment",
+    "Date",
+];
+
+pub struct CoinTracking;
+
+impl Converter for CoinTracking {
+    fn name(&self) -> &'static str {
+        "cointracking"
+    }
+
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let table = Table::read(rdr, |r| {
+            r.get(0) == Some("Type") && r.iter().any(|f| f == "Buy" || f == "Buy Amount")
+        })?;
+        // The trade table export names each currency column "Cur.", so
+        // currencies are found by position.
+        let kind = table.required(&["Type"])?;
+        let buy = table.required(&["Buy Amount", "Buy"])?;
+        let sell = table.required(&["Sell Amount", "Sell"])?;
+        let fee = table.column(&["Fee"]);
+        let exchange = table.column(&["Exchange"]);
+        let group = table.column(&["Trade-Group", "Group"]);
+        let comment = table.column(&["Comment"]);
+        let date = table.required(&["Date"])?;
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let leg = |idx: usize, name: &str| -> Result<Amount, Error> {
+                parse_leg(record, idx, idx + 1)?
+                    .ok_or_else(|| parse_error(record, format!("Missing {name} amount")))
+            };
+            let builder = match field(record, kind) {
+                "Trade" => TokenTaxRec::trade(leg(buy, "buy")?, leg(sell, "sell")?),
+                "Deposit" => TokenTaxRec::deposit(leg(buy, "buy")?),
+                "Withdrawal" => TokenTaxRec::withdrawal(leg(sell, "sell")?),
+                "Income"
+                | "Income (non taxable)"
+                | "Interest Income"
+                | "Staking"
+                | "Airdrop"
+                | "Reward / Bonus"
+                | "Gift / Tip" => TokenTaxRec::income(leg(buy, "buy")?),
+                "Mining" | "Masternode" | "Minting" => TokenTaxRec::mining(leg(buy, "buy")?),
+                "Spend" | "Margin Fee" | "Borrowing Fee" | "Other Fee" | "Other Expense" => {
+                    TokenTaxRec::spend(leg(sell, "sell")?)
+                }
+                "Gift" | "Donation" => TokenTaxRec::gift(leg(sell, "sell")?),
+                "Lost" => TokenTaxRec::lost(leg(sell, "sell")?),
+                "Stolen" => TokenTaxRec::stolen(leg(sell, "sell")?),
+                t => return Err(parse_error(record, format!("Unsupported type {t}"))),
+            };
+            let fee_amount = match fee {
+                Some(idx) => parse_amount(record, Some(idx))?,
+                None => None,
+            };
+            let fee_currency = fee.map_or("", |idx| field(record, idx + 1));
+            let mut builder = with_fee(builder, fee_amount, fee_currency)
+                .exchange(exchange.map_or("", |idx| field(record, idx)))
+                .comment(comment.map_or("", |idx| field(record, idx)));
+            let group = group.map_or("", |idx| field(record, idx));
+            if field(record, kind) == "Trade" && group.eq_ignore_ascii_case("margin") {
+                builder = builder.margin();
+            }
+            recs.extend(build(
+                builder,
+                parse_time(record, date)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+}
+
+/// Write `recs` in CoinTracking's import format
+pub fn write_csv<'a, W, I>(recs: I, wtr: W) -> Result<(), Error>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a TokenTaxRec>,
+{
+    let mut writer = csv::Writer::from_writer(wtr);
+    writer.write_record(HEADER)?;
+    for (rec_idx, rec) in recs.into_iter().enumerate() {
+        if rec.type_txs == TokenTaxRecType::Unknown {
+            return Err(Error::UnsupportedRecord { rec_idx });
+        }
+        let date = chrono::DateTime::from_timestamp_millis(rec.time).expect("SNH");
+        writer.write_record([
+            rec.type_txs.to_string(),
+            format_amount(rec.buy_amount),
+            rec.buy_currency.clone(),
+            format_amount(rec.sell_amount),
+            rec.sell_currency.clone(),
+            format_amount(rec.fee_amount),
+            rec.fee_currency.clone(),
+            rec.exchange.clone(),
+            match rec.group {
+                Some(GroupType::Margin) => "margin".to_owned(),
+                None => String::new(),
+            },
+            rec.comment.clone(),
+            date.format("%d.%m.%Y %H:%M:%S").to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::convert::converter;
+    use crate::test_utils::recs_from_csv;
+
+    const RECS: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,binance.us,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.5,USD,0.00124,BNB,binance.us,margin,\"Limit, buy\",2022-01-02 13:14:15
+Income,0.01,ETH,,,,,coinbase,,Staking,2022-01-03 00:00:00
+Mining,0.5,ETH,,,,,,,,2022-01-04 00:00:00
+Spend,,,0.1,ETH,0.001,ETH,,,Coffee,2022-01-05 00:00:00
+Gift,,,0.1,ETH,,,,,,2022-01-06 00:00:00
+Lost,,,0.1,ETH,,,,,,2022-01-07 00:00:00
+Stolen,,,0.2,ETH,,,,,,2022-01-07 00:00:00
+Withdrawal,,,1,ETH,0.001,ETH,binance.us,,,2022-01-08 00:00:00
+";
+
+    fn convert(csv: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        converter("cointracking")
+            .unwrap()
+            .convert(&mut csv.trim_start().as_bytes(), "cointracking.csv")
+    }
+
+    #[test]
+    fn test_cointracking() {
+        let csv = "
+\"Type\",\"Buy\",\"Cur.\",\"Sell\",\"Cur.\",\"Fee\",\"Cur.\",\"Exchange\",\"Group\",\"Comment\",\"Date\"
+\"Deposit\",\"5125.00000000\",\"USD\",\"\",\"\",\"\",\"\",\"binance.us\",\"\",\"\",\"01.01.2022 00:00:00\"
+\"Trade\",\"1.00000000\",\"ETH\",\"3123.50000000\",\"USD\",\"0.00124000\",\"BNB\",\"binance.us\",\"Margin\",\"Limit, buy\",\"02.01.2022 13:14:15\"
+\"Staking\",\"0.01000000\",\"ETH\",\"\",\"\",\"\",\"\",\"coinbase\",\"\",\"Staking\",\"03.01.2022 00:00:00\"
+\"Mining\",\"0.50000000\",\"ETH\",\"\",\"\",\"\",\"\",\"\",\"\",\"\",\"04.01.2022 00:00:00\"
+\"Other Fee\",\"\",\"\",\"0.10000000\",\"ETH\",\"0.00100000\",\"ETH\",\"\",\"\",\"Coffee\",\"05.01.2022 00:00:00\"
+\"Donation\",\"\",\"\",\"0.10000000\",\"ETH\",\"\",\"\",\"\",\"\",\"\",\"06.01.2022 00:00:00\"
+\"Lost\",\"\",\"\",\"0.10000000\",\"ETH\",\"\",\"\",\"\",\"\",\"\",\"07.01.2022 00:00:00\"
+\"Stolen\",\"\",\"\",\"0.20000000\",\"ETH\",\"\",\"\",\"\",\"\",\"\",\"07.01.2022 00:00:00\"
+\"Withdrawal\",\"\",\"\",\"1.00000000\",\"ETH\",\"0.00100000\",\"ETH\",\"binance.us\",\"\",\"\",\"08.01.2022 00:00:00\"
+\"Staking\",\"0.00000000\",\"ETH\",\"\",\"\",\"\",\"\",\"coinbase\",\"\",\"Staking\",\"09.01.2022 00:00:00\"
+";
+        assert_eq!(convert(csv).unwrap(), recs_from_csv(RECS));
+
+        let header = HEADER.join(",");
+        let err = convert(&format!("{header}\nTrade,1,ETH,,,,,,,,01.01.2022 00:00\n")).unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: Missing sell amount");
+        let err = convert(&format!(
+            "{header}\nMargin Profit,1,ETH,,,,,,,,01.01.2022 00:00\n"
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: Unsupported type Margin Profit");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let recs = recs_from_csv(RECS);
+        let mut buf = Vec::new();
+        write_csv(&recs, &mut buf).unwrap();
+        assert_eq!(convert(std::str::from_utf8(&buf).unwrap()).unwrap(), recs);
+
+        let unknown = TokenTaxRec::new();
+        let err = write_csv([&unknown], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedRecord { rec_idx: 0 }));
+    }
+}
@@ -0,0 +1,426 @@
+//! Block explorer exports of an Ethereum wallet in Etherscan's csv format,
+//! normal transactions, ERC-20 token transfers and internal transactions.
+//!
+//! The exports of each of our addresses are read and then the movements
+//! of each transaction, across all files, are netted per asset:
+//!
+//!  - one asset in and one out of another symbol is a `Trade`, e.g. a swap
+//!  - only an asset in is a `Deposit` and only one out a `Withdrawal`
+//!  - nothing moved, e.g. an approval, a failed transaction or a transfer
+//!    between our own addresses, is a `Spend` of the gas
+//!  - otherwise each asset is a `Deposit` or `Withdrawal` of its own
+//!
+//! Tokens are told apart by contract address, anyone can deploy a token
+//! named USDC, and their symbol is the currency of the records. Gas paid by
+//! our addresses is the fee, in ETH, on the first record of a transaction
+//! and the transaction hash is its comment.
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+use rust_decimal::prelude::*;
+
+use super::{build, field, parse_amount, parse_time, Table};
+use crate::builder::RecBuilder;
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::TokenTaxRec;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Export {
+    Normal,
+    Token,
+    Internal,
+}
+
+/// An asset, ETH or the token at a contract address
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Asset {
+    /// Lowercase contract address, empty for ETH
+    contract: String,
+    symbol: String,
+}
+
+/// A row of an export, the same transfer is in the export of each of our
+/// addresses it involves
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Transfer {
+    export: Export,
+    hash: String,
+    from: String,
+    to: String,
+    asset: Asset,
+    amount: String,
+
+    /// The log index when exported, otherwise the number of identical
+    /// rows before it in the file, so repeated transfers in a transaction
+    /// are kept
+    idx: String,
+}
+
+/// Movements of one transaction
+struct Tx {
+    hash: String,
+    time: i64,
+    source: String,
+    record: csv::StringRecord,
+
+    /// Net amount of each asset into our addresses
+    moves: Vec<(Asset, Decimal)>,
+    gas: Decimal,
+}
+
+impl Tx {
+    fn add(&mut self, asset: &Asset, amount: Decimal) {
+        match self.moves.iter_mut().find(|(a, _)| a == asset) {
+            Some((_, total)) => *total += amount,
+            None => self.moves.push((asset.clone(), amount)),
+        }
+    }
+}
+
+/// Importer of the Etherscan exports of a set of our own addresses
+pub struct Etherscan {
+    addresses: HashSet<String>,
+
+    /// Exchange of the records, "ethereum" by default
+    pub exchange: String,
+    txs: Vec<Tx>,
+    tx_idx: HashMap<String, usize>,
+
+    /// Rows already read
+    seen: HashSet<Transfer>,
+}
+
+impl Etherscan {
+    pub fn new<S: AsRef<str>>(addresses: &[S]) -> Etherscan {
+        Etherscan {
+            addresses: addresses
+                .iter()
+                .map(|a| a.as_ref().trim().to_lowercase())
+                .collect(),
+            exchange: "ethereum".to_owned(),
+            txs: Vec::new(),
+            tx_idx: HashMap::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    fn ours(&self, address: &str) -> bool {
+        self.addresses.contains(&address.to_lowercase())
+    }
+
+    /// Read an export, which kind is detected by its header
+    pub fn read(&mut self, rdr: &mut dyn Read, source: &str) -> Result<(), Error> {
+        let table = Table::read(rdr, |r| {
+            r.iter()
+                .any(|f| f.eq_ignore_ascii_case("Txhash") || f == "Transaction Hash")
+        })?;
+        let export = if table.column(&["TokenSymbol"]).is_some() {
+            Export::Token
+        } else if table.column(&["ParentTxFrom"]).is_some() {
+            Export::Internal
+        } else {
+            Export::Normal
+        };
+
+        let hash = table.required(&["Txhash", "Transaction Hash"])?;
+        let time = table.required(&["UnixTimestamp", "DateTime (UTC)"])?;
+        let from = table.required(&["From"])?;
+        let to = table.required(&["To", "TxTo"])?;
+        let (value_in, value_out, value, symbol, contract) = match export {
+            Export::Token => (
+                None,
+                None,
+                Some(table.required(&["TokenValue", "Value"])?),
+                table.column(&["TokenSymbol"]),
+                Some(table.required(&["ContractAddress"])?),
+            ),
+            _ => (
+                Some(table.required(&["Value_IN(ETH)"])?),
+                Some(table.required(&["Value_OUT(ETH)"])?),
+                None,
+                None,
+                None,
+            ),
+        };
+        let log_idx = table.column(&["LogIndex", "Log Index"]);
+        let gas = table.column(&["TxnFee(ETH)"]);
+        let err_code = table.column(&["ErrCode"]);
+        let status = table.column(&["Status"]);
+
+        let mut occurrences: HashMap<Transfer, usize> = HashMap::new();
+        for record in table.rows() {
+            let hash_value = field(record, hash).to_lowercase();
+            let time = parse_time(record, time)?;
+            let from_value = field(record, from);
+            let to_value = field(record, to);
+            let failed = err_code.is_some_and(|idx| !field(record, idx).is_empty())
+                || status.is_some_and(|idx| field(record, idx).starts_with("Error"));
+            let asset = Asset {
+                contract: contract.map_or(String::new(), |idx| field(record, idx).to_lowercase()),
+                symbol: symbol.map_or("ETH", |idx| field(record, idx)).to_owned(),
+            };
+            let amount = match value {
+                Some(_) => parse_amount(record, value)?.unwrap_or_default(),
+                None => {
+                    parse_amount(record, value_in)?.unwrap_or_default()
+                        + parse_amount(record, value_out)?.unwrap_or_default()
+                }
+            };
+
+            let mut transfer = Transfer {
+                export,
+                hash: hash_value.clone(),
+                from: from_value.to_lowercase(),
+                to: to_value.to_lowercase(),
+                asset: asset.clone(),
+                amount: amount.normalize().to_string(),
+                idx: String::new(),
+            };
+            transfer.idx = match log_idx {
+                Some(idx) => field(record, idx).to_owned(),
+                None => {
+                    let count = occurrences.entry(transfer.clone()).or_default();
+                    *count += 1;
+                    count.to_string()
+                }
+            };
+            if !self.seen.insert(transfer) {
+                continue;
+            }
+
+            let idx = match self.tx_idx.get(&hash_value) {
+                Some(&idx) => idx,
+                None => {
+                    self.txs.push(Tx {
+                        hash: field(record, hash).to_owned(),
+                        time,
+                        source: source.to_owned(),
+                        record: record.clone(),
+                        moves: Vec::new(),
+                        gas: Decimal::ZERO,
+                    });
+                    self.tx_idx.insert(hash_value, self.txs.len() - 1);
+                    self.txs.len() - 1
+                }
+            };
+            let (from_ours, to_ours) = (self.ours(from_value), self.ours(to_value));
+            let tx = &mut self.txs[idx];
+            if export == Export::Normal && from_ours {
+                tx.gas += parse_amount(record, gas)?.unwrap_or_default();
+            }
+            if failed || amount.is_zero() {
+                continue;
+            }
+            if to_ours {
+                tx.add(&asset, amount);
+            }
+            if from_ours {
+                tx.add(&asset, -amount);
+            }
+        }
+        Ok(())
+    }
+
+    /// The records of the transactions read, in time order
+    pub fn records(&self) -> Result<Vec<TokenTaxRec>, Error> {
+        let mut txs: Vec<&Tx> = self.txs.iter().collect();
+        txs.sort_by_key(|tx| tx.time);
+
+        let mut recs = Vec::new();
+        for tx in txs {
+            let ins: Vec<Amount> = tx
+                .moves
+                .iter()
+                .filter(|(_, a)| *a > Decimal::ZERO)
+                .map(|(asset, a)| Amount::new(*a, &asset.symbol))
+                .collect();
+            let outs: Vec<Amount> = tx
+                .moves
+                .iter()
+                .filter(|(_, a)| *a < Decimal::ZERO)
+                .map(|(asset, a)| Amount::new(-*a, &asset.symbol))
+                .collect();
+            let builders: Vec<RecBuilder> = match (ins.as_slice(), outs.as_slice()) {
+                ([], []) if tx.gas.is_zero() => continue,
+                ([], []) => {
+                    recs.extend(self.build(TokenTaxRec::spend(Amount::new(tx.gas, "ETH")), tx)?);
+                    continue;
+                }
+                // Tokens of different contracts may share a symbol
+                ([buy], [sell]) if buy.currency != sell.currency => {
+                    vec![TokenTaxRec::trade(buy.clone(), sell.clone())]
+                }
+                _ => ins
+                    .into_iter()
+                    .map(TokenTaxRec::deposit)
+                    .chain(outs.into_iter().map(TokenTaxRec::withdrawal))
+                    .collect(),
+            };
+            for (idx, builder) in builders.into_iter().enumerate() {
+                let builder = if idx == 0 && !tx.gas.is_zero() {
+                    builder.fee(Amount::new(tx.gas, "ETH"))
+                } else {
+                    builder
+                };
+                recs.extend(self.build(builder, tx)?);
+            }
+        }
+        Ok(recs)
+    }
+
+    fn build(&self, builder: RecBuilder, tx: &Tx) -> Result<Option<TokenTaxRec>, Error> {
+        let builder = builder
+            .exchange(&self.exchange)
+            .comment(&format!("tx: {}", tx.hash));
+        build(builder, tx.time, "etherscan", &tx.source, &tx.record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::recs_from_csv;
+
+    const ME: &str = "0x00000000000000000000000000000000000000aa";
+
+    const NORMAL: &str = r#"
+"Txhash","Blockno","UnixTimestamp","DateTime (UTC)","From","To","ContractAddress","Value_IN(ETH)","Value_OUT(ETH)","CurrentValue @ $3000/Eth","TxnFee(ETH)","TxnFee(USD)","Historical $Price/Eth","Status","ErrCode","Method"
+"0xa1","1","1640995200","2022-01-01 00:00:00","0x00000000000000000000000000000000000000ee","0x00000000000000000000000000000000000000AA","","2","0","6000","0.0021","6.3","3000","","","Transfer"
+"0xa2","2","1641081600","2022-01-02 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000dd","","0","1","3000","0.005","15","3000","","","Swap Exact ETH For Tokens"
+"0xa3","3","1641168000","2022-01-03 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000cc","","0","0","0","0.002","6","3000","","","Approve"
+"0xa4","4","1641254400","2022-01-04 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000dd","","0","0","0","0.006","18","3000","","","Swap Exact Tokens For ETH"
+"0xa5","5","1641340800","2022-01-05 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000cc","","0","0","0","0.003","9","3000","","","Transfer"
+"0xa6","6","1641427200","2022-01-06 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000dd","","0","0.5","1500","0.001","3","3000","Error(0)","Out of gas","Swap Exact ETH For Tokens"
+"#;
+
+    const TOKENS: &str = r#"
+"Txhash","Blockno","UnixTimestamp","DateTime (UTC)","From","To","TokenValue","USDValueDayOfTx","ContractAddress","TokenName","TokenSymbol"
+"0xa2","2","1641081600","2022-01-02 00:00:00","0x00000000000000000000000000000000000000dd","0x00000000000000000000000000000000000000aa","2,990.5","2990.5","0x00000000000000000000000000000000000000cc","USD Coin","USDC"
+"0xa4","4","1641254400","2022-01-04 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000dd","1000","1000","0x00000000000000000000000000000000000000cc","USD Coin","USDC"
+"0xa5","5","1641340800","2022-01-05 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000ff","500","500","0x00000000000000000000000000000000000000cc","USD Coin","USDC"
+"#;
+
+    const INTERNAL: &str = r#"
+"Txhash","Blockno","UnixTimestamp","DateTime (UTC)","ParentTxFrom","ParentTxTo","ParentTxETH_Value","From","TxTo","ContractAddress","Value_IN(ETH)","Value_OUT(ETH)","CurrentValue @ $3000/Eth","Historical $Price/Eth","Status","ErrCode","Type"
+"0xa4","4","1641254400","2022-01-04 00:00:00","0x00000000000000000000000000000000000000aa","0x00000000000000000000000000000000000000dd","0","0x00000000000000000000000000000000000000dd","0x00000000000000000000000000000000000000aa","","0.33","0","990","3000","0","","call"
+"#;
+
+    #[test]
+    fn test_etherscan() {
+        let mut etherscan = Etherscan::new(&[ME.to_uppercase().replace("0X", "0x")]);
+        etherscan
+            .read(&mut NORMAL.as_bytes(), "normal.csv")
+            .unwrap();
+        etherscan
+            .read(&mut TOKENS.as_bytes(), "tokens.csv")
+            .unwrap();
+        etherscan
+            .read(&mut INTERNAL.as_bytes(), "internal.csv")
+            .unwrap();
+        // Reading an export twice changes nothing
+        etherscan
+            .read(&mut TOKENS.as_bytes(), "tokens.csv")
+            .unwrap();
+        let recs = etherscan.records().unwrap();
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,2,ETH,,,,,ethereum,,tx: 0xa1,2022-01-01 00:00:00
+Trade,2990.5,USDC,1,ETH,0.005,ETH,ethereum,,tx: 0xa2,2022-01-02 00:00:00
+Spend,,,0.002,ETH,,,ethereum,,tx: 0xa3,2022-01-03 00:00:00
+Trade,0.33,ETH,1000,USDC,0.006,ETH,ethereum,,tx: 0xa4,2022-01-04 00:00:00
+Withdrawal,,,500,USDC,0.003,ETH,ethereum,,tx: 0xa5,2022-01-05 00:00:00
+Spend,,,0.001,ETH,,,ethereum,,tx: 0xa6,2022-01-06 00:00:00
+",
+        );
+        assert_eq!(recs, expected);
+        let source = recs[1].provenance.source.as_ref().unwrap();
+        assert_eq!(source.to_string(), "normal.csv:4");
+        assert_eq!(recs[1].provenance.rules(), vec!["etherscan"]);
+    }
+
+    #[test]
+    fn test_token_contracts() {
+        let usdc = "0x00000000000000000000000000000000000000cc";
+        let spam = "0x0000000000000000000000000000000000000bad";
+        let dex = "0x00000000000000000000000000000000000000dd";
+        let csv = format!(
+            "Txhash,UnixTimestamp,From,To,TokenValue,ContractAddress,TokenSymbol
+0xd1,1640995200,{dex},{ME},100,{usdc},USDC
+0xd1,1640995200,{dex},{ME},100,{usdc},USDC
+0xd2,1641081600,{ME},{dex},50,{usdc},USDC
+0xd2,1641081600,{dex},{ME},50,{spam},USDC
+"
+        );
+        let mut etherscan = Etherscan::new(&[ME]);
+        etherscan.read(&mut csv.as_bytes(), "tokens.csv").unwrap();
+        etherscan.read(&mut csv.as_bytes(), "tokens.csv").unwrap();
+        let recs = etherscan.records().unwrap();
+
+        // The same transfer twice in a transaction is two transfers and a
+        // token calling itself USDC isn't netted with USDC
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,200,USDC,,,,,ethereum,,tx: 0xd1,2022-01-01 00:00:00
+Deposit,50,USDC,,,,,ethereum,,tx: 0xd2,2022-01-02 00:00:00
+Withdrawal,,,50,USDC,,,ethereum,,tx: 0xd2,2022-01-02 00:00:00
+",
+        );
+        assert_eq!(recs, expected);
+
+        // With a log index identical rows are told apart by it
+        let csv = format!(
+            "Txhash,UnixTimestamp,From,To,TokenValue,ContractAddress,TokenSymbol,LogIndex
+0xd1,1640995200,{dex},{ME},100,{usdc},USDC,7
+0xd1,1640995200,{dex},{ME},100,{usdc},USDC,7
+0xd1,1640995200,{dex},{ME},100,{usdc},USDC,9
+"
+        );
+        let mut etherscan = Etherscan::new(&[ME]);
+        etherscan.read(&mut csv.as_bytes(), "tokens.csv").unwrap();
+        let recs = etherscan.records().unwrap();
+        assert_eq!(recs[0].buy_amount, Some(Decimal::from(200)));
+    }
+
+    #[test]
+    fn test_own_transfer() {
+        let other = "0x00000000000000000000000000000000000000bb";
+        let csv = format!(
+            "Txhash,UnixTimestamp,From,To,Value_IN(ETH),Value_OUT(ETH),TxnFee(ETH)
+0xb1,1640995200,{ME},{other},0,1,0.0021
+0xb2,1641081600,{ME},0x00000000000000000000000000000000000000ee,0,0.5,0.0021
+"
+        );
+        let other_csv = format!(
+            "Txhash,UnixTimestamp,From,To,Value_IN(ETH),Value_OUT(ETH),TxnFee(ETH)
+0xb1,1640995200,{ME},{other},1,0,0.0021
+"
+        );
+        let mut etherscan = Etherscan::new(&[ME, other]);
+        etherscan.exchange = "ledger".to_owned();
+        etherscan.read(&mut csv.as_bytes(), "me.csv").unwrap();
+        etherscan
+            .read(&mut other_csv.as_bytes(), "other.csv")
+            .unwrap();
+        let recs = etherscan.records().unwrap();
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Spend,,,0.0021,ETH,,,ledger,,tx: 0xb1,2022-01-01 00:00:00
+Withdrawal,,,0.5,ETH,0.0021,ETH,ledger,,tx: 0xb2,2022-01-02 00:00:00
+",
+        );
+        assert_eq!(recs, expected);
+
+        let err = Etherscan::new(&[ME])
+            .read(
+                &mut "Txhash,UnixTimestamp,From,To,Value_IN(ETH),Value_OUT(ETH)\n0xc1,yesterday,a,b,1,0\n"
+                    .as_bytes(),
+                "bad.csv",
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: Bad time yesterday");
+    }
+}
@@ -0,0 +1,203 @@
+//! Koinly's universal csv format, read and written.
+//!
+//! The format is per wallet so the exchange and group of records aren't
+//! carried. Everything else survives a round trip.
+use std::io::{Read, Write};
+
+use super::{
+    build, field, format_amount, parse_amount, parse_error, parse_leg, parse_time, with_fee,
+    Converter, Table,
+};
+use crate::error::Error;
+use crate::time_utils::format_time_ms;
+use crate::{TokenTaxRec, TokenTaxRecType};
+
+/// Labels of received amounts that are income
+const INCOME_LABELS: &[&str] = &[
+    "income",
+    "reward",
+    "airdrop",
+    "fork",
+    "staking",
+    "lending interest",
+    "loan interest",
+    "other income",
+    "cashback",
+    "royalty",
+];
+
+pub const HEADER: [&str; 12] = [
+    "Date",
+    "Sent Amount",
+    "Sent Currency",
+    "Received Amount",
+    "Received Currency",
+    "Fee Amount",
+    "Fee Currency",
+    "Net Worth Amount",
+    "Net Worth Currency",
+    "Label",
+    "Description",
+    "TxHash",
+];
+
+pub struct Koinly;
+
+impl Converter for Koinly {
+    fn name(&self) -> &'static str {
+        "koinly"
+    }
+
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let table = Table::read(rdr, |r| r.iter().any(|f| f == "Sent Amount"))?;
+        let date = table.required(&["Date"])?;
+        let sent_amount = table.required(&["Sent Amount"])?;
+        let sent_currency = table.required(&["Sent Currency"])?;
+        let received_amount = table.required(&["Received Amount"])?;
+        let received_currency = table.required(&["Received Currency"])?;
+        let fee_amount = table.column(&["Fee Amount"]);
+        let fee_currency = table.column(&["Fee Currency"]);
+        let label = table.column(&["Label"]);
+        let description = table.column(&["Description"]);
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let sent = parse_leg(record, sent_amount, sent_currency)?;
+            let received = parse_leg(record, received_amount, received_currency)?;
+            let label = label.map_or("", |idx| field(record, idx)).to_lowercase();
+            let builder = match (received, sent) {
+                (Some(received), Some(sent)) => TokenTaxRec::trade(received, sent),
+                (Some(received), None) => match label.as_str() {
+                    "" | "gift" => TokenTaxRec::deposit(received),
+                    "mining" => TokenTaxRec::mining(received),
+                    l if INCOME_LABELS.contains(&l) => TokenTaxRec::income(received),
+                    l => return Err(parse_error(record, format!("Unsupported label {l}"))),
+                },
+                (None, Some(sent)) => match label.as_str() {
+                    "" => TokenTaxRec::withdrawal(sent),
+                    "cost" | "margin fee" | "interest payment" => TokenTaxRec::spend(sent),
+                    "gift" | "donation" => TokenTaxRec::gift(sent),
+                    "lost" => TokenTaxRec::lost(sent),
+                    "stolen" => TokenTaxRec::stolen(sent),
+                    l => return Err(parse_error(record, format!("Unsupported label {l}"))),
+                },
+                (None, None) => {
+                    return Err(parse_error(record, "Nothing sent or received".to_owned()))
+                }
+            };
+            let fee_currency = fee_currency.map_or("", |idx| field(record, idx));
+            let builder = with_fee(builder, parse_amount(record, fee_amount)?, fee_currency)
+                .comment(description.map_or("", |idx| field(record, idx)));
+            recs.extend(build(
+                builder,
+                parse_time(record, date)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+}
+
+fn label(rec: &TokenTaxRec) -> Option<&'static str> {
+    match rec.type_txs {
+        TokenTaxRecType::Trade | TokenTaxRecType::Deposit | TokenTaxRecType::Withdrawal => Some(""),
+        TokenTaxRecType::Income => Some("income"),
+        TokenTaxRecType::Mining => Some("mining"),
+        TokenTaxRecType::Gift => Some("gift"),
+        TokenTaxRecType::Spend => Some("cost"),
+        TokenTaxRecType::Lost => Some("lost"),
+        TokenTaxRecType::Stolen => Some("stolen"),
+        TokenTaxRecType::Unknown => None,
+    }
+}
+
+/// Write `recs` in Koinly's universal format
+pub fn write_csv<'a, W, I>(recs: I, wtr: W) -> Result<(), Error>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a TokenTaxRec>,
+{
+    let mut writer = csv::Writer::from_writer(wtr);
+    writer.write_record(HEADER)?;
+    for (rec_idx, rec) in recs.into_iter().enumerate() {
+        let label = label(rec).ok_or(Error::UnsupportedRecord { rec_idx })?;
+        writer.write_record([
+            format!("{} UTC", format_time_ms(rec.time)),
+            format_amount(rec.sell_amount),
+            rec.sell_currency.clone(),
+            format_amount(rec.buy_amount),
+            rec.buy_currency.clone(),
+            format_amount(rec.fee_amount),
+            rec.fee_currency.clone(),
+            String::new(),
+            String::new(),
+            label.to_owned(),
+            rec.comment.clone(),
+            String::new(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::convert::converter;
+    use crate::test_utils::recs_from_csv;
+
+    const RECS: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.5,USD,0.00124,BNB,,,\"Limit, buy\",2022-01-02 00:00:00
+Income,0.01,ETH,,,,,,,Staking,2022-01-03 00:00:00
+Mining,0.5,ETH,,,,,,,,2022-01-04 00:00:00
+Spend,,,0.1,ETH,0.001,ETH,,,Coffee,2022-01-05 00:00:00
+Gift,,,0.1,ETH,,,,,,2022-01-06 00:00:00
+Lost,,,0.1,ETH,,,,,,2022-01-07 00:00:00
+Stolen,,,0.2,ETH,,,,,,2022-01-07 00:00:00
+Withdrawal,,,1,ETH,0.001,ETH,,,,2022-01-08 00:00:00
+";
+
+    fn convert(csv: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        converter("koinly")
+            .unwrap()
+            .convert(&mut csv.trim_start().as_bytes(), "koinly.csv")
+    }
+
+    #[test]
+    fn test_koinly() {
+        let csv = "
+Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,Net Worth Amount,Net Worth Currency,Label,Description,TxHash
+2022-01-01 00:00 UTC,,,\"5,125\",USD,,,,,,,
+2022-01-02 00:00:00 UTC,3123.5,USD,1,ETH,0.00124,BNB,3123.5,USD,swap,\"Limit, buy\",
+2022-01-03 00:00 UTC,,,0.01,ETH,0,,30,USD,Reward,Staking,0xabc
+2022-01-04 00:00 UTC,,,0.5,ETH,,,,,mining,,
+2022-01-05 00:00 UTC,0.1,ETH,,,0.001,ETH,,,cost,Coffee,
+2022-01-06 00:00 UTC,0.1,ETH,,,,,,,donation,,
+2022-01-07 00:00 UTC,0.1,ETH,,,,,,,lost,,
+2022-01-07 00:00 UTC,0.2,ETH,,,,,,,stolen,,
+2022-01-08 00:00 UTC,1,ETH,,,0.001,ETH,,,,,
+2022-01-09 00:00 UTC,,,0,ETH,,,,,Reward,Staking,
+";
+        assert_eq!(convert(csv).unwrap(), recs_from_csv(RECS));
+
+        let header = HEADER.join(",");
+        let err = convert(&format!("{header}\n2022-01-01,,,1,ETH,,,,,swap,,\n")).unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: Unsupported label swap");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let recs = recs_from_csv(RECS);
+        let mut buf = Vec::new();
+        write_csv(&recs, &mut buf).unwrap();
+        assert_eq!(convert(std::str::from_utf8(&buf).unwrap()).unwrap(), recs);
+
+        let unknown = TokenTaxRec::new();
+        let err = write_csv([&recs[0], &unknown], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedRecord { rec_idx: 1 }));
+    }
+}
@@ -0,0 +1,345 @@
+//! Kraken `ledgers.csv` and `trades.csv` exports, detected by header.
+use std::collections::HashMap;
+use std::io::Read;
+
+use rust_decimal::prelude::*;
+
+use super::{
+    build, field, parse_amount, parse_error, parse_time, required_amount, with_fee, Converter,
+    Table,
+};
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::TokenTaxRec;
+
+/// Kraken's legacy codes with an `X` or `Z` prefix
+const PREFIXED: &[&str] = &[
+    "XXBT", "XETH", "XETC", "XLTC", "XXRP", "XXLM", "XXMR", "XREP", "XZEC", "XMLN", "XXDG", "XDAO",
+    "XNMC", "XICN", "XXVN", "ZUSD", "ZEUR", "ZCAD", "ZJPY", "ZGBP", "ZAUD", "ZCHF", "ZKRW",
+];
+
+/// Quote currencies tried, in order, when splitting a pair without a `/`
+const QUOTES: &[&str] = &[
+    "ZUSD", "ZEUR", "ZGBP", "ZCAD", "ZJPY", "ZAUD", "ZCHF", "XXBT", "XETH", "USDT", "USDC", "DAI",
+    "USD", "EUR", "GBP", "CAD", "JPY", "AUD", "CHF", "XBT", "ETH",
+];
+
+/// The common code for a Kraken asset, e.g. `XXBT` and `XBT.M` are `BTC`,
+/// `ZUSD` is `USD` and the staked `DOT.S` is `DOT`.
+pub fn normalize_asset(asset: &str) -> String {
+    let asset = match asset.split_once('.') {
+        Some((base, _)) => base,
+        None => asset,
+    };
+    let asset = if PREFIXED.contains(&asset) {
+        &asset[1..]
+    } else {
+        asset
+    };
+    match asset {
+        "XBT" => "BTC",
+        "XDG" => "DOGE",
+        "ETH2" => "ETH",
+        a => a,
+    }
+    .to_owned()
+}
+
+fn split_pair(pair: &str) -> Option<(String, String)> {
+    if let Some((base, quote)) = pair.split_once('/') {
+        return Some((normalize_asset(base), normalize_asset(quote)));
+    }
+    QUOTES.iter().find_map(|quote| {
+        let base = pair.strip_suffix(quote)?;
+        (!base.is_empty()).then(|| (normalize_asset(base), normalize_asset(quote)))
+    })
+}
+
+/// Kraken's ledger or trades export.
+///
+/// Ledger rows sharing a `refid` are the two sides of a trade and become
+/// one `Trade`. Staking and earn rewards are `Income`, internal transfers
+/// and earn allocations are skipped and pending rows, those without a
+/// `txid`, are ignored.
+pub struct Kraken;
+
+impl Converter for Kraken {
+    fn name(&self) -> &'static str {
+        "kraken"
+    }
+
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let table = Table::read(rdr, |r| r.iter().any(|f| f == "txid"))?;
+        if table.column(&["refid"]).is_some() {
+            self.ledgers(&table, source)
+        } else {
+            self.trades(&table, source)
+        }
+    }
+}
+
+impl Kraken {
+    fn ledgers(&self, table: &Table, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let txid = table.required(&["txid"])?;
+        let refid = table.required(&["refid"])?;
+        let time = table.required(&["time"])?;
+        let kind = table.required(&["type"])?;
+        let subtype = table.column(&["subtype"]);
+        let asset = table.required(&["asset"])?;
+        let amount = table.required(&["amount"])?;
+        let fee = table.column(&["fee"]);
+
+        // Group rows by refid in order of first appearance
+        let mut groups: Vec<Vec<&csv::StringRecord>> = Vec::new();
+        let mut group_idx = HashMap::new();
+        for record in table.rows() {
+            if field(record, txid).is_empty() {
+                continue;
+            }
+            let idx = *group_idx.entry(field(record, refid)).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[idx].push(record);
+        }
+
+        let mut recs = Vec::new();
+        for group in groups {
+            let record = group[0];
+            let kind = field(record, kind);
+            let subtype = subtype.map_or("", |idx| field(record, idx));
+            let leg =
+                |record: &csv::StringRecord| -> Result<(Decimal, String, Option<Decimal>), Error> {
+                    Ok((
+                        required_amount(record, amount)?,
+                        normalize_asset(field(record, asset)),
+                        parse_amount(record, fee)?,
+                    ))
+                };
+
+            let builder = match kind {
+                "trade" | "spend" | "receive" => {
+                    let [a, b] = group.as_slice() else {
+                        return Err(parse_error(
+                            record,
+                            format!("Trade {} has {} rows", field(record, refid), group.len()),
+                        ));
+                    };
+                    let (a, b) = (leg(a)?, leg(b)?);
+                    let ((sold, sold_asset, sold_fee), (bought, bought_asset, bought_fee)) =
+                        if a.0 < Decimal::ZERO { (a, b) } else { (b, a) };
+                    if sold >= Decimal::ZERO || bought <= Decimal::ZERO {
+                        return Err(parse_error(
+                            record,
+                            "Trade rows must sell and buy".to_owned(),
+                        ));
+                    }
+                    let builder = TokenTaxRec::trade(
+                        Amount::new(bought, &bought_asset),
+                        Amount::new(-sold, &sold_asset),
+                    );
+                    match (
+                        sold_fee.filter(|f| !f.is_zero()),
+                        bought_fee.filter(|f| !f.is_zero()),
+                    ) {
+                        (Some(_), Some(_)) => {
+                            return Err(parse_error(record, "Trade has two fees".to_owned()))
+                        }
+                        (Some(f), None) => with_fee(builder, Some(f), &sold_asset),
+                        (None, f) => with_fee(builder, f, &bought_asset),
+                    }
+                }
+                _ if group.len() != 1 => {
+                    return Err(parse_error(
+                        record,
+                        format!("Unexpected rows sharing refid {}", field(record, refid)),
+                    ))
+                }
+                "deposit" => {
+                    let (qty, asset, fee) = leg(record)?;
+                    with_fee(TokenTaxRec::deposit(Amount::new(qty, &asset)), fee, &asset)
+                }
+                "withdrawal" => {
+                    let (qty, asset, fee) = leg(record)?;
+                    with_fee(
+                        TokenTaxRec::withdrawal(Amount::new(qty.abs(), &asset)),
+                        fee,
+                        &asset,
+                    )
+                }
+                "staking" => {
+                    let (qty, asset, fee) = leg(record)?;
+                    with_fee(TokenTaxRec::income(Amount::new(qty, &asset)), fee, &asset)
+                }
+                "earn" if subtype == "reward" => {
+                    let (qty, asset, fee) = leg(record)?;
+                    with_fee(TokenTaxRec::income(Amount::new(qty, &asset)), fee, &asset)
+                }
+                "earn" | "transfer" => continue,
+                t => return Err(parse_error(record, format!("Unsupported type {t}"))),
+            };
+            let builder = builder
+                .exchange("kraken")
+                .comment(&format!("refid: {}", field(record, refid)));
+            recs.extend(build(
+                builder,
+                parse_time(record, time)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+
+    fn trades(&self, table: &Table, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let txid = table.required(&["txid"])?;
+        let pair = table.required(&["pair"])?;
+        let time = table.required(&["time"])?;
+        let kind = table.required(&["type"])?;
+        let cost = table.required(&["cost"])?;
+        let fee = table.column(&["fee"]);
+        let vol = table.required(&["vol"])?;
+        let margin = table.column(&["margin"]);
+
+        let mut recs = Vec::new();
+        for record in table.rows() {
+            let (base, quote) = split_pair(field(record, pair)).ok_or_else(|| {
+                parse_error(record, format!("Unknown pair {}", field(record, pair)))
+            })?;
+            let base_amount = Amount::new(required_amount(record, vol)?, &base);
+            let quote_amount = Amount::new(required_amount(record, cost)?, &quote);
+            let builder = match field(record, kind) {
+                "buy" => TokenTaxRec::trade(base_amount, quote_amount),
+                "sell" => TokenTaxRec::trade(quote_amount, base_amount),
+                t => return Err(parse_error(record, format!("Unknown type {t}"))),
+            };
+            let mut builder = with_fee(builder, parse_amount(record, fee)?, &quote)
+                .exchange("kraken")
+                .comment(&format!("txid: {}", field(record, txid)));
+            if parse_amount(record, margin)?.is_some_and(|m| !m.is_zero()) {
+                builder = builder.margin();
+            }
+            recs.extend(build(
+                builder,
+                parse_time(record, time)?,
+                self.name(),
+                source,
+                record,
+            )?);
+        }
+        Ok(recs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::convert::converter;
+    use crate::test_utils::recs_from_csv;
+    use crate::GroupType;
+
+    fn convert(csv: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        converter("kraken")
+            .unwrap()
+            .convert(&mut csv.trim_start().as_bytes(), "ledgers.csv")
+    }
+
+    #[test]
+    fn test_normalize_asset() {
+        assert_eq!(normalize_asset("XXBT"), "BTC");
+        assert_eq!(normalize_asset("XBT.M"), "BTC");
+        assert_eq!(normalize_asset("ZUSD"), "USD");
+        assert_eq!(normalize_asset("XETH"), "ETH");
+        assert_eq!(normalize_asset("ETH2.S"), "ETH");
+        assert_eq!(normalize_asset("DOT.S"), "DOT");
+        assert_eq!(normalize_asset("XXDG"), "DOGE");
+        assert_eq!(normalize_asset("XTZ"), "XTZ");
+        assert_eq!(normalize_asset("USDC"), "USDC");
+
+        assert_eq!(split_pair("XXBTZUSD"), Some(("BTC".into(), "USD".into())));
+        assert_eq!(split_pair("DOTUSD"), Some(("DOT".into(), "USD".into())));
+        assert_eq!(split_pair("ETHXBT"), Some(("ETH".into(), "BTC".into())));
+        assert_eq!(split_pair("XBT/USDT"), Some(("BTC".into(), "USDT".into())));
+        assert_eq!(split_pair("USD"), None);
+    }
+
+    #[test]
+    fn test_ledgers() {
+        let csv = r#"
+"txid","refid","time","type","subtype","aclass","asset","amount","fee","balance"
+"L1","D1","2022-01-01 00:00:00","deposit","","currency","ZUSD",1000.0000,0.0000,1000.0000
+"","D2","2022-01-01 00:30:00","deposit","","currency","XXBT",0.0100000000,0.0000000000,
+"L2","T1","2022-01-02 00:00:00","trade","","currency","ZUSD",-400.0000,0.6400,599.3600
+"L3","T1","2022-01-02 00:00:00","trade","","currency","XXBT",0.0100000000,0.0000000000,0.0100000000
+"L4","S1","2022-01-03 00:00:00","spend","","currency","ZUSD",-100.0000,1.5000,497.8600
+"L5","S1","2022-01-03 00:00:00","receive","","currency","DOT",4.0000000000,0.0000000000,4.0000000000
+"L6","R1","2022-01-04 00:00:00","staking","","currency","DOT.S",0.0123000000,0.0000000000,0.0123000000
+"L7","R2","2022-01-05 00:00:00","earn","reward","currency","ETH2",0.0010000000,0.0000000000,0.0010000000
+"L8","R3","2022-01-05 00:00:00","earn","allocation","currency","DOT",-4.0000000000,0.0000000000,0.0000000000
+"L9","R4","2022-01-05 00:00:00","transfer","spottostaking","currency","DOT",-4.0000000000,0.0000000000,0.0000000000
+"L10","W1","2022-01-06 00:00:00","withdrawal","","currency","XXBT",-0.0095000000,0.0005000000,0.0000000000
+"L11","R5","2022-01-07 00:00:00","staking","","currency","DOT.S",0.0000000000,0.0000000000,0.0123000000
+"#;
+        let recs = convert(csv).unwrap();
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,1000,USD,,,,,kraken,,refid: D1,2022-01-01 00:00:00
+Trade,0.01,BTC,400,USD,0.64,USD,kraken,,refid: T1,2022-01-02 00:00:00
+Trade,4,DOT,100,USD,1.5,USD,kraken,,refid: S1,2022-01-03 00:00:00
+Income,0.0123,DOT,,,,,kraken,,refid: R1,2022-01-04 00:00:00
+Income,0.001,ETH,,,,,kraken,,refid: R2,2022-01-05 00:00:00
+Withdrawal,,,0.0095,BTC,0.0005,BTC,kraken,,refid: W1,2022-01-06 00:00:00
+",
+        );
+        assert_eq!(recs, expected);
+        assert_eq!(recs[1].provenance.source.as_ref().unwrap().line, 4);
+    }
+
+    #[test]
+    fn test_ledger_errors() {
+        let header = "txid,refid,time,type,subtype,aclass,asset,amount,fee,balance\n";
+        let err = |rows: &str| convert(&format!("{header}{rows}")).unwrap_err().to_string();
+        assert_eq!(
+            err("L1,T1,2022-01-02 00:00:00,trade,,currency,ZUSD,-400,0,0\n"),
+            "Line 2: Trade T1 has 1 rows"
+        );
+        assert_eq!(
+            err("L1,T1,2022-01-02 00:00:00,trade,,currency,ZUSD,-400,1,0\nL2,T1,2022-01-02 00:00:00,trade,,currency,XXBT,0.01,0.0001,0\n"),
+            "Line 2: Trade has two fees"
+        );
+        assert_eq!(
+            err("L1,M1,2022-01-02 00:00:00,margin,,currency,ZUSD,-1,0,0\n"),
+            "Line 2: Unsupported type margin"
+        );
+    }
+
+    #[test]
+    fn test_trades() {
+        let csv = r#"
+"txid","ordertxid","pair","time","type","ordertype","price","cost","fee","vol","margin","misc","ledgers"
+"T1","O1","XXBTZUSD","2022-01-02 00:00:00.1234","buy","limit",40000.00000,400.00000,0.64000,0.01000000,0.00000,"","L2,L3"
+"T2","O2","XETHXXBT","2022-01-03 00:00:00","sell","market",0.07,0.0700000,0.0001820,1.00000000,0.0350000,"","L4,L5"
+"#;
+        let recs = convert(csv).unwrap();
+        assert_eq!(recs.len(), 2);
+        assert_eq!(
+            recs[0].buy_leg(),
+            Some(Amount::new(Decimal::new(1, 2), "BTC"))
+        );
+        assert_eq!(
+            recs[0].sell_leg(),
+            Some(Amount::new(Decimal::new(400, 0), "USD"))
+        );
+        assert_eq!(recs[0].comment, "txid: T1");
+        assert_eq!(recs[0].group, None);
+        assert_eq!(
+            recs[1].buy_leg(),
+            Some(Amount::new(Decimal::new(7, 2), "BTC"))
+        );
+        assert_eq!(recs[1].sell_leg(), Some(Amount::new(Decimal::ONE, "ETH")));
+        assert_eq!(recs[1].fee_currency, "BTC");
+        assert_eq!(recs[1].group, Some(GroupType::Margin));
+    }
+}
@@ -0,0 +1,265 @@
+//! Converters from exchange and wallet exports to `TokenTaxRec`s.
+//!
+//! Each record converted has its source file and line and the converter's
+//! name recorded in its provenance.
+use std::io::Read;
+
+use rust_decimal::prelude::*;
+
+use crate::audit::{AuditKind, AuditStep, Source};
+use crate::builder::RecBuilder;
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::time_utils::parse_time_ms;
+use crate::TokenTaxRec;
+
+pub mod coinbase;
+pub mod cointracker;
+pub mod cointracking;
+pub mod etherscan;
+pub mod koinly;
+pub mod kraken;
+
+pub trait Converter {
+    /// Name used to select the converter
+    fn name(&self) -> &'static str;
+
+    /// Convert the export read from `rdr`, `source` names it in provenance
+    fn convert(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error>;
+}
+
+/// The converter called `name`
+pub fn converter(name: &str) -> Option<Box<dyn Converter>> {
+    match name.to_lowercase().as_str() {
+        "coinbase" => Some(Box::new(coinbase::Coinbase)),
+        "coinbase-advanced" => Some(Box::new(coinbase::CoinbaseAdvanced)),
+        "cointracker" => Some(Box::new(cointracker::CoinTracker)),
+        "cointracking" => Some(Box::new(cointracking::CoinTracking)),
+        "koinly" => Some(Box::new(koinly::Koinly)),
+        "kraken" => Some(Box::new(kraken::Kraken)),
+        _ => None,
+    }
+}
+
+/// Names of the converters `converter` knows
+pub const CONVERTERS: &[&str] = &[
+    "coinbase",
+    "coinbase-advanced",
+    "cointracker",
+    "cointracking",
+    "koinly",
+    "kraken",
+];
+
+/// The rows of a csv export with columns found by header name
+pub(crate) struct Table {
+    headers: csv::StringRecord,
+    header_line: usize,
+    rows: Vec<csv::StringRecord>,
+}
+
+impl Table {
+    /// Read `rdr` skipping any preamble before the first row `is_header`
+    /// accepts.
+    pub(crate) fn read<F>(rdr: &mut dyn Read, is_header: F) -> Result<Table, Error>
+    where
+        F: Fn(&csv::StringRecord) -> bool,
+    {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(rdr);
+        let mut headers = None;
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            if headers.is_none() {
+                if is_header(&record) {
+                    headers = Some(record);
+                }
+            } else if record.iter().any(|f| !f.is_empty()) {
+                rows.push(record);
+            }
+        }
+        let headers = headers.ok_or_else(|| Error::Parse {
+            line: 1,
+            msg: "No header found".to_owned(),
+        })?;
+        let header_line = headers.position().map_or(0, |p| p.line() as usize);
+        Ok(Table {
+            headers,
+            header_line,
+            rows,
+        })
+    }
+
+    pub(crate) fn rows(&self) -> &[csv::StringRecord] {
+        &self.rows
+    }
+
+    /// Index of the first column with one of `names`, ignoring case
+    pub(crate) fn column(&self, names: &[&str]) -> Option<usize> {
+        self.headers
+            .iter()
+            .position(|h| names.iter().any(|n| h.eq_ignore_ascii_case(n)))
+    }
+
+    pub(crate) fn required(&self, names: &[&str]) -> Result<usize, Error> {
+        self.column(names).ok_or_else(|| Error::Parse {
+            line: self.header_line,
+            msg: format!("Missing column {}", names[0]),
+        })
+    }
+}
+
+/// Line of `record` in the file
+pub(crate) fn line(record: &csv::StringRecord) -> usize {
+    record.position().map_or(0, |p| p.line() as usize)
+}
+
+pub(crate) fn parse_error(record: &csv::StringRecord, msg: String) -> Error {
+    Error::Parse {
+        line: line(record),
+        msg,
+    }
+}
+
+/// Field `idx` of `record`, empty if missing
+pub(crate) fn field(record: &csv::StringRecord, idx: usize) -> &str {
+    record.get(idx).unwrap_or("")
+}
+
+/// `s` without its thousands separators, commas each followed by three
+/// digits in the integer part. None if it has any other comma, e.g. the
+/// decimal comma of `1,5`.
+pub(crate) fn without_thousands_separators(s: &str) -> Option<String> {
+    let (int, rest) = s.split_at(s.find(['.', 'e', 'E']).unwrap_or(s.len()));
+    if rest.contains(',') {
+        return None;
+    }
+    let mut groups = int.split(',');
+    let mut cleaned = groups.next().expect("SNH").to_owned();
+    for group in groups {
+        let leading_digit = cleaned.ends_with(|c: char| c.is_ascii_digit());
+        if !leading_digit || group.len() != 3 || !group.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        cleaned.push_str(group);
+    }
+    cleaned.push_str(rest);
+    Some(cleaned)
+}
+
+/// Parse an amount allowing currency symbols and thousands separators,
+/// e.g. `-$1,234.50`. Empty fields are `None`.
+pub(crate) fn parse_amount(
+    record: &csv::StringRecord,
+    idx: Option<usize>,
+) -> Result<Option<Decimal>, Error> {
+    let s = match idx {
+        Some(idx) => field(record, idx),
+        None => return Ok(None),
+    };
+    let bad_amount = || parse_error(record, format!("Bad amount {s}"));
+    let cleaned: String = s
+        .chars()
+        .filter(|c| !matches!(c, '$' | '€' | '£' | ' '))
+        .collect();
+    if cleaned.is_empty() {
+        return Ok(None);
+    }
+    let cleaned = without_thousands_separators(&cleaned).ok_or_else(bad_amount)?;
+    Decimal::from_str(&cleaned)
+        .or_else(|_| Decimal::from_scientific(&cleaned))
+        .map(Some)
+        .map_err(|_| bad_amount())
+}
+
+/// Like `parse_amount` but the field must be present
+pub(crate) fn required_amount(record: &csv::StringRecord, idx: usize) -> Result<Decimal, Error> {
+    parse_amount(record, Some(idx))?
+        .ok_or_else(|| parse_error(record, format!("Missing amount in column {}", idx + 1)))
+}
+
+/// The amount and currency in columns `amount` and `currency`, `None` if
+/// either is empty
+pub(crate) fn parse_leg(
+    record: &csv::StringRecord,
+    amount: usize,
+    currency: usize,
+) -> Result<Option<Amount>, Error> {
+    let currency = field(record, currency);
+    match parse_amount(record, Some(amount))? {
+        Some(amount) if !currency.is_empty() => Ok(Some(Amount::new(amount, currency))),
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn parse_time(record: &csv::StringRecord, idx: usize) -> Result<i64, Error> {
+    let s = field(record, idx);
+    parse_time_ms(s).ok_or_else(|| parse_error(record, format!("Bad time {s}")))
+}
+
+/// Build the record for `record`, recording where it came from. Rows that
+/// move nothing, e.g. zero quantity rewards, are `None`.
+pub(crate) fn build(
+    builder: RecBuilder,
+    time: i64,
+    converter: &str,
+    source: &str,
+    record: &csv::StringRecord,
+) -> Result<Option<TokenTaxRec>, Error> {
+    if builder.moves_nothing() {
+        return Ok(None);
+    }
+    let mut rec = builder
+        .at(time)
+        .map_err(|e| parse_error(record, e.to_string()))?;
+    rec.provenance.source = Some(Source {
+        file: source.to_owned(),
+        line: line(record) as u64,
+    });
+    rec.provenance.steps.push(AuditStep {
+        kind: AuditKind::Conversion,
+        rule: Some(converter.to_owned()),
+        changes: Vec::new(),
+        merged: Vec::new(),
+    });
+    Ok(Some(rec))
+}
+
+/// Add `amount` of `currency` as the fee if it's non-zero
+pub(crate) fn with_fee(builder: RecBuilder, amount: Option<Decimal>, currency: &str) -> RecBuilder {
+    match amount {
+        Some(amount) if !amount.is_zero() => builder.fee(Amount::new(amount.abs(), currency)),
+        _ => builder,
+    }
+}
+
+/// An amount as written by the exporters, empty if `None`
+pub(crate) fn format_amount(amount: Option<Decimal>) -> String {
+    amount.map(|a| a.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_amount() {
+        let parse = |s: &str| parse_amount(&csv::StringRecord::from(vec![s]), Some(0));
+        assert_eq!(parse("-$1,234.50").unwrap(), Some(dec!(-1234.50)));
+        assert_eq!(parse("1,234,567").unwrap(), Some(dec!(1234567)));
+        assert_eq!(parse("€ 12").unwrap(), Some(dec!(12)));
+        assert_eq!(parse("1.5e-3").unwrap(), Some(dec!(0.0015)));
+        assert_eq!(parse("").unwrap(), None);
+        for bad in ["1,5", "1,2345", ",123", "-,123", "1.234,5", "1,,234"] {
+            assert!(
+                matches!(parse(bad), Err(Error::Parse { .. })),
+                "{bad} parsed"
+            );
+        }
+    }
+}
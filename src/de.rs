@@ -0,0 +1,207 @@
+//! German private sale (§ 23 EStG) classification.
+//!
+//! Lots are matched FIFO and a disposal of a lot held longer than one
+//! year is tax free. The remaining net gain for a calendar year is only
+//! taxable if it reaches the exemption threshold, €600 up to 2023 and
+//! €1000 from 2024, otherwise all of it is tax free.
+use std::collections::BTreeMap;
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::error::Error;
+use crate::events::tax_events;
+use crate::jurisdiction::{Germany, Jurisdiction};
+use crate::lots::{LotDisposal, LotEngine};
+use crate::price::PriceOracle;
+use crate::TokenTaxRec;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeClassification {
+    Taxable,
+
+    /// Held longer than one year
+    ExemptHoldingPeriod,
+
+    /// The year's net gain is below the threshold
+    ExemptThreshold,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeDisposal {
+    pub lot: LotDisposal,
+    pub classification: DeClassification,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeYearSummary {
+    /// Net gain of lots held one year or less
+    pub short_term_gain: Decimal,
+
+    /// Net gain of lots held longer than one year
+    pub exempt_gain: Decimal,
+
+    /// short_term_gain if it reached the threshold, otherwise zero
+    pub taxable_gain: Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeReport {
+    pub disposals: Vec<DeDisposal>,
+    pub years: BTreeMap<i32, DeYearSummary>,
+}
+
+/// Lots are consumed in the order of the profile's cost basis and are
+/// exempt when held longer than its long term period
+#[derive(Clone, Debug)]
+pub struct DeCalculator<J: Jurisdiction = Germany> {
+    pub jurisdiction: J,
+
+    /// Exemption thresholds (Freigrenze) by the year they apply from
+    pub thresholds: BTreeMap<i32, Decimal>,
+}
+
+impl DeCalculator<Germany> {
+    pub fn new() -> DeCalculator<Germany> {
+        DeCalculator::with_jurisdiction(Germany::new())
+    }
+}
+
+impl<J: Jurisdiction> DeCalculator<J> {
+    pub fn with_jurisdiction(jurisdiction: J) -> DeCalculator<J> {
+        DeCalculator {
+            jurisdiction,
+            thresholds: BTreeMap::from([(i32::MIN, dec!(600)), (2024, dec!(1000))]),
+        }
+    }
+
+    /// The exemption threshold of tax year `year`, zero if none applies
+    pub fn threshold(&self, year: i32) -> Decimal {
+        self.thresholds
+            .range(..=year)
+            .next_back()
+            .map_or(Decimal::ZERO, |(_, t)| *t)
+    }
+
+    pub fn calculate<O: PriceOracle + ?Sized>(
+        &self,
+        recs: &[TokenTaxRec],
+        oracle: &O,
+    ) -> Result<DeReport, Error> {
+        let events = tax_events(recs, &self.jurisdiction, oracle)?;
+        let method = self.jurisdiction.cost_basis().lot_method();
+        let long_term_months = self.jurisdiction.long_term_months();
+        let lots = LotEngine::new(method).process(&events)?;
+
+        let mut report = DeReport::default();
+        for lot in lots {
+            let year = self.jurisdiction.tax_year(lot.disposed_time);
+            let summary = report.years.entry(year).or_default();
+            let classification = if long_term_months.is_some_and(|m| lot.held_longer_than(m)) {
+                summary.exempt_gain += lot.gain();
+                DeClassification::ExemptHoldingPeriod
+            } else {
+                summary.short_term_gain += lot.gain();
+                DeClassification::Taxable
+            };
+            report.disposals.push(DeDisposal {
+                lot,
+                classification,
+            });
+        }
+
+        for (year, summary) in report.years.iter_mut() {
+            if summary.short_term_gain >= self.threshold(*year) {
+                summary.taxable_gain = summary.short_term_gain;
+                continue;
+            }
+            for d in report.disposals.iter_mut() {
+                if d.classification == DeClassification::Taxable
+                    && self.jurisdiction.tax_year(d.lot.disposed_time) == *year
+                {
+                    d.classification = DeClassification::ExemptThreshold;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl Default for DeCalculator<Germany> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jurisdiction::Canada;
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+
+    #[test]
+    fn test_de_classification() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,BTC,10000,EUR,,,kraken,,,2020-01-01 00:00:00
+Trade,1,BTC,20000,EUR,,,kraken,,,2021-03-01 00:00:00
+Trade,45000,EUR,1.5,BTC,,,kraken,,,2021-06-01 00:00:00
+Trade,1,ETH,1000,EUR,,,kraken,,,2022-01-01 00:00:00
+Trade,1500,EUR,1,ETH,,,kraken,,,2022-02-01 00:00:00
+Trade,1,LTC,100,EUR,,,kraken,,,2023-01-01 00:00:00
+Trade,900,EUR,1,LTC,,,kraken,,,2023-02-01 00:00:00
+Trade,1,XRP,100,EUR,,,kraken,,,2024-01-01 00:00:00
+Trade,900,EUR,1,XRP,,,kraken,,,2024-02-01 00:00:00
+",
+        );
+        let report = DeCalculator::new()
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+
+        assert_eq!(report.disposals.len(), 5);
+        let d = &report.disposals[0];
+        assert_eq!(d.classification, DeClassification::ExemptHoldingPeriod);
+        assert_eq!(d.lot.gain(), dec!(20000));
+        let d = &report.disposals[1];
+        assert_eq!(d.classification, DeClassification::Taxable);
+        assert_eq!(d.lot.quantity, dec!(0.5));
+        assert_eq!(d.lot.gain(), dec!(5000));
+        let d = &report.disposals[2];
+        assert_eq!(d.classification, DeClassification::ExemptThreshold);
+
+        let y2021 = &report.years[&2021];
+        assert_eq!(y2021.short_term_gain, dec!(5000));
+        assert_eq!(y2021.exempt_gain, dec!(20000));
+        assert_eq!(y2021.taxable_gain, dec!(5000));
+        let y2022 = &report.years[&2022];
+        assert_eq!(y2022.short_term_gain, dec!(500));
+        assert_eq!(y2022.exempt_gain, dec!(0));
+        assert_eq!(y2022.taxable_gain, dec!(0));
+
+        // The threshold was €600 until 2023
+        assert_eq!(
+            report.disposals[3].classification,
+            DeClassification::Taxable
+        );
+        assert_eq!(report.years[&2023].taxable_gain, dec!(800));
+        let d = &report.disposals[4];
+        assert_eq!(d.classification, DeClassification::ExemptThreshold);
+        assert_eq!(report.years[&2024].taxable_gain, dec!(0));
+
+        // A profile without a long term period exempts nothing by holding
+        let canada = Canada {
+            home_currency: "EUR".to_owned(),
+        };
+        let report = DeCalculator::with_jurisdiction(canada)
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+        assert!(report
+            .disposals
+            .iter()
+            .all(|d| d.classification != DeClassification::ExemptHoldingPeriod));
+        assert_eq!(report.years[&2021].taxable_gain, dec!(25000));
+    }
+}
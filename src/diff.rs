@@ -0,0 +1,224 @@
+//! Differences between two versions of a set of records, e.g. before and
+//! after re-exporting from an exchange.
+//!
+//! Records with equal fingerprints are unchanged. The rest are paired by a
+//! fuzzy match, records close in time with only a few fields changed, and
+//! reported as modified with their field changes. Anything left unpaired
+//! was added or removed.
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::{field_changes, FieldChange, TokenTaxRec};
+
+/// A record present in both sets but changed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Modified<'a> {
+    pub old_idx: usize,
+    pub old: &'a TokenTaxRec,
+    pub new_idx: usize,
+    pub new: &'a TokenTaxRec,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Diff<'a> {
+    /// Index in the new set and record
+    pub added: Vec<(usize, &'a TokenTaxRec)>,
+
+    /// Index in the old set and record
+    pub removed: Vec<(usize, &'a TokenTaxRec)>,
+    pub modified: Vec<Modified<'a>>,
+    pub unchanged: usize,
+}
+
+impl<'a> Diff<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl<'a> Display for Diff<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, rec) in self.removed.iter() {
+            writeln!(f, "- {idx}: {rec}")?;
+        }
+        for (idx, rec) in self.added.iter() {
+            writeln!(f, "+ {idx}: {rec}")?;
+        }
+        for m in self.modified.iter() {
+            write!(f, "~ {} -> {}:", m.old_idx, m.new_idx)?;
+            for (idx, change) in m.changes.iter().enumerate() {
+                let sep = if idx == 0 { " " } else { ", " };
+                write!(f, "{sep}{change}")?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} modified, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len(),
+            self.unchanged
+        )
+    }
+}
+
+pub struct Differ {
+    /// Records further apart in time are never paired as modified
+    pub time_tolerance_ms: i64,
+
+    /// Records with more changed fields are never paired as modified
+    pub max_changes: usize,
+}
+
+impl Default for Differ {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Differ {
+    pub fn new() -> Differ {
+        Differ {
+            time_tolerance_ms: 24 * 60 * 60 * 1000,
+            max_changes: 3,
+        }
+    }
+
+    pub fn diff<'a>(&self, old: &'a [TokenTaxRec], new: &'a [TokenTaxRec]) -> Diff<'a> {
+        let mut diff = Diff::default();
+
+        // Pair exact matches in order, duplicates pair one to one
+        let mut new_by_fingerprint: HashMap<String, Vec<usize>> = HashMap::new();
+        for (idx, rec) in new.iter().enumerate().rev() {
+            new_by_fingerprint
+                .entry(rec.fingerprint())
+                .or_default()
+                .push(idx);
+        }
+        let mut old_left = Vec::new();
+        let mut new_matched = vec![false; new.len()];
+        for (idx, rec) in old.iter().enumerate() {
+            match new_by_fingerprint
+                .get_mut(&rec.fingerprint())
+                .and_then(|idxs| idxs.pop())
+            {
+                Some(new_idx) => {
+                    new_matched[new_idx] = true;
+                    diff.unchanged += 1;
+                }
+                None => old_left.push(idx),
+            }
+        }
+        let new_left: Vec<usize> = (0..new.len()).filter(|&idx| !new_matched[idx]).collect();
+
+        // Fuzzy pair the rest, fewest changes then closest in time first
+        let mut candidates = Vec::new();
+        for &old_idx in old_left.iter() {
+            for &new_idx in new_left.iter() {
+                let dt = (old[old_idx].time - new[new_idx].time).abs();
+                if dt > self.time_tolerance_ms {
+                    continue;
+                }
+                let changes = field_changes(&old[old_idx], &new[new_idx]);
+                if changes.len() <= self.max_changes {
+                    candidates.push((changes.len(), dt, old_idx, new_idx, changes));
+                }
+            }
+        }
+        candidates.sort_by_key(|&(count, dt, old_idx, new_idx, _)| (count, dt, old_idx, new_idx));
+        let mut old_paired = vec![false; old.len()];
+        let mut new_paired = vec![false; new.len()];
+        for (_, _, old_idx, new_idx, changes) in candidates {
+            if old_paired[old_idx] || new_paired[new_idx] {
+                continue;
+            }
+            old_paired[old_idx] = true;
+            new_paired[new_idx] = true;
+            diff.modified.push(Modified {
+                old_idx,
+                old: &old[old_idx],
+                new_idx,
+                new: &new[new_idx],
+                changes,
+            });
+        }
+        diff.modified.sort_by_key(|m| m.old_idx);
+
+        diff.removed = old_left
+            .into_iter()
+            .filter(|&idx| !old_paired[idx])
+            .map(|idx| (idx, &old[idx]))
+            .collect();
+        diff.added = new_left
+            .into_iter()
+            .filter(|&idx| !new_paired[idx])
+            .map(|idx| (idx, &new[idx]))
+            .collect();
+        diff
+    }
+}
+
+/// Diff `old` and `new` with the default `Differ`
+pub fn diff<'a>(old: &'a [TokenTaxRec], new: &'a [TokenTaxRec]) -> Diff<'a> {
+    Differ::new().diff(old, new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::recs_from_csv;
+
+    #[test]
+    fn test_diff() {
+        let old = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,binance.us,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,,,2022-01-02 00:00:00
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,,,2022-01-02 00:00:00
+Deposit,0.01,ETH,,,,,coinbase,,\"Staking\",2022-01-03 00:00:00
+Withdrawal,,,100,USD,,,some bank,,,2022-01-04 00:00:00
+",
+        );
+        let new = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125.0,usd,,,,,binance.us,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,,,2022-01-02 00:00:00
+Trade,1,ETH,3123.00,USD,0.00125,BNB,binance.us,,,2022-01-02 00:00:00
+Income,0.01,ETH,,,,,coinbase,,\"Staking\",2022-01-03 00:01:00
+Trade,1,BTC,40000,USD,,,binance.us,,,2022-01-05 00:00:00
+",
+        );
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.unchanged, 2);
+        assert_eq!(diff.removed, vec![(4, &old[4])]);
+        assert_eq!(diff.added, vec![(4, &new[4])]);
+        assert_eq!(diff.modified.len(), 2);
+        assert_eq!((diff.modified[0].old_idx, diff.modified[0].new_idx), (2, 2));
+        assert_eq!(
+            diff.modified[0].changes[0].to_string(),
+            "fee_amount 0.00124 -> 0.00125"
+        );
+        let changes: Vec<&str> = diff.modified[1].changes.iter().map(|c| c.field).collect();
+        assert_eq!(changes, vec!["type", "date"]);
+        assert!(!diff.is_empty());
+        assert!(diff
+            .to_string()
+            .ends_with("~ 2 -> 2: fee_amount 0.00124 -> 0.00125\n~ 3 -> 3: type Deposit -> Income, date 2022-01-03 00:00:00 -> 2022-01-03 00:01:00\n1 added, 1 removed, 2 modified, 2 unchanged"));
+
+        assert!(super::diff(&old, &old).is_empty());
+
+        // Too many changes to be the same record
+        let differ = Differ {
+            max_changes: 1,
+            ..Differ::new()
+        };
+        let diff = differ.diff(&old, &new);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.added.len(), 2);
+    }
+}
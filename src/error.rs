@@ -0,0 +1,155 @@
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No price was available for `asset` in `currency` at `time`
+    MissingPrice {
+        asset: String,
+        currency: String,
+        time: i64,
+    },
+
+    /// A field required by the record's type was empty
+    MissingField {
+        rec_idx: usize,
+        field: &'static str,
+    },
+
+    /// The record's type can't be processed, e.g. `Unknown`
+    UnsupportedRecord {
+        rec_idx: usize,
+    },
+
+    /// A record being built is inconsistent
+    InvalidRecord {
+        msg: String,
+    },
+
+    /// A disposal of more `asset` than was held
+    InsufficientHoldings {
+        asset: String,
+        rec_idx: usize,
+    },
+
+    /// Line `line` of an input file couldn't be parsed
+    Parse {
+        line: usize,
+        msg: String,
+    },
+
+    /// A filter expression couldn't be parsed at byte `pos`
+    Filter {
+        pos: usize,
+        msg: String,
+    },
+
+    /// Rule number `rule`, counting from 1, is invalid
+    Rule {
+        rule: usize,
+        msg: String,
+    },
+
+    Csv(csv::Error),
+    Io(std::io::Error),
+
+    #[cfg(feature = "arrow")]
+    Arrow(arrow::error::ArrowError),
+
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+
+    #[cfg(feature = "xlsx")]
+    Xlsx(rust_xlsxwriter::XlsxError),
+
+    #[cfg(feature = "ods")]
+    Zip(zip::result::ZipError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingPrice {
+                asset,
+                currency,
+                time,
+            } => write!(f, "No price for {asset} in {currency} at time {time}"),
+            Error::MissingField { rec_idx, field } => {
+                write!(f, "Record {rec_idx}: missing {field}")
+            }
+            Error::UnsupportedRecord { rec_idx } => {
+                write!(f, "Record {rec_idx}: unsupported record type")
+            }
+            Error::InvalidRecord { msg } => write!(f, "Invalid record: {msg}"),
+            Error::InsufficientHoldings { asset, rec_idx } => {
+                write!(f, "Record {rec_idx}: disposes of more {asset} than held")
+            }
+            Error::Parse { line, msg } => write!(f, "Line {line}: {msg}"),
+            Error::Filter { pos, msg } => write!(f, "Filter position {pos}: {msg}"),
+            Error::Rule { rule, msg } => write!(f, "Rule {rule}: {msg}"),
+            Error::Csv(e) => write!(f, "{e}"),
+            Error::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(e) => write!(f, "{e}"),
+            #[cfg(feature = "parquet")]
+            Error::Parquet(e) => write!(f, "{e}"),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(e) => write!(f, "{e}"),
+            #[cfg(feature = "xlsx")]
+            Error::Xlsx(e) => write!(f, "{e}"),
+            #[cfg(feature = "ods")]
+            Error::Zip(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow::error::ArrowError> for Error {
+    fn from(e: arrow::error::ArrowError) -> Self {
+        Error::Arrow(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        Error::Parquet(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+#[cfg(feature = "xlsx")]
+impl From<rust_xlsxwriter::XlsxError> for Error {
+    fn from(e: rust_xlsxwriter::XlsxError) -> Self {
+        Error::Xlsx(e)
+    }
+}
+
+#[cfg(feature = "ods")]
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        Error::Zip(e)
+    }
+}
@@ -0,0 +1,283 @@
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::jurisdiction::{Jurisdiction, Treatment};
+use crate::price::{value_of, PriceOracle};
+use crate::{TokenTaxRec, TokenTaxRecType};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TaxEventKind {
+    Acquire,
+    Dispose,
+
+    /// Leaves holdings without a gain or loss
+    Remove,
+}
+
+/// An acquisition or disposal of an asset valued in the home currency
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaxEvent {
+    pub kind: TaxEventKind,
+    pub asset: String,
+    pub quantity: Decimal,
+
+    /// Cost of an Acquire or proceeds of a Dispose, excluding fees
+    pub value: Decimal,
+
+    /// Fees attributable to this event
+    pub fee: Decimal,
+
+    pub time: i64,
+
+    /// Index of the originating record
+    pub rec_idx: usize,
+}
+
+fn amount(amount: Option<Decimal>, rec_idx: usize, field: &'static str) -> Result<Decimal, Error> {
+    amount.ok_or(Error::MissingField { rec_idx, field })
+}
+
+fn event(
+    kind: TaxEventKind,
+    asset: &str,
+    quantity: Decimal,
+    value: Decimal,
+    time: i64,
+    rec_idx: usize,
+) -> TaxEvent {
+    TaxEvent {
+        kind,
+        asset: asset.to_owned(),
+        quantity,
+        value,
+        fee: Decimal::ZERO,
+        time,
+        rec_idx,
+    }
+}
+
+/// Convert records into the acquisitions and disposals they cause.
+///
+/// Holdings of the home currency are cash and never generate events.
+/// Deposits and withdrawals are transfers between accounts and are
+/// ignored, Spend, Gift, Lost and Stolen are handled as the
+/// jurisdiction's `treatment` says. A fee paid in a currency other than
+/// the home currency is itself a disposal of the fee currency. The
+/// events are returned in record order.
+pub fn tax_events<J: Jurisdiction + ?Sized, O: PriceOracle + ?Sized>(
+    recs: &[TokenTaxRec],
+    jurisdiction: &J,
+    oracle: &O,
+) -> Result<Vec<TaxEvent>, Error> {
+    let home_currency = jurisdiction.home_currency();
+    let mut events = Vec::new();
+
+    for (rec_idx, rec) in recs.iter().enumerate() {
+        let time = rec.time;
+        let start = events.len();
+
+        match rec.type_txs {
+            TokenTaxRecType::Unknown => return Err(Error::UnsupportedRecord { rec_idx }),
+            TokenTaxRecType::Deposit | TokenTaxRecType::Withdrawal => {}
+            TokenTaxRecType::Trade => {
+                let buy_amount = amount(rec.buy_amount, rec_idx, "buy_amount")?;
+                let sell_amount = amount(rec.sell_amount, rec_idx, "sell_amount")?;
+                let buy_currency = rec.buy_currency.as_str();
+                let sell_currency = rec.sell_currency.as_str();
+
+                // Both legs have the same value, prefer the value of the
+                // sell leg as that is what was given up.
+                let value = if sell_currency == home_currency {
+                    sell_amount
+                } else if buy_currency == home_currency {
+                    buy_amount
+                } else {
+                    value_of(oracle, sell_amount, sell_currency, home_currency, time).or_else(
+                        |_| value_of(oracle, buy_amount, buy_currency, home_currency, time),
+                    )?
+                };
+
+                if buy_currency != home_currency {
+                    events.push(event(
+                        TaxEventKind::Acquire,
+                        buy_currency,
+                        buy_amount,
+                        value,
+                        time,
+                        rec_idx,
+                    ));
+                }
+                if sell_currency != home_currency {
+                    events.push(event(
+                        TaxEventKind::Dispose,
+                        sell_currency,
+                        sell_amount,
+                        value,
+                        time,
+                        rec_idx,
+                    ));
+                }
+            }
+            TokenTaxRecType::Income | TokenTaxRecType::Mining => {
+                let buy_amount = amount(rec.buy_amount, rec_idx, "buy_amount")?;
+                if rec.buy_currency != home_currency {
+                    let value =
+                        value_of(oracle, buy_amount, &rec.buy_currency, home_currency, time)?;
+                    events.push(event(
+                        TaxEventKind::Acquire,
+                        &rec.buy_currency,
+                        buy_amount,
+                        value,
+                        time,
+                        rec_idx,
+                    ));
+                }
+            }
+            TokenTaxRecType::Spend
+            | TokenTaxRecType::Gift
+            | TokenTaxRecType::Lost
+            | TokenTaxRecType::Stolen => {
+                let sell_amount = amount(rec.sell_amount, rec_idx, "sell_amount")?;
+                if rec.sell_currency != home_currency {
+                    let (kind, value) = match jurisdiction.treatment(&rec.type_txs) {
+                        Treatment::Disposal => (
+                            TaxEventKind::Dispose,
+                            value_of(oracle, sell_amount, &rec.sell_currency, home_currency, time)?,
+                        ),
+                        Treatment::ZeroProceeds => (TaxEventKind::Dispose, Decimal::ZERO),
+                        Treatment::Removal => (TaxEventKind::Remove, Decimal::ZERO),
+                    };
+                    events.push(event(
+                        kind,
+                        &rec.sell_currency,
+                        sell_amount,
+                        value,
+                        time,
+                        rec_idx,
+                    ));
+                }
+            }
+        }
+
+        if let Some(fee_amount) = rec.fee_amount {
+            if fee_amount.is_zero() || rec.fee_currency.is_empty() {
+                continue;
+            }
+            let fee = value_of(oracle, fee_amount, &rec.fee_currency, home_currency, time)?;
+
+            // Attach the fee to the acquisition if there is one, otherwise
+            // to the disposal, so it increases cost or reduces proceeds.
+            let rec_events = &mut events[start..];
+            let target = rec_events
+                .iter()
+                .position(|e| e.kind == TaxEventKind::Acquire)
+                .or_else(|| {
+                    rec_events
+                        .iter()
+                        .position(|e| e.kind == TaxEventKind::Dispose)
+                });
+            if let Some(idx) = target {
+                rec_events[idx].fee += fee;
+            }
+
+            if rec.fee_currency != home_currency {
+                events.push(event(
+                    TaxEventKind::Dispose,
+                    &rec.fee_currency,
+                    fee_amount,
+                    fee,
+                    time,
+                    rec_idx,
+                ));
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jurisdiction::{Canada, UnitedStates};
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_tax_events() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5000,USD,,,,,binance.us,,,2021-01-01 00:00:00
+Trade,1,ETH,3000,USD,0.01,BNB,binance.us,,,2021-01-02 00:00:00
+Trade,0.1,BTC,1,ETH,,,binance.us,,,2021-01-03 00:00:00
+Income,0.5,BNB,,,,,binance.us,,,2021-01-04 00:00:00
+Withdrawal,,,0.1,BTC,,,binance.us,,,2021-01-05 00:00:00
+Lost,,,0.1,BTC,,,,,,2021-01-06 00:00:00
+",
+        );
+        let mut pt = PriceTable::new();
+        pt.insert("BNB", "USD", 0, dec!(100));
+        pt.insert("ETH", "USD", 0, dec!(3500));
+
+        let events = tax_events(&recs, &UnitedStates::new(), &pt).unwrap();
+        assert_eq!(events.len(), 6);
+
+        // Trade ETH for USD, fee paid in BNB
+        assert_eq!(events[0].kind, TaxEventKind::Acquire);
+        assert_eq!(events[0].asset, "ETH");
+        assert_eq!(events[0].value, dec!(3000));
+        assert_eq!(events[0].fee, dec!(1));
+        assert_eq!(events[0].rec_idx, 1);
+        assert_eq!(events[1].kind, TaxEventKind::Dispose);
+        assert_eq!(events[1].asset, "BNB");
+        assert_eq!(events[1].quantity, dec!(0.01));
+        assert_eq!(events[1].value, dec!(1));
+
+        // Crypto to crypto trade is valued by the sell leg
+        assert_eq!(events[2].kind, TaxEventKind::Acquire);
+        assert_eq!(events[2].asset, "BTC");
+        assert_eq!(events[2].value, dec!(3500));
+        assert_eq!(events[3].kind, TaxEventKind::Dispose);
+        assert_eq!(events[3].asset, "ETH");
+        assert_eq!(events[3].value, dec!(3500));
+
+        assert_eq!(events[4].kind, TaxEventKind::Acquire);
+        assert_eq!(events[4].asset, "BNB");
+        assert_eq!(events[4].value, dec!(50));
+
+        // Lost isn't deductible in the US but is a capital loss in Canada
+        assert_eq!(events[5].kind, TaxEventKind::Remove);
+        assert_eq!(events[5].asset, "BTC");
+        assert_eq!(events[5].value, dec!(0));
+        assert_eq!(events[5].rec_idx, 5);
+
+        let ca = Canada {
+            home_currency: "USD".to_owned(),
+        };
+        let events = tax_events(&recs, &ca, &pt).unwrap();
+        assert_eq!(events[5].kind, TaxEventKind::Dispose);
+        assert_eq!(events[5].value, dec!(0));
+    }
+
+    #[test]
+    fn test_tax_events_errors() {
+        let recs = vec![TokenTaxRec::new()];
+        assert!(matches!(
+            tax_events(&recs, &UnitedStates::new(), &PriceTable::new()),
+            Err(Error::UnsupportedRecord { rec_idx: 0 })
+        ));
+
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Income,1,ETH,,,,,binance.us,,,2021-01-01 00:00:00
+",
+        );
+        assert!(matches!(
+            tax_events(&recs, &UnitedStates::new(), &PriceTable::new()),
+            Err(Error::MissingPrice { .. })
+        ));
+    }
+}
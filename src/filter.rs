@@ -0,0 +1,497 @@
+//! Filter expressions over `TokenTaxRec`s, e.g.
+//!
+//! ```text
+//! type in (Trade,Spend) and asset = ETH and exchange = "binance.us" and date >= 2022-04-01
+//! ```
+//!
+//! Comparisons are `field op value` with `op` one of `=`, `!=`, `<`, `<=`,
+//! `>`, `>=` or `~` (contains), or `field in (value, ...)`. They're combined
+//! with `and`, `or`, `not` and parentheses. Text comparisons ignore case and
+//! values with spaces or operator characters are quoted.
+//!
+//! Fields are `type`, `asset` (the buy or sell currency), `buy_currency`,
+//! `sell_currency`, `fee_currency`, `buy_amount`, `sell_amount`,
+//! `fee_amount`, `exchange`, `group`, `comment` and `date`. A date without a
+//! time covers the whole day, so `date = 2022-04-01` matches any time that
+//! day and `date <= 2022-06-30` includes the 30th.
+use std::str::FromStr;
+
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::time_utils::parse_time_ms;
+use crate::{GroupType, TokenTaxRec, TokenTaxRecType};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    Type,
+    Asset,
+    BuyCurrency,
+    SellCurrency,
+    FeeCurrency,
+    BuyAmount,
+    SellAmount,
+    FeeAmount,
+    Exchange,
+    Group,
+    Comment,
+    Date,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name.to_lowercase().as_str() {
+            "type" => Field::Type,
+            "asset" => Field::Asset,
+            "buy_currency" => Field::BuyCurrency,
+            "sell_currency" => Field::SellCurrency,
+            "fee_currency" => Field::FeeCurrency,
+            "buy_amount" => Field::BuyAmount,
+            "sell_amount" => Field::SellAmount,
+            "fee_amount" => Field::FeeAmount,
+            "exchange" => Field::Exchange,
+            "group" => Field::Group,
+            "comment" => Field::Comment,
+            "date" | "time" => Field::Date,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Text(String),
+    Type(TokenTaxRecType),
+    Amount(Decimal),
+
+    /// Start, inclusive, and end, exclusive
+    Time(i64, i64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Field, Op, Value),
+    In(Field, Vec<Value>),
+}
+
+/// A parsed filter expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Result<Filter, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            idx: 0,
+            end: s.len(),
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.idx) {
+            return Err(filter_error(token.pos, "Expected and, or or end"));
+        }
+        Ok(Filter { expr })
+    }
+
+    pub fn matches(&self, rec: &TokenTaxRec) -> bool {
+        eval(&self.expr, rec)
+    }
+
+    /// The records of `recs` matching the filter
+    pub fn apply<'a>(&self, recs: &'a [TokenTaxRec]) -> Vec<&'a TokenTaxRec> {
+        recs.iter().filter(|rec| self.matches(rec)).collect()
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Filter::parse(s)
+    }
+}
+
+fn filter_error(pos: usize, msg: &str) -> Error {
+    Error::Filter {
+        pos,
+        msg: msg.to_owned(),
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Tok {
+    LParen,
+    RParen,
+    Comma,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+#[derive(Debug)]
+struct Token {
+    tok: Tok,
+    pos: usize,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(pos, c)) = chars.peek() {
+        chars.next();
+        let next_is_eq = matches!(chars.peek(), Some((_, '=')));
+        let tok = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            ',' => Tok::Comma,
+            '~' => Tok::Op(Op::Contains),
+            '=' => Tok::Op(Op::Eq),
+            '!' | '<' | '>' => {
+                if next_is_eq {
+                    chars.next();
+                }
+                Tok::Op(match (c, next_is_eq) {
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return Err(filter_error(pos, "Expected !=")),
+                })
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => text.push(c),
+                            None => return Err(filter_error(pos, "Unterminated string")),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(filter_error(pos, "Unterminated string")),
+                    }
+                }
+                Tok::Quoted(text)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || "(),=!<>~\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                Tok::Word(word)
+            }
+        };
+        tokens.push(Token { tok, pos });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    idx: usize,
+
+    /// Position reported for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn pos(&self) -> usize {
+        self.tokens.get(self.idx).map_or(self.end, |t| t.pos)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.idx).map(|t| &t.tok)
+    }
+
+    fn next(&mut self) -> Option<&Tok> {
+        let tok = self.tokens.get(self.idx).map(|t| &t.tok);
+        self.idx += 1;
+        tok
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Tok::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.idx += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, tok: Tok, msg: &str) -> Result<(), Error> {
+        let pos = self.pos();
+        match self.next() {
+            Some(t) if *t == tok => Ok(()),
+            _ => Err(filter_error(pos, msg)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, Error> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let pos = self.pos();
+        let field = match self.next() {
+            Some(Tok::LParen) => {
+                let expr = self.or()?;
+                self.expect(Tok::RParen, "Expected )")?;
+                return Ok(expr);
+            }
+            Some(Tok::Word(w)) => {
+                Field::from_name(w).ok_or_else(|| filter_error(pos, "Unknown field"))?
+            }
+            _ => return Err(filter_error(pos, "Expected a field or (")),
+        };
+
+        if self.keyword("in") {
+            self.expect(Tok::LParen, "Expected (")?;
+            let mut values = vec![self.value(field, Op::Eq)?];
+            while self.peek() == Some(&Tok::Comma) {
+                self.idx += 1;
+                values.push(self.value(field, Op::Eq)?);
+            }
+            self.expect(Tok::RParen, "Expected , or )")?;
+            return Ok(Expr::In(field, values));
+        }
+
+        let pos = self.pos();
+        let op = match self.next() {
+            Some(Tok::Op(op)) => *op,
+            _ => return Err(filter_error(pos, "Expected an operator or in")),
+        };
+        let value = self.value(field, op)?;
+        Ok(Expr::Cmp(field, op, value))
+    }
+
+    fn value(&mut self, field: Field, op: Op) -> Result<Value, Error> {
+        let pos = self.pos();
+        let text = match self.next() {
+            Some(Tok::Word(w)) | Some(Tok::Quoted(w)) => w.clone(),
+            _ => return Err(filter_error(pos, "Expected a value")),
+        };
+        let ordered = matches!(op, Op::Lt | Op::Le | Op::Gt | Op::Ge);
+        match field {
+            Field::Type => {
+                if ordered || op == Op::Contains {
+                    return Err(filter_error(pos, "type only supports =, != and in"));
+                }
+                let t = TokenTaxRecType::from_str_ignore_case(&text)
+                    .map_err(|e| filter_error(pos, &e))?;
+                Ok(Value::Type(t))
+            }
+            Field::BuyAmount | Field::SellAmount | Field::FeeAmount => {
+                if op == Op::Contains {
+                    return Err(filter_error(pos, "Amounts don't support ~"));
+                }
+                Decimal::from_str(&text)
+                    .map(Value::Amount)
+                    .map_err(|_| filter_error(pos, "Bad amount"))
+            }
+            Field::Date => {
+                if op == Op::Contains {
+                    return Err(filter_error(pos, "date doesn't support ~"));
+                }
+                let start = parse_time_ms(&text).ok_or_else(|| filter_error(pos, "Bad date"))?;
+                let end = if chrono::NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d").is_ok() {
+                    start + DAY_MS
+                } else {
+                    start + 1
+                };
+                Ok(Value::Time(start, end))
+            }
+            _ => {
+                if ordered {
+                    return Err(filter_error(pos, "Text only supports =, !=, ~ and in"));
+                }
+                Ok(Value::Text(text))
+            }
+        }
+    }
+}
+
+fn eval(expr: &Expr, rec: &TokenTaxRec) -> bool {
+    match expr {
+        Expr::And(a, b) => eval(a, rec) && eval(b, rec),
+        Expr::Or(a, b) => eval(a, rec) || eval(b, rec),
+        Expr::Not(a) => !eval(a, rec),
+        Expr::Cmp(field, Op::Ne, value) => !compare(rec, *field, Op::Eq, value),
+        Expr::Cmp(field, op, value) => compare(rec, *field, *op, value),
+        Expr::In(field, values) => values.iter().any(|v| compare(rec, *field, Op::Eq, v)),
+    }
+}
+
+fn compare(rec: &TokenTaxRec, field: Field, op: Op, value: &Value) -> bool {
+    let text = |s: &str, v: &str| match op {
+        Op::Eq => s.eq_ignore_ascii_case(v),
+        Op::Contains => s.to_lowercase().contains(&v.to_lowercase()),
+        _ => false,
+    };
+    let amount = |a: Option<Decimal>, v: &Decimal| match a {
+        Some(a) => match op {
+            Op::Eq => a == *v,
+            Op::Lt => a < *v,
+            Op::Le => a <= *v,
+            Op::Gt => a > *v,
+            Op::Ge => a >= *v,
+            _ => false,
+        },
+        None => false,
+    };
+    match (field, value) {
+        (Field::Type, Value::Type(t)) => rec.type_txs == *t,
+        (Field::Asset, Value::Text(v)) => text(&rec.buy_currency, v) || text(&rec.sell_currency, v),
+        (Field::BuyCurrency, Value::Text(v)) => text(&rec.buy_currency, v),
+        (Field::SellCurrency, Value::Text(v)) => text(&rec.sell_currency, v),
+        (Field::FeeCurrency, Value::Text(v)) => text(&rec.fee_currency, v),
+        (Field::Exchange, Value::Text(v)) => text(&rec.exchange, v),
+        (Field::Comment, Value::Text(v)) => text(&rec.comment, v),
+        (Field::Group, Value::Text(v)) => match rec.group {
+            Some(GroupType::Margin) => text("margin", v),
+            None => text("", v),
+        },
+        (Field::BuyAmount, Value::Amount(v)) => amount(rec.buy_amount, v),
+        (Field::SellAmount, Value::Amount(v)) => amount(rec.sell_amount, v),
+        (Field::FeeAmount, Value::Amount(v)) => amount(rec.fee_amount, v),
+        (Field::Date, Value::Time(start, end)) => match op {
+            Op::Eq => *start <= rec.time && rec.time < *end,
+            Op::Lt => rec.time < *start,
+            Op::Le => rec.time < *end,
+            Op::Gt => rec.time >= *end,
+            Op::Ge => rec.time >= *start,
+            _ => false,
+        },
+        _ => panic!("SNH"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::recs_from_csv;
+
+    fn recs() -> Vec<TokenTaxRec> {
+        recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,,,2022-04-01 10:00:00
+Trade,3000,USD,1,ETH,,,binance.us,margin,,2022-06-30 23:00:00
+Spend,,,0.5,ETH,,,coinbase,,\"Gift for wife\",2022-05-01 00:00:00
+Income,0.001,BNB,,,,,binance.us,,\"Referral Commission\",2022-03-31 23:59:59
+Trade,1,BTC,40000,USD,,,binance.us,,,2022-07-01 00:00:00
+",
+        )
+    }
+
+    fn matching(filter: &str) -> Vec<usize> {
+        let filter = Filter::parse(filter).unwrap();
+        recs()
+            .iter()
+            .enumerate()
+            .filter(|(_, rec)| filter.matches(rec))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(
+            matching(
+                r#"type in (Trade,Spend) and asset = ETH and exchange = "binance.us" and date >= 2022-04-01"#
+            ),
+            vec![0, 1]
+        );
+        assert_eq!(matching("asset = eth and not type = Trade"), vec![2]);
+        assert_eq!(matching("type != Trade"), vec![2, 3]);
+        assert_eq!(matching("date = 2022-06-30"), vec![1]);
+        assert_eq!(
+            matching("date >= 2022-04-01 and date <= 2022-06-30"),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            matching("date < 2022-04-01 or date > 2022-06-30"),
+            vec![3, 4]
+        );
+        assert_eq!(matching(r#"date < "2022-04-01 10:00:00""#), vec![3]);
+        assert_eq!(matching("comment ~ gift or group = margin"), vec![1, 2]);
+        assert_eq!(
+            matching("sell_amount >= 3000 and (fee_currency = BNB or buy_currency = BTC)"),
+            vec![0, 4]
+        );
+        assert_eq!(matching("fee_amount != 0.00124"), vec![1, 2, 3, 4]);
+        assert_eq!(
+            matching("TYPE = Income OR type = Spend AND asset = BTC"),
+            vec![3]
+        );
+        assert_eq!(matching("type = income"), vec![3]);
+        assert_eq!(matching("type in (TRADE, spend)"), vec![0, 1, 2, 4]);
+        assert_eq!(
+            recs().len(),
+            Filter::parse("asset ~ \"\"").unwrap().apply(&recs()).len()
+        );
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        let pos = |s: &str| match Filter::parse(s) {
+            Err(Error::Filter { pos, .. }) => pos,
+            r => panic!("Unexpected {r:?}"),
+        };
+        assert_eq!(pos("price = 1"), 0);
+        assert_eq!(pos("type = Swap"), 7);
+        assert_eq!(pos("type < Trade"), 7);
+        assert_eq!(pos("asset ETH"), 6);
+        assert_eq!(pos("asset = ETH and"), 15);
+        assert_eq!(pos("asset = ETH ETH"), 12);
+        assert_eq!(pos("(asset = ETH"), 12);
+        assert_eq!(pos("type in (Trade Spend)"), 15);
+        assert_eq!(pos("date >= 2022-13-01"), 8);
+        assert_eq!(pos("fee_amount > abc"), 13);
+        assert_eq!(pos("comment = \"abc"), 10);
+        assert_eq!(pos("asset ! ETH"), 6);
+    }
+}
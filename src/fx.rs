@@ -0,0 +1,289 @@
+//! Conversion of amounts into a home currency on top of a `PriceOracle`.
+//!
+//! When there is no direct price between two currencies the conversion is
+//! triangulated through bridge currencies, e.g. ETH->USD->EUR, and the path
+//! used is reported with the result.
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Display;
+
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::price::PriceOracle;
+use crate::TokenTaxRec;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Amount {
+    pub amount: Decimal,
+    pub currency: String,
+}
+
+impl Amount {
+    pub fn new(amount: Decimal, currency: &str) -> Amount {
+        Amount {
+            amount,
+            currency: currency.to_owned(),
+        }
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+/// One hop of a conversion
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FxStep {
+    pub from: String,
+    pub to: String,
+    pub rate: Decimal,
+
+    /// The rate is the reciprocal of the oracle's `to` in `from` price
+    pub inverted: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConversionPath {
+    /// Empty if the amount was already in the target currency
+    pub steps: Vec<FxStep>,
+}
+
+impl ConversionPath {
+    pub fn rate(&self) -> Decimal {
+        self.steps.iter().map(|s| s.rate).product()
+    }
+
+    pub fn is_direct(&self) -> bool {
+        self.steps.len() <= 1
+    }
+}
+
+impl Display for ConversionPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.steps.first() {
+            Some(first) => {
+                write!(f, "{}", first.from)?;
+                for step in self.steps.iter() {
+                    write!(f, "->{}", step.to)?;
+                }
+                Ok(())
+            }
+            None => write!(f, "identity"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Converted {
+    pub original: Amount,
+    pub converted: Amount,
+    pub path: ConversionPath,
+}
+
+/// Each leg of a record converted into the home currency
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConvertedRec {
+    pub buy: Option<Converted>,
+    pub sell: Option<Converted>,
+    pub fee: Option<Converted>,
+}
+
+pub struct FxConverter<'a, O: PriceOracle + ?Sized> {
+    oracle: &'a O,
+    pub home_currency: String,
+
+    /// Currencies to triangulate through, in order of preference
+    pub bridges: Vec<String>,
+
+    /// Maximum number of hops in a path
+    pub max_hops: usize,
+}
+
+impl<'a, O: PriceOracle + ?Sized> FxConverter<'a, O> {
+    pub fn new(oracle: &'a O, home_currency: &str) -> FxConverter<'a, O> {
+        FxConverter {
+            oracle,
+            home_currency: home_currency.to_owned(),
+            bridges: ["USD", "EUR", "USDT", "BTC", "ETH"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            max_hops: 3,
+        }
+    }
+
+    fn step(&self, from: &str, to: &str, time: i64) -> Option<FxStep> {
+        if let Some(rate) = self.oracle.price(from, to, time) {
+            return Some(FxStep {
+                from: from.to_owned(),
+                to: to.to_owned(),
+                rate,
+                inverted: false,
+            });
+        }
+        match self.oracle.price(to, from, time) {
+            Some(price) if !price.is_zero() => Some(FxStep {
+                from: from.to_owned(),
+                to: to.to_owned(),
+                rate: Decimal::ONE / price,
+                inverted: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Shortest path from `from` to `to` at `time`
+    pub fn path(&self, from: &str, to: &str, time: i64) -> Option<ConversionPath> {
+        if from == to {
+            return Some(ConversionPath::default());
+        }
+
+        let mut visited = HashSet::new();
+        visited.insert(from.to_owned());
+        let mut queue = VecDeque::new();
+        queue.push_back((from.to_owned(), Vec::<FxStep>::new()));
+        while let Some((currency, steps)) = queue.pop_front() {
+            if let Some(step) = self.step(&currency, to, time) {
+                let mut steps = steps;
+                steps.push(step);
+                return Some(ConversionPath { steps });
+            }
+            if steps.len() + 1 >= self.max_hops {
+                continue;
+            }
+            for bridge in self.bridges.iter() {
+                if visited.contains(bridge) || bridge == to {
+                    continue;
+                }
+                if let Some(step) = self.step(&currency, bridge, time) {
+                    visited.insert(bridge.clone());
+                    let mut steps = steps.clone();
+                    steps.push(step);
+                    queue.push_back((bridge.clone(), steps));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn convert_to(
+        &self,
+        amount: &Amount,
+        currency: &str,
+        time: i64,
+    ) -> Result<Converted, Error> {
+        let path =
+            self.path(&amount.currency, currency, time)
+                .ok_or_else(|| Error::MissingPrice {
+                    asset: amount.currency.clone(),
+                    currency: currency.to_owned(),
+                    time,
+                })?;
+        Ok(Converted {
+            original: amount.clone(),
+            converted: Amount::new(amount.amount * path.rate(), currency),
+            path,
+        })
+    }
+
+    pub fn convert(&self, amount: &Amount, time: i64) -> Result<Converted, Error> {
+        self.convert_to(amount, &self.home_currency, time)
+    }
+
+    /// Convert each leg of `rec` at the record's time
+    pub fn convert_rec(&self, rec: &TokenTaxRec) -> Result<ConvertedRec, Error> {
+        let convert = |leg: Option<Amount>| leg.map(|a| self.convert(&a, rec.time)).transpose();
+        Ok(ConvertedRec {
+            buy: convert(rec.buy_leg())?,
+            sell: convert(rec.sell_leg())?,
+            fee: convert(rec.fee_leg())?,
+        })
+    }
+}
+
+/// Prices through the converter are triangulated
+impl<'a, O: PriceOracle + ?Sized> PriceOracle for FxConverter<'a, O> {
+    fn price(&self, asset: &str, currency: &str, time: i64) -> Option<Decimal> {
+        self.path(asset, currency, time).map(|path| path.rate())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    fn prices() -> PriceTable {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(2000));
+        pt.insert("EUR", "USD", 0, dec!(1.25));
+        pt.insert("BNB", "BTC", 0, dec!(0.01));
+        pt.insert("BTC", "USD", 0, dec!(40000));
+        pt
+    }
+
+    #[test]
+    fn test_paths() {
+        let pt = prices();
+        let fx = FxConverter::new(&pt, "EUR");
+
+        let path = fx.path("EUR", "EUR", 0).unwrap();
+        assert_eq!(path.to_string(), "identity");
+        assert_eq!(path.rate(), dec!(1));
+
+        let path = fx.path("USD", "EUR", 0).unwrap();
+        assert_eq!(path.to_string(), "USD->EUR");
+        assert!(path.steps[0].inverted);
+        assert_eq!(path.rate(), dec!(0.8));
+
+        let path = fx.path("ETH", "EUR", 0).unwrap();
+        assert_eq!(path.to_string(), "ETH->USD->EUR");
+        assert_eq!(path.rate(), dec!(1600));
+
+        let path = fx.path("BNB", "EUR", 0).unwrap();
+        assert_eq!(path.to_string(), "BNB->BTC->USD->EUR");
+        assert_eq!(path.rate(), dec!(320));
+
+        assert_eq!(fx.path("XYZ", "EUR", 0), None);
+        assert_eq!(fx.path("ETH", "EUR", -1), None);
+    }
+
+    #[test]
+    fn test_convert() {
+        let pt = prices();
+        let fx = FxConverter::new(&pt, "EUR");
+
+        let converted = fx.convert(&Amount::new(dec!(2), "ETH"), 0).unwrap();
+        assert_eq!(converted.converted, Amount::new(dec!(3200), "EUR"));
+        assert_eq!(converted.path.to_string(), "ETH->USD->EUR");
+        assert!(matches!(
+            fx.convert(&Amount::new(dec!(2), "XYZ"), 0),
+            Err(Error::MissingPrice { .. })
+        ));
+        assert_eq!(fx.price("ETH", "EUR", 0), Some(dec!(1600)));
+    }
+
+    #[test]
+    fn test_convert_rec() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,2000,USD,0.01,BNB,binance.us,,,1970-01-01 00:00:00
+",
+        );
+        let pt = prices();
+        let fx = FxConverter::new(&pt, "EUR");
+
+        let converted = fx.convert_rec(&recs[0]).unwrap();
+        assert_eq!(converted.buy.unwrap().converted.amount, dec!(1600));
+        assert_eq!(converted.sell.unwrap().converted.amount, dec!(1600));
+        let fee = converted.fee.unwrap();
+        assert_eq!(fee.converted.amount, dec!(3.2));
+        assert_eq!(fee.path.to_string(), "BNB->BTC->USD->EUR");
+    }
+}
@@ -0,0 +1,91 @@
+//! Holdings of each currency implied by records, the amounts bought less
+//! the amounts sold and fees paid.
+use std::collections::BTreeMap;
+
+use rust_decimal::prelude::*;
+
+use crate::TokenTaxRec;
+
+/// Changes to holdings made by `recs` in time order, records at the same
+/// time keep their order
+fn changes(recs: &[TokenTaxRec]) -> Vec<(i64, String, Decimal)> {
+    let mut recs: Vec<&TokenTaxRec> = recs.iter().collect();
+    recs.sort_by_key(|r| r.time);
+    let mut changes = Vec::new();
+    for rec in recs {
+        let legs = [
+            (rec.buy_leg(), Decimal::ONE),
+            (rec.sell_leg(), Decimal::NEGATIVE_ONE),
+            (rec.fee_leg(), Decimal::NEGATIVE_ONE),
+        ];
+        for (leg, sign) in legs {
+            if let Some(leg) = leg {
+                changes.push((rec.time, leg.currency, leg.amount * sign));
+            }
+        }
+    }
+    changes
+}
+
+/// Balance of each currency after the records at or before `time`,
+/// currencies with nothing held are left out
+pub fn balances_at(recs: &[TokenTaxRec], time: i64) -> BTreeMap<String, Decimal> {
+    let mut balances: BTreeMap<String, Decimal> = BTreeMap::new();
+    for (_, currency, change) in changes(recs).into_iter().filter(|c| c.0 <= time) {
+        *balances.entry(currency).or_default() += change;
+    }
+    balances.retain(|_, balance| !balance.is_zero());
+    balances
+}
+
+/// Balance of each currency after each time it changes
+pub fn holdings_over_time(recs: &[TokenTaxRec]) -> BTreeMap<String, Vec<(i64, Decimal)>> {
+    let mut holdings: BTreeMap<String, Vec<(i64, Decimal)>> = BTreeMap::new();
+    for (time, currency, change) in changes(recs) {
+        let points = holdings.entry(currency).or_default();
+        let balance = points.last().map_or(Decimal::ZERO, |p| p.1) + change;
+        match points.last_mut() {
+            Some(last) if last.0 == time => last.1 = balance,
+            _ => points.push((time, balance)),
+        }
+    }
+    holdings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    const CSV: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3000,USD,0.01,ETH,kraken,,,2022-01-02 00:00:00
+Deposit,5000,USD,,,,,kraken,,,2022-01-01 00:00:00
+Trade,2000,USD,0.5,ETH,,,kraken,,,2022-01-03 00:00:00
+Spend,,,0.49,ETH,,,,,,2022-01-03 00:00:00
+";
+
+    #[test]
+    fn test_holdings() {
+        let recs = recs_from_csv(CSV);
+        let balances = balances_at(&recs, time_ms("2022-01-02 00:00:00"));
+        assert_eq!(balances["ETH"], dec!(0.99));
+        assert_eq!(balances["USD"], dec!(2000));
+
+        // The ETH is all gone
+        let balances = balances_at(&recs, time_ms("2022-01-03 00:00:00"));
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances["USD"], dec!(4000));
+
+        let holdings = holdings_over_time(&recs);
+        assert_eq!(
+            holdings["ETH"],
+            [
+                (time_ms("2022-01-02 00:00:00"), dec!(0.99)),
+                (time_ms("2022-01-03 00:00:00"), dec!(0)),
+            ]
+        );
+        assert_eq!(holdings["USD"].len(), 3);
+    }
+}
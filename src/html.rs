@@ -0,0 +1,479 @@
+//! A self-contained static HTML report of a record set, a single file
+//! with no scripts or external resources so it can be viewed offline.
+//!
+//! The overview totals gains per tax year and lists the assets held. Each
+//! asset has a page, an anchored section shown on its own by CSS, with a
+//! chart of holdings over time, its transactions, the lots its disposals
+//! consumed and its realized gains.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::io::Write;
+
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::events::tax_events;
+use crate::holdings::holdings_over_time;
+use crate::jurisdiction::{CostBasis, Jurisdiction};
+use crate::lots::{LotDisposal, LotEngine};
+use crate::price::PriceOracle;
+use crate::report::{realized_gains, Realized};
+use crate::time_utils::format_time_ms;
+use crate::TokenTaxRec;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+nav { margin-bottom: 1em; }
+nav a { margin-right: 0.8em; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; }
+th { background: #eee; }
+td.n { text-align: right; font-family: monospace; }
+tr.total td { font-weight: bold; }
+section { display: none; }
+section:target, #overview { display: block; }
+section:target ~ #overview { display: none; }
+svg { background: #fafafa; border: 1px solid #ccc; }
+";
+
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 200.0;
+const CHART_MARGIN: f64 = 24.0;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Id of the page of the `idx`th asset, asset names may hold anything
+fn page_id(idx: usize) -> String {
+    format!("asset-{idx}")
+}
+
+/// True if any leg of `rec` is in `asset`
+fn touches(rec: &TokenTaxRec, asset: &str) -> bool {
+    [&rec.buy_currency, &rec.sell_currency, &rec.fee_currency]
+        .iter()
+        .any(|c| *c == asset)
+}
+
+fn money(d: Decimal) -> String {
+    d.round_dp(2).to_string()
+}
+
+fn row(html: &mut String, class: &str, cells: &[(bool, String)]) {
+    match class {
+        "" => html.push_str("<tr>"),
+        _ => write!(html, r#"<tr class="{class}">"#).unwrap(),
+    }
+    for (numeric, cell) in cells {
+        match numeric {
+            true => write!(html, r#"<td class="n">{cell}</td>"#).unwrap(),
+            false => write!(html, "<td>{cell}</td>").unwrap(),
+        }
+    }
+    html.push_str("</tr>\n");
+}
+
+fn header(html: &mut String, names: &[&str]) {
+    html.push_str("<table>\n<tr>");
+    for name in names {
+        write!(html, "<th>{name}</th>").unwrap();
+    }
+    html.push_str("</tr>\n");
+}
+
+/// A step chart of `points`, balances after each time they change
+fn chart(html: &mut String, asset: &str, points: &[(i64, Decimal)]) {
+    let start = points.first().map_or(0, |p| p.0);
+    let end = points.last().map_or(0, |p| p.0).max(start + 86_400_000);
+    let max = points
+        .iter()
+        .map(|p| p.1)
+        .max()
+        .filter(|m| *m > Decimal::ZERO)
+        .unwrap_or(Decimal::ONE);
+    let x = |time: i64| {
+        CHART_MARGIN
+            + (time - start) as f64 / (end - start) as f64 * (CHART_WIDTH - 2.0 * CHART_MARGIN)
+    };
+    let y = |balance: Decimal| {
+        let fraction = (balance / max).to_f64().unwrap_or(0.0).max(0.0);
+        CHART_HEIGHT - CHART_MARGIN - fraction * (CHART_HEIGHT - 2.0 * CHART_MARGIN)
+    };
+
+    let mut path = format!("M{:.1},{:.1}", x(start), y(Decimal::ZERO));
+    for (time, balance) in points {
+        write!(path, " H{:.1} V{:.1}", x(*time), y(*balance)).unwrap();
+    }
+    write!(path, " H{:.1}", x(end)).unwrap();
+    write!(
+        html,
+        r##"<svg viewBox="0 0 {CHART_WIDTH} {CHART_HEIGHT}" width="{CHART_WIDTH}" height="{CHART_HEIGHT}" role="img" aria-label="{asset} held over time">
+<line x1="{m}" y1="{b:.1}" x2="{r:.1}" y2="{b:.1}" stroke="#999"/>
+<path d="{path}" fill="none" stroke="#2a6fb0" stroke-width="2"/>
+<text x="{m}" y="{t:.1}" font-size="11">{max}</text>
+<text x="{m}" y="{l:.1}" font-size="11">{first}</text>
+<text x="{r:.1}" y="{l:.1}" font-size="11" text-anchor="end">{last}</text>
+</svg>
+"##,
+        m = CHART_MARGIN,
+        b = CHART_HEIGHT - CHART_MARGIN,
+        r = CHART_WIDTH - CHART_MARGIN,
+        t = CHART_MARGIN - 8.0,
+        l = CHART_HEIGHT - 8.0,
+        max = max.normalize(),
+        first = &format_time_ms(start)[..10],
+        last = &format_time_ms(end)[..10],
+    )
+    .unwrap();
+}
+
+fn gains_table(html: &mut String, realized: &[&Realized]) {
+    header(
+        html,
+        &[
+            "Acquired", "Disposed", "Quantity", "Proceeds", "Cost", "Gain", "Term", "Tax year",
+        ],
+    );
+    for r in realized {
+        row(
+            html,
+            "",
+            &[
+                (
+                    false,
+                    r.acquired_time.map_or("pooled".to_owned(), format_time_ms),
+                ),
+                (false, format_time_ms(r.disposed_time)),
+                (true, r.quantity.normalize().to_string()),
+                (true, money(r.proceeds)),
+                (true, money(r.cost)),
+                (true, money(r.gain())),
+                (false, if r.long_term { "Long" } else { "Short" }.to_owned()),
+                (false, r.tax_year.to_string()),
+            ],
+        );
+    }
+    let total = |get: fn(&Realized) -> Decimal| money(realized.iter().map(|r| get(r)).sum());
+    row(
+        html,
+        "total",
+        &[
+            (false, "Total".to_owned()),
+            (false, String::new()),
+            (false, String::new()),
+            (true, total(|r| r.proceeds)),
+            (true, total(|r| r.cost)),
+            (true, total(|r| r.gain())),
+            (false, String::new()),
+            (false, String::new()),
+        ],
+    );
+    html.push_str("</table>\n");
+}
+
+fn lots_table(html: &mut String, disposals: &[&LotDisposal]) {
+    header(
+        html,
+        &[
+            "Acquired", "Record", "Disposed", "Record", "Quantity", "Cost", "Fee", "Proceeds",
+            "Gain",
+        ],
+    );
+    for d in disposals {
+        row(
+            html,
+            "",
+            &[
+                (false, format_time_ms(d.acquired_time)),
+                (true, d.acquired_rec_idx.to_string()),
+                (false, format_time_ms(d.disposed_time)),
+                (true, d.rec_idx.to_string()),
+                (true, d.quantity.normalize().to_string()),
+                (true, money(d.cost)),
+                (true, money(d.fee)),
+                (true, money(d.proceeds)),
+                (true, money(d.gain())),
+            ],
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+fn transactions_table(html: &mut String, asset: &str, recs: &[(usize, &TokenTaxRec)]) {
+    header(
+        html,
+        &[
+            "Record",
+            "Date",
+            "Type",
+            "Change",
+            "Other leg",
+            "Fee",
+            "Exchange",
+            "Comment",
+        ],
+    );
+    for (rec_idx, rec) in recs {
+        let leg = |amount: Option<Decimal>, currency: &str| match amount {
+            Some(amount) if !currency.is_empty() => {
+                escape(&format!("{} {currency}", amount.normalize()))
+            }
+            _ => String::new(),
+        };
+        let (change, other) = if rec.buy_currency == asset {
+            let mut change = rec.buy_amount.unwrap_or_default();
+            if rec.fee_currency == asset {
+                change -= rec.fee_amount.unwrap_or_default();
+            }
+            (change, leg(rec.sell_amount, &rec.sell_currency))
+        } else if rec.sell_currency == asset {
+            let mut change = -rec.sell_amount.unwrap_or_default();
+            if rec.fee_currency == asset {
+                change -= rec.fee_amount.unwrap_or_default();
+            }
+            (change, leg(rec.buy_amount, &rec.buy_currency))
+        } else {
+            (-rec.fee_amount.unwrap_or_default(), String::new())
+        };
+        row(
+            html,
+            "",
+            &[
+                (true, rec_idx.to_string()),
+                (false, format_time_ms(rec.time)),
+                (false, rec.type_txs.to_string()),
+                (true, change.normalize().to_string()),
+                (false, other),
+                (false, leg(rec.fee_amount, &rec.fee_currency)),
+                (false, escape(&rec.exchange)),
+                (false, escape(&rec.comment)),
+            ],
+        );
+    }
+    html.push_str("</table>\n");
+}
+
+/// Write the report of `recs` titled `title`, gains use the rules of
+/// `jurisdiction` with values from `oracle`
+pub fn write_html<J, O, W>(
+    recs: &[TokenTaxRec],
+    jurisdiction: &J,
+    oracle: &O,
+    title: &str,
+    mut wtr: W,
+) -> Result<(), Error>
+where
+    J: Jurisdiction + ?Sized,
+    O: PriceOracle + ?Sized,
+    W: Write,
+{
+    let home = jurisdiction.home_currency();
+    let report = realized_gains(recs, jurisdiction, oracle)?;
+    let disposals = match jurisdiction.cost_basis() {
+        CostBasis::Lots(method) => {
+            Some(LotEngine::new(method).process(&tax_events(recs, jurisdiction, oracle)?)?)
+        }
+        CostBasis::AverageCost | CostBasis::SharePooling => None,
+    };
+    let holdings = holdings_over_time(recs);
+    let assets: BTreeSet<&str> = recs
+        .iter()
+        .flat_map(|r| [&r.buy_currency, &r.sell_currency, &r.fee_currency])
+        .map(|c| c.as_str())
+        .filter(|c| !c.is_empty() && *c != home)
+        .collect();
+
+    let title = escape(title);
+    let mut html = String::new();
+    write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <nav><a href=\"#overview\">Overview</a>"
+    )
+    .unwrap();
+    for (idx, asset) in assets.iter().enumerate() {
+        write!(
+            html,
+            r##"<a href="#{}">{}</a>"##,
+            page_id(idx),
+            escape(asset)
+        )
+        .unwrap();
+    }
+    html.push_str("</nav>\n");
+
+    // Asset pages come first so a targeted one can hide the overview
+    for (idx, asset) in assets.iter().enumerate() {
+        let name = escape(asset);
+        write!(html, "<section id=\"{}\">\n<h1>{name}</h1>\n", page_id(idx)).unwrap();
+        if let Some(points) = holdings.get(*asset) {
+            html.push_str("<h2>Holdings</h2>\n");
+            chart(&mut html, &name, points);
+        }
+
+        html.push_str("<h2>Transactions</h2>\n");
+        let transactions: Vec<(usize, &TokenTaxRec)> = recs
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| touches(r, asset))
+            .collect();
+        transactions_table(&mut html, asset, &transactions);
+
+        if let Some(disposals) = &disposals {
+            html.push_str("<h2>Lot consumption</h2>\n");
+            let disposals: Vec<&LotDisposal> =
+                disposals.iter().filter(|d| d.asset == *asset).collect();
+            lots_table(&mut html, &disposals);
+        }
+
+        writeln!(html, "<h2>Realized gains ({})</h2>", escape(home)).unwrap();
+        let realized: Vec<&Realized> = report
+            .realized
+            .iter()
+            .filter(|r| r.asset == *asset)
+            .collect();
+        gains_table(&mut html, &realized);
+        html.push_str("</section>\n");
+    }
+
+    write!(
+        html,
+        "<section id=\"overview\">\n<h1>{title}</h1>\n<p>{} records, gains under {} rules in {}</p>\n\
+         <h2>Gains by tax year</h2>\n",
+        recs.len(),
+        escape(jurisdiction.name()),
+        escape(home),
+    )
+    .unwrap();
+    header(
+        &mut html,
+        &[
+            "Tax year",
+            "Proceeds",
+            "Cost",
+            "Short term gain",
+            "Long term gain",
+            "Gain",
+        ],
+    );
+    let mut totals = [Decimal::ZERO; 5];
+    for (year, gains) in &report.years {
+        let values = [
+            gains.proceeds,
+            gains.cost,
+            gains.short_term_gain,
+            gains.long_term_gain,
+            gains.short_term_gain + gains.long_term_gain,
+        ];
+        let mut cells = vec![(false, year.to_string())];
+        for (total, value) in totals.iter_mut().zip(values) {
+            *total += value;
+            cells.push((true, money(value)));
+        }
+        row(&mut html, "", &cells);
+    }
+    let mut cells = vec![(false, "Total".to_owned())];
+    cells.extend(totals.iter().map(|t| (true, money(*t))));
+    row(&mut html, "total", &cells);
+    html.push_str("</table>\n<h2>Assets</h2>\n");
+
+    header(&mut html, &["Asset", "Records", "Held", "Realized gain"]);
+    let mut gains: BTreeMap<&str, Decimal> = BTreeMap::new();
+    for r in &report.realized {
+        *gains.entry(r.asset.as_str()).or_default() += r.gain();
+    }
+    for (idx, asset) in assets.iter().enumerate() {
+        let count = recs.iter().filter(|r| touches(r, asset)).count();
+        let held = holdings
+            .get(*asset)
+            .and_then(|points| points.last())
+            .map_or(Decimal::ZERO, |p| p.1);
+        row(
+            &mut html,
+            "",
+            &[
+                (
+                    false,
+                    format!(r##"<a href="#{}">{}</a>"##, page_id(idx), escape(asset)),
+                ),
+                (true, count.to_string()),
+                (true, held.normalize().to_string()),
+                (true, money(gains.get(asset).copied().unwrap_or_default())),
+            ],
+        );
+    }
+    html.push_str("</table>\n</section>\n</body>\n</html>\n");
+
+    wtr.write_all(html.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jurisdiction::{jurisdiction, Canada};
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    const CSV: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,2,ETH,2000,USD,,,kraken,,,2020-01-01 00:00:00
+Trade,1,ETH,1000,USD,,,kraken,,,2021-01-01 00:00:00
+Trade,3000,USD,1,ETH,,,kraken,,,2021-06-01 00:00:00
+Trade,0.1,BTC,1,ETH,,,kraken,,<swap>,2022-08-01 00:00:00
+";
+
+    fn prices() -> PriceTable {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(1500));
+        pt
+    }
+
+    fn report<J: Jurisdiction + ?Sized>(jurisdiction: &J) -> String {
+        let mut buf = Vec::new();
+        write_html(
+            &recs_from_csv(CSV),
+            jurisdiction,
+            &prices(),
+            "Taxes & more",
+            &mut buf,
+        )
+        .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_write_html() {
+        let us = jurisdiction("US").unwrap();
+        let html = report(us.as_ref());
+        assert!(html.contains("<title>Taxes &amp; more</title>"));
+        assert!(html.contains(
+            r##"<nav><a href="#overview">Overview</a><a href="#asset-0">BTC</a><a href="#asset-1">ETH</a></nav>"##
+        ));
+        assert!(html.contains(
+            r#"<tr><td>2022</td><td class="n">1500</td><td class="n">1000</td><td class="n">0</td><td class="n">500</td><td class="n">500</td></tr>"#
+        ));
+        assert!(html.contains(r#"<tr class="total"><td>Total</td><td class="n">4500</td>"#));
+        assert!(html.contains("<td>&lt;swap&gt;</td>"));
+        assert!(html.contains(r#"aria-label="ETH held over time""#));
+        assert_eq!(html.matches("<h2>Lot consumption</h2>").count(), 2);
+
+        // ETH held 2, 3, 2 then 1
+        assert!(html.contains(
+            r##"<tr><td><a href="#asset-1">ETH</a></td><td class="n">4</td><td class="n">1</td><td class="n">2500</td></tr>"##
+        ));
+
+        // Pooled cost has no lots to show
+        let mut ca = Canada::new();
+        ca.home_currency = "USD".to_owned();
+        let html = report(&ca);
+        assert!(!html.contains("Lot consumption"));
+        assert!(html.contains("<td>pooled</td>"));
+    }
+}
@@ -0,0 +1,1412 @@
+//! Plain text accounting journals, Beancount and hledger or ledger-cli.
+//!
+//! Each record is a transaction moving amounts between the account of its
+//! exchange and an income, expense or transfer account. In Beancount
+//! acquisitions are held at cost, `{basis}` in the home currency, and
+//! disposals reduce lots at `{}` with the gain going to the gains account,
+//! so prices are needed for anything not traded against the home currency.
+//! hledger and ledger-cli journals keep amounts in their commodities with
+//! trades priced by `@@`.
+//!
+//! Beancount currencies are upper case letters, digits and `'._-`, starting
+//! with a letter, so currencies such as `stETH` or `1INCH` must be given a
+//! Beancount name in `Journal::commodities`. hledger commodities other than
+//! letters are quoted, e.g. `"1INCH"`.
+//!
+//! Journals in a subset of either syntax are read back into records, see
+//! `Journal::read`.
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+
+use chrono::{NaiveDate, NaiveTime};
+use rust_decimal::prelude::*;
+
+use crate::audit::{AuditKind, AuditStep, Source};
+use crate::builder::RecBuilder;
+use crate::error::Error;
+use crate::fx::Amount;
+use crate::price::{value_of, PriceOracle};
+use crate::{GroupType, TokenTaxRec, TokenTaxRecType};
+
+/// Names of the accounts postings are made to
+#[derive(Clone, Debug)]
+pub struct Accounts {
+    /// Parent of the exchange accounts not in `exchanges`
+    pub assets: String,
+
+    /// Account of an exchange, by the records' `exchange`
+    pub exchanges: HashMap<String, String>,
+    pub income: String,
+    pub mining: String,
+    pub gains: String,
+    pub fees: String,
+    pub spend: String,
+    pub gifts: String,
+
+    /// Lost and stolen amounts
+    pub losses: String,
+
+    /// Counterpart of deposits and withdrawals
+    pub transfers: String,
+}
+
+impl Default for Accounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Accounts {
+    pub fn new() -> Accounts {
+        Accounts {
+            assets: "Assets:Crypto".to_owned(),
+            exchanges: HashMap::new(),
+            income: "Income:Crypto:Income".to_owned(),
+            mining: "Income:Crypto:Mining".to_owned(),
+            gains: "Income:Crypto:Gains".to_owned(),
+            fees: "Expenses:Crypto:Fees".to_owned(),
+            spend: "Expenses:Crypto:Spend".to_owned(),
+            gifts: "Expenses:Crypto:Gifts".to_owned(),
+            losses: "Expenses:Crypto:Losses".to_owned(),
+            transfers: "Equity:Crypto:Transfers".to_owned(),
+        }
+    }
+
+    /// Account of `exchange`, from `exchanges` or named after it under
+    /// `assets`, e.g. `binance.us` is `Assets:Crypto:BinanceUs`
+    pub fn exchange(&self, exchange: &str) -> String {
+        if let Some(account) = self.exchanges.get(exchange) {
+            return account.clone();
+        }
+        let name: String = exchange
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let mut chars = part.chars();
+                let first = chars.next().expect("SNH").to_ascii_uppercase();
+                std::iter::once(first).chain(chars).collect::<String>()
+            })
+            .collect();
+        if name.is_empty() {
+            format!("{}:Wallet", self.assets)
+        } else {
+            format!("{}:{name}", self.assets)
+        }
+    }
+
+    /// The income or expense account of a record's type
+    fn counterpart(&self, type_txs: &TokenTaxRecType) -> &str {
+        match type_txs {
+            TokenTaxRecType::Income => &self.income,
+            TokenTaxRecType::Mining => &self.mining,
+            TokenTaxRecType::Spend => &self.spend,
+            TokenTaxRecType::Gift => &self.gifts,
+            TokenTaxRecType::Lost | TokenTaxRecType::Stolen => &self.losses,
+            _ => &self.transfers,
+        }
+    }
+}
+
+/// A posting's account and amount, an empty amount is filled in by the
+/// journal's balancing
+struct Posting {
+    account: String,
+    amount: String,
+}
+
+fn posting(account: &str, amount: String) -> Posting {
+    Posting {
+        account: account.to_owned(),
+        amount,
+    }
+}
+
+fn units(amount: Decimal, currency: &str) -> String {
+    format!("{} {currency}", amount.normalize())
+}
+
+/// `{unit} HOME` when the unit price of `quantity` is exact, otherwise the
+/// total with the doubled delimiters, e.g. `{{total}}` or `@@`
+fn per_unit(
+    rec_idx: usize,
+    quantity: Decimal,
+    total: Decimal,
+    home: &str,
+    single: &str,
+    double: &str,
+) -> Result<String, Error> {
+    if quantity.is_zero() {
+        return Err(Error::InvalidRecord {
+            msg: format!("Record {rec_idx}: a zero amount has no unit cost"),
+        });
+    }
+    let unit = total / quantity;
+    Ok(if unit * quantity == total {
+        format!("{single}{}", units(unit, home))
+    } else {
+        format!("{double}{}", units(total, home))
+    })
+}
+
+/// True if `currency` is a valid Beancount currency
+fn is_beancount_currency(currency: &str) -> bool {
+    let chars: Vec<char> = currency.chars().collect();
+    let inner = |c: &char| c.is_ascii_uppercase() || c.is_ascii_digit() || "'._-".contains(*c);
+    match chars.as_slice() {
+        [first, middle @ .., last] => {
+            first.is_ascii_uppercase()
+                && middle.iter().all(inner)
+                && (last.is_ascii_uppercase() || last.is_ascii_digit())
+                && chars.len() <= 24
+        }
+        [only] => only.is_ascii_uppercase(),
+        [] => false,
+    }
+}
+
+/// An hledger commodity, quoted unless it's only letters
+fn ledger_commodity(rec_idx: usize, currency: &str) -> Result<String, Error> {
+    if currency.chars().all(char::is_alphabetic) {
+        return Ok(currency.to_owned());
+    }
+    if currency.contains(['"', '\n', '\r']) {
+        return Err(Error::InvalidRecord {
+            msg: format!("Record {rec_idx}: currency {currency:?} can't be quoted"),
+        });
+    }
+    Ok(format!("\"{currency}\""))
+}
+
+/// Escape a Beancount string
+fn quoted(s: &str) -> String {
+    let s = s.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", s.replace(['\n', '\r'], " "))
+}
+
+fn narration(rec: &TokenTaxRec) -> String {
+    if rec.comment.is_empty() {
+        rec.type_txs.to_string()
+    } else {
+        rec.comment.replace(['\n', '\r'], " ")
+    }
+}
+
+/// Writer of records as journal transactions
+#[derive(Clone, Debug)]
+pub struct Journal {
+    pub home_currency: String,
+    pub accounts: Accounts,
+
+    /// Beancount names of currencies that aren't valid Beancount
+    /// currencies, e.g. `stETH` as `STETH`
+    pub commodities: HashMap<String, String>,
+}
+
+impl Journal {
+    pub fn new(home_currency: &str) -> Journal {
+        Journal {
+            home_currency: home_currency.to_owned(),
+            accounts: Accounts::new(),
+            commodities: HashMap::new(),
+        }
+    }
+
+    /// The Beancount name of `currency`
+    fn beancount_currency(&self, rec_idx: usize, currency: &str) -> Result<String, Error> {
+        let name = self
+            .commodities
+            .get(currency)
+            .map_or(currency, |c| c.as_str());
+        if !is_beancount_currency(name) {
+            return Err(Error::InvalidRecord {
+                msg: format!(
+                    "Record {rec_idx}: {name} isn't a Beancount currency, name it in commodities"
+                ),
+            });
+        }
+        Ok(name.to_owned())
+    }
+
+    /// The currency whose Beancount name is `name`
+    fn original_currency<'a>(&'a self, name: &'a str) -> &'a str {
+        self.commodities
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map_or(name, |(currency, _)| currency.as_str())
+    }
+
+    /// `amount` with its currency by its Beancount name
+    fn beancount_amount(&self, rec_idx: usize, amount: Amount) -> Result<Amount, Error> {
+        let currency = self.beancount_currency(rec_idx, &amount.currency)?;
+        Ok(Amount::new(amount.amount, &currency))
+    }
+
+    /// The legs of `rec` its type uses
+    fn legs(rec: &TokenTaxRec, rec_idx: usize) -> Result<(Option<Amount>, Option<Amount>), Error> {
+        let missing = |field| Error::MissingField { rec_idx, field };
+        let buy = || rec.buy_leg().ok_or(missing("buy_amount"));
+        let sell = || rec.sell_leg().ok_or(missing("sell_amount"));
+        match rec.type_txs {
+            TokenTaxRecType::Trade => Ok((Some(buy()?), Some(sell()?))),
+            TokenTaxRecType::Deposit | TokenTaxRecType::Income | TokenTaxRecType::Mining => {
+                Ok((Some(buy()?), None))
+            }
+            TokenTaxRecType::Withdrawal
+            | TokenTaxRecType::Spend
+            | TokenTaxRecType::Gift
+            | TokenTaxRecType::Lost
+            | TokenTaxRecType::Stolen => Ok((None, Some(sell()?))),
+            TokenTaxRecType::Unknown => Err(Error::UnsupportedRecord { rec_idx }),
+        }
+    }
+
+    fn beancount_postings<O: PriceOracle + ?Sized>(
+        &self,
+        rec: &TokenTaxRec,
+        rec_idx: usize,
+        oracle: &O,
+    ) -> Result<Vec<Posting>, Error> {
+        let home = self.beancount_currency(rec_idx, &self.home_currency)?;
+        let home = home.as_str();
+        let value = |a: &Amount| {
+            let currency = self.original_currency(&a.currency);
+            value_of(oracle, a.amount, currency, &self.home_currency, rec.time)
+        };
+        let acquire = |a: &Amount, value: Decimal| -> Result<String, Error> {
+            if a.currency == home {
+                return Ok(units(a.amount, home));
+            }
+            let cost = per_unit(rec_idx, a.amount, value, home, "{", "{{")?;
+            let close = if cost.starts_with("{{") { "}}" } else { "}" };
+            Ok(format!("{} {cost}{close}", units(a.amount, &a.currency)))
+        };
+        let dispose = |a: &Amount, value: Option<Decimal>| -> Result<String, Error> {
+            if a.currency == home {
+                return Ok(units(-a.amount, home));
+            }
+            let price = match value {
+                Some(v) => format!(" {}", per_unit(rec_idx, a.amount, v, home, "@ ", "@@ ")?),
+                None => String::new(),
+            };
+            Ok(format!("{} {{}}{price}", units(-a.amount, &a.currency)))
+        };
+
+        let account = self.accounts.exchange(&rec.exchange);
+        let counterpart = self.accounts.counterpart(&rec.type_txs);
+        let (buy, sell) = Self::legs(rec, rec_idx)?;
+        let buy = buy.map(|a| self.beancount_amount(rec_idx, a)).transpose()?;
+        let sell = sell
+            .map(|a| self.beancount_amount(rec_idx, a))
+            .transpose()?;
+        let mut postings = Vec::new();
+        let mut auto = None;
+        match (buy, sell) {
+            (Some(buy), Some(sell)) => {
+                // The executed price when either side is the home currency
+                let v = if sell.currency == home {
+                    sell.amount
+                } else if buy.currency == home {
+                    buy.amount
+                } else {
+                    value(&sell).or_else(|_| value(&buy))?
+                };
+                postings.push(posting(&account, acquire(&buy, v)?));
+                postings.push(posting(&account, dispose(&sell, Some(v))?));
+            }
+            (Some(buy), None) => {
+                let v = value(&buy)?;
+                postings.push(posting(&account, acquire(&buy, v)?));
+                postings.push(posting(counterpart, units(-v, home)));
+            }
+            (None, Some(sell)) if rec.type_txs == TokenTaxRecType::Withdrawal => {
+                postings.push(posting(&account, dispose(&sell, None)?));
+                if sell.currency == home {
+                    postings.push(posting(counterpart, units(sell.amount, home)));
+                } else {
+                    // The cost of what's withdrawn isn't known until booked
+                    auto = Some(counterpart.to_owned());
+                }
+            }
+            (None, Some(sell)) => {
+                let v = value(&sell)?;
+                postings.push(posting(&account, dispose(&sell, Some(v))?));
+                postings.push(posting(counterpart, units(v, home)));
+            }
+            (None, None) => panic!("SNH"),
+        }
+        if let Some(fee) = rec.fee_leg().filter(|f| !f.amount.is_zero()) {
+            let fee = self.beancount_amount(rec_idx, fee)?;
+            let v = value(&fee)?;
+            postings.push(posting(&account, dispose(&fee, None)?));
+            postings.push(posting(&self.accounts.fees, units(v, home)));
+        }
+        let reduces = postings.iter().any(|p| p.amount.contains("{}"));
+        match auto {
+            Some(account) => postings.push(posting(&account, String::new())),
+            None if reduces => postings.push(posting(&self.accounts.gains, String::new())),
+            None => (),
+        }
+        Ok(postings)
+    }
+
+    /// Write `recs` as a Beancount journal, with `oracle` valuing in the
+    /// home currency whatever isn't traded for it. Lots are booked FIFO.
+    pub fn write_beancount<O, W>(
+        &self,
+        recs: &[TokenTaxRec],
+        oracle: &O,
+        mut wtr: W,
+    ) -> Result<(), Error>
+    where
+        O: PriceOracle + ?Sized,
+        W: Write,
+    {
+        let mut txns = Vec::new();
+        let mut accounts = BTreeSet::new();
+        for (rec_idx, rec) in recs.iter().enumerate() {
+            let postings = self.beancount_postings(rec, rec_idx, oracle)?;
+            accounts.extend(postings.iter().map(|p| p.account.clone()));
+            txns.push((rec, postings));
+        }
+
+        writeln!(
+            wtr,
+            "option \"operating_currency\" {}",
+            quoted(&self.beancount_currency(0, &self.home_currency)?)
+        )?;
+        writeln!(wtr, "option \"booking_method\" \"FIFO\"")?;
+        if let Some(first) = recs.iter().map(|r| r.time).min() {
+            let date = date(first);
+            writeln!(wtr)?;
+            for account in accounts.iter() {
+                writeln!(wtr, "{} open {account}", date.format("%Y-%m-%d"))?;
+            }
+        }
+        for (rec, postings) in txns {
+            let date = date(rec.time);
+            let margin = match rec.group {
+                Some(GroupType::Margin) => " #margin",
+                None => "",
+            };
+            writeln!(wtr)?;
+            writeln!(
+                wtr,
+                "{} * {} {}{margin}",
+                date.format("%Y-%m-%d"),
+                quoted(&rec.exchange),
+                quoted(&narration(rec))
+            )?;
+            writeln!(wtr, "  time: \"{}\"", date.format("%H:%M:%S%.3f"))?;
+            writeln!(wtr, "  type: {}", quoted(&rec.type_txs.to_string()))?;
+            write_postings(&mut wtr, &postings)?;
+        }
+        Ok(())
+    }
+
+    fn ledger_postings(&self, rec: &TokenTaxRec, rec_idx: usize) -> Result<Vec<Posting>, Error> {
+        let account = self.accounts.exchange(&rec.exchange);
+        let counterpart = self.accounts.counterpart(&rec.type_txs);
+        let quote = |a: Option<Amount>| -> Result<Option<Amount>, Error> {
+            a.map(|a| ledger_commodity(rec_idx, &a.currency).map(|c| Amount::new(a.amount, &c)))
+                .transpose()
+        };
+        let (buy, sell) = Self::legs(rec, rec_idx)?;
+        let mut postings = Vec::new();
+        match (quote(buy)?, quote(sell)?) {
+            (Some(buy), Some(sell)) => {
+                postings.push(posting(
+                    &account,
+                    format!(
+                        "{} @@ {}",
+                        units(buy.amount, &buy.currency),
+                        units(sell.amount, &sell.currency)
+                    ),
+                ));
+                postings.push(posting(&account, units(-sell.amount, &sell.currency)));
+            }
+            (Some(buy), None) => {
+                postings.push(posting(&account, units(buy.amount, &buy.currency)));
+                postings.push(posting(counterpart, units(-buy.amount, &buy.currency)));
+            }
+            (None, Some(sell)) => {
+                postings.push(posting(&account, units(-sell.amount, &sell.currency)));
+                postings.push(posting(counterpart, units(sell.amount, &sell.currency)));
+            }
+            (None, None) => panic!("SNH"),
+        }
+        if let Some(fee) = quote(rec.fee_leg().filter(|f| !f.amount.is_zero()))? {
+            postings.push(posting(&account, units(-fee.amount, &fee.currency)));
+            postings.push(posting(
+                &self.accounts.fees,
+                units(fee.amount, &fee.currency),
+            ));
+        }
+        Ok(postings)
+    }
+
+    /// Write `recs` as an hledger or ledger-cli journal
+    pub fn write_ledger<W: Write>(&self, recs: &[TokenTaxRec], mut wtr: W) -> Result<(), Error> {
+        for (rec_idx, rec) in recs.iter().enumerate() {
+            let postings = self.ledger_postings(rec, rec_idx)?;
+            let date = date(rec.time);
+            if rec_idx > 0 {
+                writeln!(wtr)?;
+            }
+            writeln!(wtr, "{} * {}", date.format("%Y-%m-%d"), narration(rec))?;
+            writeln!(wtr, "  ; time: {}", date.format("%H:%M:%S%.3f"))?;
+            writeln!(wtr, "  ; type: {}", rec.type_txs)?;
+            if !rec.exchange.is_empty() {
+                writeln!(wtr, "  ; exchange: {}", rec.exchange)?;
+            }
+            if rec.group == Some(GroupType::Margin) {
+                writeln!(wtr, "  ; :margin:")?;
+            }
+            write_postings(&mut wtr, &postings)?;
+        }
+        Ok(())
+    }
+}
+
+fn date(time: i64) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::from_timestamp_millis(time).expect("SNH")
+}
+
+fn write_postings<W: Write>(wtr: &mut W, postings: &[Posting]) -> Result<(), Error> {
+    for p in postings {
+        if p.amount.is_empty() {
+            writeln!(wtr, "  {}", p.account)?;
+        } else {
+            writeln!(wtr, "  {}  {}", p.account, p.amount)?;
+        }
+    }
+    Ok(())
+}
+
+/// A posting read from a journal
+struct Entry {
+    account: String,
+
+    /// None if elided, to be filled in by balancing
+    amount: Option<Amount>,
+
+    /// What the posting weighs when balancing, None if its cost is booked
+    weight: Option<Amount>,
+
+    /// Reduces a lot at cost with no price, as Beancount fees are written
+    at_cost: bool,
+}
+
+/// A transaction read from a journal
+struct Txn {
+    line: usize,
+    date: NaiveDate,
+    payee: Option<String>,
+    narration: String,
+    margin: bool,
+    meta: HashMap<String, String>,
+    entries: Vec<Entry>,
+}
+
+/// An account with its net amount per commodity and fee
+type Net = (String, Vec<(String, Decimal)>, Option<Amount>);
+
+fn parse_error(line: usize, msg: String) -> Error {
+    Error::Parse { line, msg }
+}
+
+/// A quoted commodity at the start of `s`, e.g. `"1INCH"`, and the rest
+fn quoted_commodity(s: &str) -> Option<(&str, &str)> {
+    let (commodity, rest) = s.strip_prefix('"')?.split_once('"')?;
+    Some((commodity, rest))
+}
+
+/// Parse an amount such as `-1,000.5 ETH`, `$-100` or `1 "1INCH"`
+/// returning the rest
+fn parse_units(s: &str) -> Option<(Amount, &str)> {
+    let s = s.trim_start();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, s),
+    };
+    let is_number = |c: char| c.is_ascii_digit() || matches!(c, '.' | ',' | '-');
+    let (currency, number, rest) = match s.chars().next()? {
+        '"' => {
+            let (currency, rest) = quoted_commodity(s)?;
+            let rest = rest.trim_start();
+            let end = rest.find(|c| !is_number(c)).unwrap_or(rest.len());
+            (currency, &rest[..end], &rest[end..])
+        }
+        symbol @ ('$' | '€' | '£') => {
+            let rest = &s[symbol.len_utf8()..];
+            let end = rest.find(|c| !is_number(c)).unwrap_or(rest.len());
+            let currency = match symbol {
+                '$' => "USD",
+                '€' => "EUR",
+                _ => "GBP",
+            };
+            (currency, &rest[..end], &rest[end..])
+        }
+        _ => {
+            let end = s.find(|c| !is_number(c)).unwrap_or(s.len());
+            let (number, rest) = s.split_at(end);
+            let rest = rest.trim_start();
+            match quoted_commodity(rest) {
+                Some((currency, rest)) => (currency, number, rest),
+                None => {
+                    let end = rest
+                        .find(|c: char| {
+                            !(c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '\''))
+                        })
+                        .unwrap_or(rest.len());
+                    (&rest[..end], number, &rest[end..])
+                }
+            }
+        }
+    };
+    if currency.is_empty() || number.is_empty() {
+        return None;
+    }
+    let amount = Decimal::from_str(&number.replace(',', "")).ok()?;
+    let amount = if negative { -amount } else { amount };
+    Some((Amount::new(amount, currency), rest))
+}
+
+fn parse_posting(line: usize, text: &str) -> Result<Entry, Error> {
+    let text = text.split(';').next().unwrap_or("").trim();
+    let text = text
+        .strip_prefix("! ")
+        .or_else(|| text.strip_prefix("* "))
+        .unwrap_or(text);
+    let (account, rest) = match text.find("  ").or_else(|| text.find('\t')) {
+        Some(idx) => text.split_at(idx),
+        None => text.split_once(' ').unwrap_or((text, "")),
+    };
+    let mut entry = Entry {
+        account: account.trim().to_owned(),
+        amount: None,
+        weight: None,
+        at_cost: false,
+    };
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok(entry);
+    }
+    let bad = || parse_error(line, format!("Bad amount {rest}"));
+    let (amount, rest) = parse_units(rest).ok_or_else(bad)?;
+    let mut rest = rest.trim();
+
+    // A cost, `{unit}`, `{{total}}` or `{}` when booked, then a price
+    let mut cost = None;
+    if let Some(r) = rest.strip_prefix("{{") {
+        let end = r.find("}}").ok_or_else(bad)?;
+        cost = Some((true, &r[..end]));
+        rest = r[end + 2..].trim();
+    } else if let Some(r) = rest.strip_prefix('{') {
+        let end = r.find('}').ok_or_else(bad)?;
+        cost = Some((false, &r[..end]));
+        rest = r[end + 1..].trim();
+    }
+    let price = if let Some(r) = rest.strip_prefix("@@") {
+        Some((true, r))
+    } else {
+        rest.strip_prefix('@').map(|r| (false, r))
+    };
+
+    let weigh = |(total, s): (bool, &str)| -> Result<Option<Amount>, Error> {
+        let s = s.split(", ").next().unwrap_or("").trim();
+        if s.is_empty() {
+            return Ok(None);
+        }
+        let (per, _) = parse_units(s).ok_or_else(bad)?;
+        Ok(Some(if total {
+            Amount::new(per.amount * amount.amount.signum(), &per.currency)
+        } else {
+            Amount::new(per.amount * amount.amount, &per.currency)
+        }))
+    };
+    entry.weight = match (cost, price) {
+        (Some(cost), _) => weigh(cost)?,
+        (None, Some(price)) => weigh(price)?,
+        (None, None) => Some(amount.clone()),
+    };
+    entry.at_cost = cost.is_some() && price.is_none() && amount.amount < Decimal::ZERO;
+    entry.amount = Some(amount);
+    Ok(entry)
+}
+
+/// Beancount's quoted strings, unescaped, and tags following the flag
+fn parse_strings(mut s: &str, txn: &mut Txn) -> Vec<String> {
+    let mut strings = Vec::new();
+    loop {
+        s = s.trim_start();
+        if let Some(rest) = s.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = rest.char_indices();
+            let mut end = rest.len();
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = idx + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            strings.push(value);
+            s = &rest[end..];
+        } else if s.starts_with('#') || s.starts_with('^') {
+            let end = s.find(char::is_whitespace).unwrap_or(s.len());
+            if &s[..end] == "#margin" {
+                txn.margin = true;
+            }
+            s = &s[end..];
+        } else {
+            return strings;
+        }
+    }
+}
+
+/// `key: value` metadata and `:tag:` tags of a comment
+fn parse_comment(comment: &str, txn: &mut Txn) {
+    let comment = comment.trim();
+    if comment.len() > 1 && comment.starts_with(':') && comment.ends_with(':') {
+        txn.margin |= comment.split(':').any(|tag| tag == "margin");
+    } else if let Some((key, value)) = comment.split_once(':') {
+        if !key.is_empty() && !key.contains(char::is_whitespace) {
+            txn.meta.insert(key.to_lowercase(), value.trim().to_owned());
+        }
+    }
+}
+
+/// The transaction started by a header line, None for other directives
+fn parse_header(line: usize, text: &str) -> Result<Option<Txn>, Error> {
+    let (date, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let date = date.split('=').next().unwrap_or(date);
+    let Some(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y/%m/%d"))
+        .ok()
+    else {
+        return Ok(None);
+    };
+    let rest = rest.trim();
+    let first = rest.split_whitespace().next().unwrap_or("");
+    let rest = match first {
+        "*" | "!" | "txn" => rest[first.len()..].trim_start(),
+        "open" | "close" | "commodity" | "balance" | "pad" | "price" | "note" | "document"
+        | "event" | "query" | "custom" => return Ok(None),
+        _ => rest,
+    };
+    let rest = match rest.strip_prefix('(') {
+        Some(r) => r.split_once(')').map_or(r, |(_, r)| r).trim_start(),
+        None => rest,
+    };
+
+    let mut txn = Txn {
+        line,
+        date,
+        payee: None,
+        narration: String::new(),
+        margin: false,
+        meta: HashMap::new(),
+        entries: Vec::new(),
+    };
+    if rest.starts_with('"') {
+        let mut strings = parse_strings(rest, &mut txn);
+        txn.narration = strings.pop().unwrap_or_default();
+        txn.payee = strings.pop().filter(|p| !p.is_empty());
+    } else {
+        let (description, comment) = rest.split_once(';').unwrap_or((rest, ""));
+        parse_comment(comment, &mut txn);
+        match description.split_once('|') {
+            Some((payee, narration)) => {
+                txn.payee = Some(payee.trim().to_owned()).filter(|p| !p.is_empty());
+                txn.narration = narration.trim().to_owned();
+            }
+            None => txn.narration = description.trim().to_owned(),
+        }
+    }
+    Ok(Some(txn))
+}
+
+fn parse_journal(text: &str) -> Result<Vec<Txn>, Error> {
+    let mut txns = Vec::new();
+    let mut current: Option<Txn> = None;
+    for (idx, raw) in text.lines().enumerate() {
+        let line = idx + 1;
+        if raw.trim().is_empty() {
+            txns.extend(current.take());
+            continue;
+        }
+        if !raw.starts_with([' ', '\t']) {
+            txns.extend(current.take());
+            current = parse_header(line, raw)?;
+            continue;
+        }
+        let Some(txn) = current.as_mut() else {
+            continue;
+        };
+        let text = raw.trim();
+        if let Some(comment) = text.strip_prefix(';') {
+            parse_comment(comment, txn);
+            continue;
+        }
+        // Beancount metadata, `key: value` with a lower case key
+        let first = text.split_whitespace().next().unwrap_or("");
+        if first.ends_with(':')
+            && first.matches(':').count() == 1
+            && first.starts_with(|c: char| c.is_ascii_lowercase())
+        {
+            let value = text[first.len()..].trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            txn.meta
+                .insert(first.trim_end_matches(':').to_owned(), value.to_owned());
+            continue;
+        }
+        txn.entries.push(parse_posting(line, text)?);
+    }
+    txns.extend(current);
+    Ok(txns)
+}
+
+/// A builder for a record of `type_txs` with the legs given, None if the
+/// type doesn't use them
+fn builder_for(
+    type_txs: &TokenTaxRecType,
+    buy: Option<Amount>,
+    sell: Option<Amount>,
+) -> Option<RecBuilder> {
+    Some(match (type_txs, buy, sell) {
+        (TokenTaxRecType::Trade, Some(buy), Some(sell)) => TokenTaxRec::trade(buy, sell),
+        (TokenTaxRecType::Deposit, Some(buy), None) => TokenTaxRec::deposit(buy),
+        (TokenTaxRecType::Income, Some(buy), None) => TokenTaxRec::income(buy),
+        (TokenTaxRecType::Mining, Some(buy), None) => TokenTaxRec::mining(buy),
+        (TokenTaxRecType::Withdrawal, None, Some(sell)) => TokenTaxRec::withdrawal(sell),
+        (TokenTaxRecType::Spend, None, Some(sell)) => TokenTaxRec::spend(sell),
+        (TokenTaxRecType::Gift, None, Some(sell)) => TokenTaxRec::gift(sell),
+        (TokenTaxRecType::Lost, None, Some(sell)) => TokenTaxRec::lost(sell),
+        (TokenTaxRecType::Stolen, None, Some(sell)) => TokenTaxRec::stolen(sell),
+        _ => return None,
+    })
+}
+
+/// Root of an account, e.g. `income` of `Income:Staking`, lower cased
+fn root(account: &str) -> String {
+    account.split(':').next().unwrap_or("").to_lowercase()
+}
+
+impl Accounts {
+    fn is_tracked(&self, account: &str) -> bool {
+        account == self.assets
+            || account
+                .strip_prefix(self.assets.as_str())
+                .is_some_and(|rest| rest.starts_with(':'))
+            || self.exchanges.values().any(|a| a == account)
+    }
+
+    /// The fees account or one named `Fee` or `Fees`, e.g. `Expenses:Fees`
+    fn is_fee(&self, account: &str) -> bool {
+        account == self.fees
+            || account.rsplit(':').next().is_some_and(|last| {
+                last.eq_ignore_ascii_case("fee") || last.eq_ignore_ascii_case("fees")
+            })
+    }
+
+    /// The type of a record moving an amount in from `counterparts`
+    fn type_in<'a, I: Iterator<Item = &'a str>>(&self, counterparts: I) -> TokenTaxRecType {
+        for account in counterparts {
+            let last = account.rsplit(':').next().unwrap_or("");
+            if account == self.mining || last.eq_ignore_ascii_case("mining") {
+                return TokenTaxRecType::Mining;
+            }
+            if account == self.income || (root(account) == "income" && account != self.gains) {
+                return TokenTaxRecType::Income;
+            }
+        }
+        TokenTaxRecType::Deposit
+    }
+
+    /// The type of a record moving an amount out to `counterparts`
+    fn type_out<'a, I: Iterator<Item = &'a str>>(&self, counterparts: I) -> TokenTaxRecType {
+        for account in counterparts {
+            if account == self.gifts {
+                return TokenTaxRecType::Gift;
+            }
+            if account == self.losses {
+                return TokenTaxRecType::Lost;
+            }
+            if account == self.spend || root(account) == "expenses" {
+                return TokenTaxRecType::Spend;
+            }
+        }
+        TokenTaxRecType::Withdrawal
+    }
+
+    /// The exchange of `account`, by `exchanges` or its last component
+    fn exchange_of(&self, account: &str) -> String {
+        match self.exchanges.iter().find(|(_, a)| *a == account) {
+            Some((exchange, _)) => exchange.clone(),
+            None => account.rsplit(':').next().unwrap_or("").to_lowercase(),
+        }
+    }
+}
+
+impl Journal {
+    /// Read the transactions of a Beancount or hledger journal as records.
+    ///
+    /// Postings to accounts under `accounts.assets`, or in
+    /// `accounts.exchanges`, are netted per account and commodity. One
+    /// commodity in and one out of an account is a `Trade`, an amount in
+    /// is `Income` or `Mining` from an income account and otherwise a
+    /// `Deposit`, an amount out is a `Spend`, `Gift` or `Lost` to an
+    /// expense account and otherwise a `Withdrawal`. Postings to the fees
+    /// account are the fee. A transfer between our accounts is a
+    /// withdrawal and deposit. The `time`, `type` and `exchange` metadata
+    /// and `margin` tag written by the exporters are honoured and
+    /// currencies named in `commodities` are read as the currency.
+    pub fn read(&self, rdr: &mut dyn Read, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let mut text = String::new();
+        rdr.read_to_string(&mut text)?;
+        let mut recs = Vec::new();
+        for mut txn in parse_journal(&text)? {
+            // Beancount names back to the currencies they stand for
+            for entry in txn.entries.iter_mut() {
+                for amount in [&mut entry.amount, &mut entry.weight].into_iter().flatten() {
+                    let currency = self.original_currency(&amount.currency).to_owned();
+                    amount.currency = currency;
+                }
+            }
+            recs.extend(self.txn_records(txn, source)?);
+        }
+        Ok(recs)
+    }
+
+    fn txn_records(&self, mut txn: Txn, source: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        let accounts = &self.accounts;
+        let line = txn.line;
+
+        // Fill in an elided posting to one of our accounts
+        let elided: Vec<usize> = (0..txn.entries.len())
+            .filter(|&idx| txn.entries[idx].amount.is_none())
+            .collect();
+        if elided.len() > 1 {
+            return Err(parse_error(
+                line,
+                "More than one posting without an amount".to_owned(),
+            ));
+        }
+        if let Some(&idx) = elided.first() {
+            if accounts.is_tracked(&txn.entries[idx].account) {
+                let mut residual: Vec<(String, Decimal)> = Vec::new();
+                for entry in txn.entries.iter().filter(|e| e.amount.is_some()) {
+                    let weight = entry.weight.as_ref().ok_or_else(|| {
+                        parse_error(line, "Can't balance a posting booked at cost".to_owned())
+                    })?;
+                    match residual.iter_mut().find(|(c, _)| *c == weight.currency) {
+                        Some((_, total)) => *total += weight.amount,
+                        None => residual.push((weight.currency.clone(), weight.amount)),
+                    }
+                }
+                residual.retain(|(_, total)| !total.is_zero());
+                let [(currency, total)] = residual.as_slice() else {
+                    return Err(parse_error(
+                        line,
+                        "Can't balance the transaction".to_owned(),
+                    ));
+                };
+                txn.entries[idx].amount = Some(Amount::new(-*total, currency));
+            }
+        }
+
+        // Net amounts of each of our accounts, in order of first posting
+        let mut nets: Vec<Net> = Vec::new();
+        for entry in txn.entries.iter() {
+            let Some(amount) = &entry.amount else {
+                continue;
+            };
+            if !accounts.is_tracked(&entry.account) {
+                continue;
+            }
+            let idx = match nets.iter().position(|(a, _, _)| *a == entry.account) {
+                Some(idx) => idx,
+                None => {
+                    nets.push((entry.account.clone(), Vec::new(), None));
+                    nets.len() - 1
+                }
+            };
+            let net = &mut nets[idx].1;
+            match net.iter_mut().find(|(c, _)| *c == amount.currency) {
+                Some((_, total)) => *total += amount.amount,
+                None => net.push((amount.currency.clone(), amount.amount)),
+            }
+        }
+        if nets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // A fee in a commodity moved out of one of our accounts is taken
+        // from it, one valued in another is the last posting at cost
+        let mut at_cost: Vec<&Entry> = txn
+            .entries
+            .iter()
+            .filter(|e| e.at_cost && accounts.is_tracked(&e.account))
+            .collect();
+        let fee_entries = txn
+            .entries
+            .iter()
+            .filter(|e| !accounts.is_tracked(&e.account) && accounts.is_fee(&e.account));
+        for entry in fee_entries {
+            let Some(paid) = &entry.amount else {
+                continue;
+            };
+            let (account, fee) = if let Some(reduce) = at_cost.pop() {
+                let amount = reduce.amount.as_ref().expect("SNH");
+                (
+                    reduce.account.clone(),
+                    Amount::new(-amount.amount, &amount.currency),
+                )
+            } else {
+                let account = nets
+                    .iter()
+                    .find(|(_, net, _)| {
+                        net.iter()
+                            .any(|(c, total)| *c == paid.currency && *total < Decimal::ZERO)
+                    })
+                    .map_or(nets[0].0.clone(), |(a, _, _)| a.clone());
+                (account, paid.clone())
+            };
+            let (_, net, slot) = nets
+                .iter_mut()
+                .find(|(a, _, _)| *a == account)
+                .expect("SNH");
+            if slot.is_some() {
+                return Err(parse_error(
+                    line,
+                    format!("More than one fee from {account}"),
+                ));
+            }
+            if let Some((_, total)) = net.iter_mut().find(|(c, _)| *c == fee.currency) {
+                *total += fee.amount;
+            }
+            *slot = Some(fee);
+        }
+
+        let counterparts = || {
+            txn.entries
+                .iter()
+                .map(|e| e.account.as_str())
+                .filter(|a| !accounts.is_tracked(a) && !accounts.is_fee(a))
+        };
+        let single = nets.len() == 1;
+        let time = match txn.meta.get("time") {
+            Some(t) => NaiveTime::parse_from_str(t, "%H:%M:%S%.f")
+                .map_err(|_| parse_error(line, format!("Bad time {t}")))?,
+            None => NaiveTime::MIN,
+        };
+        let time = txn.date.and_time(time).and_utc().timestamp_millis();
+
+        let mut recs = Vec::new();
+        for (account, net, fee) in nets {
+            let ins: Vec<Amount> = net
+                .iter()
+                .filter(|(_, total)| *total > Decimal::ZERO)
+                .map(|(c, total)| Amount::new(*total, c))
+                .collect();
+            let outs: Vec<Amount> = net
+                .iter()
+                .filter(|(_, total)| *total < Decimal::ZERO)
+                .map(|(c, total)| Amount::new(-*total, c))
+                .collect();
+            let (mut type_txs, buy, sell, fee) = match (ins.as_slice(), outs.as_slice(), fee) {
+                ([buy], [sell], fee) => (
+                    TokenTaxRecType::Trade,
+                    Some(buy.clone()),
+                    Some(sell.clone()),
+                    fee,
+                ),
+                ([buy], [], fee) => {
+                    let type_txs = match single {
+                        true => accounts.type_in(counterparts()),
+                        false => TokenTaxRecType::Deposit,
+                    };
+                    (type_txs, Some(buy.clone()), None, fee)
+                }
+                ([], [sell], fee) => {
+                    let type_txs = match single {
+                        true => accounts.type_out(counterparts()),
+                        false => TokenTaxRecType::Withdrawal,
+                    };
+                    (type_txs, None, Some(sell.clone()), fee)
+                }
+                // Only a fee was paid
+                ([], [], Some(fee)) => (TokenTaxRecType::Spend, None, Some(fee), None),
+                ([], [], None) => continue,
+                _ => {
+                    return Err(parse_error(
+                        line,
+                        format!(
+                        "Unsupported transaction moving {} commodities in and {} out of {account}",
+                        ins.len(),
+                        outs.len()
+                    ),
+                    ))
+                }
+            };
+            if single {
+                if let Some(t) = txn.meta.get("type").and_then(|t| t.parse().ok()) {
+                    type_txs = t;
+                }
+            }
+            let mut builder = builder_for(&type_txs, buy, sell)
+                .ok_or_else(|| parse_error(line, format!("{type_txs} doesn't fit the postings")))?;
+            if let Some(fee) = fee {
+                builder = builder.fee(fee);
+            }
+            let exchange = match (single, txn.meta.get("exchange"), &txn.payee) {
+                (true, Some(exchange), _) => exchange.clone(),
+                _ if accounts.exchanges.values().any(|a| *a == account) => {
+                    accounts.exchange_of(&account)
+                }
+                (true, None, Some(payee)) => payee.clone(),
+                _ => accounts.exchange_of(&account),
+            };
+            let comment = if txn.narration == type_txs.to_string() {
+                ""
+            } else {
+                txn.narration.as_str()
+            };
+            builder = builder.exchange(&exchange).comment(comment);
+            if txn.margin && type_txs == TokenTaxRecType::Trade {
+                builder = builder.margin();
+            }
+            let mut rec = builder
+                .at(time)
+                .map_err(|e| parse_error(line, e.to_string()))?;
+            rec.provenance.source = Some(Source {
+                file: source.to_owned(),
+                line: line as u64,
+            });
+            rec.provenance.steps.push(AuditStep {
+                kind: AuditKind::Conversion,
+                rule: Some("journal".to_owned()),
+                changes: Vec::new(),
+                merged: Vec::new(),
+            });
+            recs.push(rec);
+        }
+        Ok(recs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::price::PriceTable;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    const RECS: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,binance.us,,,2022-01-01 00:00:00
+Trade,1,ETH,3123.5,USD,0.001,BNB,binance.us,margin,\"Limit \"\"buy\"\"\",2022-01-02 13:14:15
+Income,0.01,ETH,,,,,coinbase,,Staking,2022-01-03 00:00:00
+Trade,0.05,BTC,0.5,ETH,,,binance.us,,,2022-01-04 00:00:00
+Withdrawal,,,0.01,ETH,0.001,ETH,coinbase,,,2022-01-05 00:00:00
+";
+
+    fn prices() -> PriceTable {
+        let mut prices = PriceTable::new();
+        prices.insert("BNB", "USD", time_ms("2022-01-01 00:00:00"), dec!(500));
+        prices.insert("ETH", "USD", time_ms("2022-01-01 00:00:00"), dec!(3000));
+        prices
+    }
+
+    #[test]
+    fn test_accounts() {
+        let mut accounts = Accounts::new();
+        assert_eq!(accounts.exchange("binance.us"), "Assets:Crypto:BinanceUs");
+        assert_eq!(
+            accounts.exchange("coinbase advanced"),
+            "Assets:Crypto:CoinbaseAdvanced"
+        );
+        assert_eq!(accounts.exchange(""), "Assets:Crypto:Wallet");
+        accounts
+            .exchanges
+            .insert("coinbase".to_owned(), "Assets:Coinbase".to_owned());
+        assert_eq!(accounts.exchange("coinbase"), "Assets:Coinbase");
+    }
+
+    #[test]
+    fn test_beancount() {
+        let recs = recs_from_csv(RECS);
+        let mut buf = Vec::new();
+        Journal::new("USD")
+            .write_beancount(&recs, &prices(), &mut buf)
+            .unwrap();
+        let expected = r#"option "operating_currency" "USD"
+option "booking_method" "FIFO"
+
+2022-01-01 open Assets:Crypto:BinanceUs
+2022-01-01 open Assets:Crypto:Coinbase
+2022-01-01 open Equity:Crypto:Transfers
+2022-01-01 open Expenses:Crypto:Fees
+2022-01-01 open Income:Crypto:Gains
+2022-01-01 open Income:Crypto:Income
+
+2022-01-01 * "binance.us" "Deposit"
+  time: "00:00:00.000"
+  type: "Deposit"
+  Assets:Crypto:BinanceUs  5125 USD
+  Equity:Crypto:Transfers  -5125 USD
+
+2022-01-02 * "binance.us" "Limit \"buy\"" #margin
+  time: "13:14:15.000"
+  type: "Trade"
+  Assets:Crypto:BinanceUs  1 ETH {3123.5 USD}
+  Assets:Crypto:BinanceUs  -3123.5 USD
+  Assets:Crypto:BinanceUs  -0.001 BNB {}
+  Expenses:Crypto:Fees  0.5 USD
+  Income:Crypto:Gains
+
+2022-01-03 * "coinbase" "Staking"
+  time: "00:00:00.000"
+  type: "Income"
+  Assets:Crypto:Coinbase  0.01 ETH {3000 USD}
+  Income:Crypto:Income  -30 USD
+
+2022-01-04 * "binance.us" "Trade"
+  time: "00:00:00.000"
+  type: "Trade"
+  Assets:Crypto:BinanceUs  0.05 BTC {30000 USD}
+  Assets:Crypto:BinanceUs  -0.5 ETH {} @ 3000 USD
+  Income:Crypto:Gains
+
+2022-01-05 * "coinbase" "Withdrawal"
+  time: "00:00:00.000"
+  type: "Withdrawal"
+  Assets:Crypto:Coinbase  -0.01 ETH {}
+  Assets:Crypto:Coinbase  -0.001 ETH {}
+  Expenses:Crypto:Fees  3 USD
+  Equity:Crypto:Transfers
+"#;
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+
+        // A sale for the home currency is at the price received
+        let sale = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1600,USD,0.5,ETH,,,coinbase,,,2022-01-06 00:00:00
+",
+        );
+        for prices in [prices(), PriceTable::new()] {
+            let mut buf = Vec::new();
+            Journal::new("USD")
+                .write_beancount(&sale, &prices, &mut buf)
+                .unwrap();
+            let text = String::from_utf8(buf).unwrap();
+            assert!(text.contains("  Assets:Crypto:Coinbase  1600 USD\n"));
+            assert!(text.contains("  Assets:Crypto:Coinbase  -0.5 ETH {} @ 3200 USD\n"));
+        }
+
+        // Without prices only trades for the home currency can be valued
+        let err = Journal::new("USD")
+            .write_beancount(&recs[2..3], &PriceTable::new(), &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(err, Error::MissingPrice { .. }));
+    }
+
+    #[test]
+    fn test_ledger() {
+        let recs = recs_from_csv(RECS);
+        let mut journal = Journal::new("USD");
+        journal.accounts.income = "Income:Staking".to_owned();
+        let mut buf = Vec::new();
+        journal.write_ledger(&recs[1..4], &mut buf).unwrap();
+        let expected = r#"2022-01-02 * Limit "buy"
+  ; time: 13:14:15.000
+  ; type: Trade
+  ; exchange: binance.us
+  ; :margin:
+  Assets:Crypto:BinanceUs  1 ETH @@ 3123.5 USD
+  Assets:Crypto:BinanceUs  -3123.5 USD
+  Assets:Crypto:BinanceUs  -0.001 BNB
+  Expenses:Crypto:Fees  0.001 BNB
+
+2022-01-03 * Staking
+  ; time: 00:00:00.000
+  ; type: Income
+  ; exchange: coinbase
+  Assets:Crypto:Coinbase  0.01 ETH
+  Income:Staking  -0.01 ETH
+
+2022-01-04 * Trade
+  ; time: 00:00:00.000
+  ; type: Trade
+  ; exchange: binance.us
+  Assets:Crypto:BinanceUs  0.05 BTC @@ 0.5 ETH
+  Assets:Crypto:BinanceUs  -0.5 ETH
+"#;
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+
+        let err = journal
+            .write_ledger(&[TokenTaxRec::new()], &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(err, Error::UnsupportedRecord { rec_idx: 0 }));
+    }
+
+    #[test]
+    fn test_commodity_names() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,2,stETH,6000,USD,,,lido,,,2022-01-01 00:00:00
+Trade,100,1INCH,1.5,USDC.E,,,,,,2022-01-02 00:00:00
+",
+        );
+        let mut journal = Journal::new("USD");
+        let mut buf = Vec::new();
+        journal.write_ledger(&recs, &mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.contains("  Assets:Crypto:Lido  2 stETH @@ 6000 USD\n"));
+        assert!(text.contains("  Assets:Crypto:Wallet  100 \"1INCH\" @@ 1.5 \"USDC.E\"\n"));
+
+        let err = journal
+            .write_beancount(&recs[..1], &PriceTable::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid record: Record 0: stETH isn't a Beancount currency, name it in commodities"
+        );
+        journal
+            .commodities
+            .insert("stETH".to_owned(), "STETH".to_owned());
+        let mut buf = Vec::new();
+        journal
+            .write_beancount(&recs[..1], &PriceTable::new(), &mut buf)
+            .unwrap();
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .contains("  Assets:Crypto:Lido  2 STETH {3000 USD}\n"));
+
+        // A zero amount has no unit cost
+        let mut rec = recs[0].clone();
+        rec.buy_amount = Some(Decimal::ZERO);
+        let err = journal
+            .write_beancount(&[rec], &PriceTable::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid record: Record 0: a zero amount has no unit cost"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let recs = recs_from_csv(RECS);
+        let journal = Journal::new("USD");
+        let mut buf = Vec::new();
+        journal.write_beancount(&recs, &prices(), &mut buf).unwrap();
+        let read = journal
+            .read(&mut buf.as_slice(), "books.beancount")
+            .unwrap();
+        assert_eq!(read, recs);
+        assert_eq!(read[1].provenance.source.as_ref().unwrap().line, 17);
+
+        let mut buf = Vec::new();
+        journal.write_ledger(&recs, &mut buf).unwrap();
+        assert_eq!(
+            journal.read(&mut buf.as_slice(), "books.journal").unwrap(),
+            recs
+        );
+
+        // Quoted and renamed commodities
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,100,1INCH,3000,USD,1,USDC.E,binance.us,,,2022-01-02 00:00:00
+",
+        );
+        let mut journal = Journal::new("USD");
+        let mut buf = Vec::new();
+        journal.write_ledger(&recs, &mut buf).unwrap();
+        assert_eq!(
+            journal.read(&mut buf.as_slice(), "books.journal").unwrap(),
+            recs
+        );
+        journal
+            .commodities
+            .insert("1INCH".to_owned(), "ONEINCH".to_owned());
+        let mut prices = prices();
+        prices.insert("USDC.E", "USD", 0, dec!(1));
+        let mut buf = Vec::new();
+        journal.write_beancount(&recs, &prices, &mut buf).unwrap();
+        assert_eq!(
+            journal
+                .read(&mut buf.as_slice(), "books.beancount")
+                .unwrap(),
+            recs
+        );
+    }
+
+    #[test]
+    fn test_read_hledger() {
+        let text = "
+; Moving money in
+2022-01-01 opening
+    assets:bank:checking     $-1,000.00
+    assets:crypto:coinbase   $1,000.00
+
+2022/01/02 * Coinbase | Buy BTC
+    assets:crypto:coinbase    0.02 BTC @ $40,000
+    assets:crypto:coinbase
+
+2022-01-03 Staking reward  ; exchange: kraken
+    assets:crypto:dot    1.5 DOT
+    income:staking
+
+2022-01-04 Move to cold storage
+    assets:crypto:coinbase   -0.01 BTC
+    assets:crypto:ledger      0.0099 BTC
+    expenses:fees             0.0001 BTC
+
+2022-01-05 Coffee
+    assets:crypto:ledger   -0.0001 BTC
+    expenses:food
+
+2022-01-06 Coffee again
+    assets:crypto:ledger   -0.0001 BTC @ $300
+    Expenses:Coffee
+";
+        let mut journal = Journal::new("USD");
+        journal.accounts.assets = "assets:crypto".to_owned();
+        let recs = journal.read(&mut text.as_bytes(), "books.journal").unwrap();
+        let expected = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,1000,USD,,,,,coinbase,,opening,2022-01-01 00:00:00
+Trade,0.02,BTC,800,USD,,,Coinbase,,Buy BTC,2022-01-02 00:00:00
+Income,1.5,DOT,,,,,kraken,,Staking reward,2022-01-03 00:00:00
+Withdrawal,,,0.0099,BTC,0.0001,BTC,coinbase,,Move to cold storage,2022-01-04 00:00:00
+Deposit,0.0099,BTC,,,,,ledger,,Move to cold storage,2022-01-04 00:00:00
+Spend,,,0.0001,BTC,,,ledger,,Coffee,2022-01-05 00:00:00
+Spend,,,0.0001,BTC,,,ledger,,Coffee again,2022-01-06 00:00:00
+",
+        );
+        assert_eq!(recs, expected);
+
+        let err = journal
+            .read(
+                &mut "2022-01-01 LP\n  assets:crypto:x  1 A\n  assets:crypto:x  1 B\n  assets:crypto:x  -1 C\n  equity:y\n"
+                    .as_bytes(),
+                "bad.journal",
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line 1: Unsupported transaction moving 2 commodities in and 1 out of assets:crypto:x"
+        );
+    }
+}
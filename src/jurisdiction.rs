@@ -0,0 +1,372 @@
+//! Jurisdiction profiles bundle the assumptions that vary between tax
+//! authorities so calculations can be driven by a selected profile.
+use chrono::{Datelike, TimeZone, Utc};
+
+use crate::lots::CostBasisMethod;
+use crate::TokenTaxRecType;
+
+/// How a record that removes an asset without a trade is taxed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Treatment {
+    /// Disposed of at market value
+    Disposal,
+
+    /// Disposed of with no proceeds, realizing a loss of the cost
+    ZeroProceeds,
+
+    /// Leaves holdings without a gain or loss
+    Removal,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CostBasis {
+    /// Individual lots consumed in the order given by the method
+    Lots(CostBasisMethod),
+
+    /// A single pool per asset at average cost
+    AverageCost,
+
+    /// UK same-day, bed and breakfast and Section 104 matching
+    SharePooling,
+}
+
+impl CostBasis {
+    /// The order lots are consumed in, FIFO when the basis is pooled
+    pub fn lot_method(&self) -> CostBasisMethod {
+        match self {
+            CostBasis::Lots(method) => *method,
+            CostBasis::AverageCost | CostBasis::SharePooling => CostBasisMethod::Fifo,
+        }
+    }
+}
+
+pub trait Jurisdiction {
+    fn name(&self) -> &str;
+
+    fn home_currency(&self) -> &str;
+
+    /// The tax year containing `time` using the jurisdiction's
+    /// conventional numbering
+    fn tax_year(&self, time: i64) -> i32;
+
+    /// Start, inclusive, and end, exclusive, of tax year `year`
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64);
+
+    /// Months an asset must be held for long term treatment, None if the
+    /// holding period doesn't matter
+    fn long_term_months(&self) -> Option<u32>;
+
+    fn cost_basis(&self) -> CostBasis;
+
+    /// Treatment of Spend, Gift, Lost and Stolen records
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment;
+}
+
+impl<J: Jurisdiction + ?Sized> Jurisdiction for &J {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn home_currency(&self) -> &str {
+        (**self).home_currency()
+    }
+
+    fn tax_year(&self, time: i64) -> i32 {
+        (**self).tax_year(time)
+    }
+
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64) {
+        (**self).tax_year_bounds(year)
+    }
+
+    fn long_term_months(&self) -> Option<u32> {
+        (**self).long_term_months()
+    }
+
+    fn cost_basis(&self) -> CostBasis {
+        (**self).cost_basis()
+    }
+
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment {
+        (**self).treatment(type_txs)
+    }
+}
+
+fn start_of_day_ms(year: i32, month: u32, day: u32) -> i64 {
+    Utc.with_ymd_and_hms(year, month, day, 0, 0, 0)
+        .unwrap()
+        .timestamp_millis()
+}
+
+fn calendar_year(time: i64) -> i32 {
+    Utc.timestamp_millis_opt(time).unwrap().year()
+}
+
+fn calendar_year_bounds(year: i32) -> (i64, i64) {
+    (start_of_day_ms(year, 1, 1), start_of_day_ms(year + 1, 1, 1))
+}
+
+/// Select a profile by ISO 3166 country code
+pub fn jurisdiction(code: &str) -> Option<Box<dyn Jurisdiction>> {
+    match code.to_uppercase().as_str() {
+        "US" => Some(Box::new(UnitedStates::new())),
+        "CA" => Some(Box::new(Canada::new())),
+        "GB" | "UK" => Some(Box::new(UnitedKingdom::new())),
+        "DE" => Some(Box::new(Germany::new())),
+        "AU" => Some(Box::new(Australia::new())),
+        _ => None,
+    }
+}
+
+macro_rules! profile {
+    ($name:ident, $currency:expr) => {
+        #[derive(Clone, Debug, Eq, PartialEq)]
+        pub struct $name {
+            pub home_currency: String,
+        }
+
+        impl $name {
+            pub fn new() -> $name {
+                $name {
+                    home_currency: $currency.to_owned(),
+                }
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    };
+}
+
+profile!(UnitedStates, "USD");
+profile!(Canada, "CAD");
+profile!(UnitedKingdom, "GBP");
+profile!(Germany, "EUR");
+profile!(Australia, "AUD");
+
+impl Jurisdiction for UnitedStates {
+    fn name(&self) -> &str {
+        "United States"
+    }
+
+    fn home_currency(&self) -> &str {
+        &self.home_currency
+    }
+
+    fn tax_year(&self, time: i64) -> i32 {
+        calendar_year(time)
+    }
+
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64) {
+        calendar_year_bounds(year)
+    }
+
+    fn long_term_months(&self) -> Option<u32> {
+        Some(12)
+    }
+
+    fn cost_basis(&self) -> CostBasis {
+        CostBasis::Lots(CostBasisMethod::Fifo)
+    }
+
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment {
+        match type_txs {
+            // Gifts aren't taxed to the giver and personal casualty and
+            // theft losses aren't deductible.
+            TokenTaxRecType::Gift | TokenTaxRecType::Lost | TokenTaxRecType::Stolen => {
+                Treatment::Removal
+            }
+            _ => Treatment::Disposal,
+        }
+    }
+}
+
+impl Jurisdiction for Canada {
+    fn name(&self) -> &str {
+        "Canada"
+    }
+
+    fn home_currency(&self) -> &str {
+        &self.home_currency
+    }
+
+    fn tax_year(&self, time: i64) -> i32 {
+        calendar_year(time)
+    }
+
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64) {
+        calendar_year_bounds(year)
+    }
+
+    fn long_term_months(&self) -> Option<u32> {
+        None
+    }
+
+    fn cost_basis(&self) -> CostBasis {
+        CostBasis::AverageCost
+    }
+
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment {
+        match type_txs {
+            TokenTaxRecType::Lost | TokenTaxRecType::Stolen => Treatment::ZeroProceeds,
+            _ => Treatment::Disposal,
+        }
+    }
+}
+
+impl Jurisdiction for UnitedKingdom {
+    fn name(&self) -> &str {
+        "United Kingdom"
+    }
+
+    fn home_currency(&self) -> &str {
+        &self.home_currency
+    }
+
+    /// Identified by the year it starts in, 2021 is 6 April 2021 to
+    /// 5 April 2022
+    fn tax_year(&self, time: i64) -> i32 {
+        crate::uk::tax_year(time)
+    }
+
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64) {
+        (start_of_day_ms(year, 4, 6), start_of_day_ms(year + 1, 4, 6))
+    }
+
+    fn long_term_months(&self) -> Option<u32> {
+        None
+    }
+
+    fn cost_basis(&self) -> CostBasis {
+        CostBasis::SharePooling
+    }
+
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment {
+        match type_txs {
+            // A negligible value claim for lost assets, theft isn't a
+            // disposal.
+            TokenTaxRecType::Lost => Treatment::ZeroProceeds,
+            TokenTaxRecType::Stolen => Treatment::Removal,
+            _ => Treatment::Disposal,
+        }
+    }
+}
+
+impl Jurisdiction for Germany {
+    fn name(&self) -> &str {
+        "Germany"
+    }
+
+    fn home_currency(&self) -> &str {
+        &self.home_currency
+    }
+
+    fn tax_year(&self, time: i64) -> i32 {
+        calendar_year(time)
+    }
+
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64) {
+        calendar_year_bounds(year)
+    }
+
+    fn long_term_months(&self) -> Option<u32> {
+        Some(12)
+    }
+
+    fn cost_basis(&self) -> CostBasis {
+        CostBasis::Lots(CostBasisMethod::Fifo)
+    }
+
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment {
+        match type_txs {
+            // Only a sale, or an exchange, is a private sale transaction
+            TokenTaxRecType::Gift | TokenTaxRecType::Lost | TokenTaxRecType::Stolen => {
+                Treatment::Removal
+            }
+            _ => Treatment::Disposal,
+        }
+    }
+}
+
+impl Jurisdiction for Australia {
+    fn name(&self) -> &str {
+        "Australia"
+    }
+
+    fn home_currency(&self) -> &str {
+        &self.home_currency
+    }
+
+    /// Identified by the year it ends in, 2022 is 1 July 2021 to
+    /// 30 June 2022
+    fn tax_year(&self, time: i64) -> i32 {
+        crate::au::financial_year(time)
+    }
+
+    fn tax_year_bounds(&self, year: i32) -> (i64, i64) {
+        (start_of_day_ms(year - 1, 7, 1), start_of_day_ms(year, 7, 1))
+    }
+
+    fn long_term_months(&self) -> Option<u32> {
+        Some(12)
+    }
+
+    fn cost_basis(&self) -> CostBasis {
+        CostBasis::Lots(CostBasisMethod::Fifo)
+    }
+
+    fn treatment(&self, type_txs: &TokenTaxRecType) -> Treatment {
+        match type_txs {
+            TokenTaxRecType::Lost | TokenTaxRecType::Stolen => Treatment::ZeroProceeds,
+            _ => Treatment::Disposal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::time_ms;
+
+    #[test]
+    fn test_jurisdiction() {
+        assert!(jurisdiction("xx").is_none());
+
+        let us = jurisdiction("us").unwrap();
+        assert_eq!(us.name(), "United States");
+        assert_eq!(us.home_currency(), "USD");
+        assert_eq!(us.treatment(&TokenTaxRecType::Gift), Treatment::Removal);
+        assert_eq!(us.treatment(&TokenTaxRecType::Spend), Treatment::Disposal);
+
+        let ca = jurisdiction("CA").unwrap();
+        assert_eq!(ca.cost_basis(), CostBasis::AverageCost);
+        assert_eq!(
+            ca.treatment(&TokenTaxRecType::Lost),
+            Treatment::ZeroProceeds
+        );
+    }
+
+    #[test]
+    fn test_tax_year_bounds() {
+        let profiles: Vec<Box<dyn Jurisdiction>> = ["US", "CA", "UK", "DE", "AU"]
+            .iter()
+            .map(|code| jurisdiction(code).unwrap())
+            .collect();
+        for j in profiles.iter() {
+            for year in 2019..2023 {
+                let (start, end) = j.tax_year_bounds(year);
+                assert_eq!(j.tax_year(start), year, "{}", j.name());
+                assert_eq!(j.tax_year(end - 1), year, "{}", j.name());
+                assert_eq!(j.tax_year(end), year + 1, "{}", j.name());
+            }
+        }
+
+        let uk = UnitedKingdom::new();
+        assert_eq!(uk.tax_year_bounds(2021).0, time_ms("2021-04-06 00:00:00"));
+        let au = Australia::new();
+        assert_eq!(au.tax_year_bounds(2022).0, time_ms("2021-07-01 00:00:00"));
+    }
+}
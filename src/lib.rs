@@ -1,9 +1,44 @@
+pub mod acb;
+pub mod au;
+pub mod audit;
+pub mod builder;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+pub mod convert;
+pub mod de;
+pub mod diff;
+pub mod error;
+pub mod events;
+pub mod filter;
+pub mod fx;
+pub mod holdings;
+pub mod html;
+pub mod journal;
+pub mod jurisdiction;
+pub mod lots;
+pub mod peg;
+pub mod price;
+pub mod price_store;
+pub mod report;
+pub mod rules;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod spreadsheet;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+pub mod time_utils;
+pub mod uk;
+
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 
+use audit::{AuditKind, AuditStep, Provenance};
+use fx::Amount;
 use rust_decimal::prelude::*;
 //use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
 use serde_utc_time_ms::{de_string_to_utc_time_ms, se_time_ms_to_utc_string};
+use sha2::{Digest, Sha256};
 use time_ms_conversions::time_ms_to_utc_string;
 
 #[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, PartialOrd, Ord)]
@@ -27,6 +62,41 @@ impl Display for TokenTaxRecType {
     }
 }
 
+impl TokenTaxRecType {
+    /// Parse a type name ignoring case, e.g. `income` or `INCOME`
+    pub fn from_str_ignore_case(s: &str) -> Result<Self, String> {
+        // Type names are a capitalized word, e.g. Income
+        let mut chars = s.chars();
+        let name: String = chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars.flat_map(char::to_lowercase)))
+            .into_iter()
+            .flatten()
+            .collect();
+        TokenTaxRecType::from_str(&name).map_err(|_| format!("Unknown record type {s}"))
+    }
+}
+
+impl FromStr for TokenTaxRecType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Income" => Ok(TokenTaxRecType::Income),
+            "Deposit" => Ok(TokenTaxRecType::Deposit),
+            "Mining" => Ok(TokenTaxRecType::Mining),
+            "Gift" => Ok(TokenTaxRecType::Gift),
+            "Trade" => Ok(TokenTaxRecType::Trade),
+            "Withdrawal" => Ok(TokenTaxRecType::Withdrawal),
+            "Spend" => Ok(TokenTaxRecType::Spend),
+            "Lost" => Ok(TokenTaxRecType::Lost),
+            "Stolen" => Ok(TokenTaxRecType::Stolen),
+            "Unknown" => Ok(TokenTaxRecType::Unknown),
+            _ => Err(format!("Unknown record type {s}")),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Eq, Ord, PartialEq, PartialOrd)]
 pub enum GroupType {
     #[serde(rename = "margin")]
@@ -53,6 +123,11 @@ pub struct TokenTaxRec {
     #[serde(deserialize_with = "de_string_to_utc_time_ms")]
     #[serde(serialize_with = "se_time_ms_to_utc_string")]
     pub time: i64,
+
+    /// Where the record came from and how it's been changed, not part of
+    /// its identity
+    #[serde(skip)]
+    pub provenance: Provenance,
 }
 
 impl TokenTaxRec {
@@ -69,9 +144,11 @@ impl TokenTaxRec {
             group: None,
             comment: "".to_string(),
             time: 0,
+            provenance: Provenance::default(),
         }
     }
 
+    #[deprecated(note = "use the typed constructors, e.g. TokenTaxRec::trade, which validate")]
     #[allow(clippy::too_many_arguments)]
     pub fn from(
         type_txs: TokenTaxRecType,
@@ -98,6 +175,7 @@ impl TokenTaxRec {
             group,
             comment,
             time,
+            provenance: Provenance::default(),
         }
     }
 
@@ -145,6 +223,210 @@ impl TokenTaxRec {
             TokenTaxRecType::Gift => &self.buy_currency,
         }
     }
+
+    pub fn buy_leg(&self) -> Option<Amount> {
+        leg(self.buy_amount, &self.buy_currency)
+    }
+
+    pub fn sell_leg(&self) -> Option<Amount> {
+        leg(self.sell_amount, &self.sell_currency)
+    }
+
+    pub fn fee_leg(&self) -> Option<Amount> {
+        leg(self.fee_amount, &self.fee_currency)
+    }
+
+    /// Hex SHA-256 of the record's canonical form, amounts are normalized
+    /// so `1.50` and `1.5` match and currencies are upper cased.
+    pub fn fingerprint(&self) -> String {
+        let amount = |a: Option<Decimal>| a.map(|a| a.normalize().to_string()).unwrap_or_default();
+        let group = match self.group {
+            Some(GroupType::Margin) => "margin",
+            None => "",
+        };
+        let fields = [
+            self.type_txs.to_string(),
+            amount(self.buy_amount),
+            self.buy_currency.trim().to_uppercase(),
+            amount(self.sell_amount),
+            self.sell_currency.trim().to_uppercase(),
+            amount(self.fee_amount),
+            self.fee_currency.trim().to_uppercase(),
+            self.exchange.trim().to_owned(),
+            group.to_owned(),
+            self.comment.clone(),
+            self.time.to_string(),
+        ];
+
+        // Length prefixes keep field boundaries unambiguous
+        let mut hasher = Sha256::new();
+        for field in fields.iter() {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// A field whose value differs between two versions of a record
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}", self.field, self.old, self.new)
+    }
+}
+
+/// The fields of `old` changed in `new`, amounts compare by value so
+/// `1.50` and `1.5` are unchanged.
+pub fn field_changes(old: &TokenTaxRec, new: &TokenTaxRec) -> Vec<FieldChange> {
+    let amount = |a: Option<Decimal>| a.map(|a| a.to_string()).unwrap_or_default();
+    let group = |g: &Option<GroupType>| match g {
+        Some(GroupType::Margin) => "margin".to_owned(),
+        None => "".to_owned(),
+    };
+    let mut changes = Vec::new();
+    let mut check = |field, changed: bool, old: String, new: String| {
+        if changed {
+            changes.push(FieldChange { field, old, new });
+        }
+    };
+    check(
+        "type",
+        old.type_txs != new.type_txs,
+        old.type_txs.to_string(),
+        new.type_txs.to_string(),
+    );
+    for (field, a, b) in [
+        ("buy_amount", old.buy_amount, new.buy_amount),
+        ("sell_amount", old.sell_amount, new.sell_amount),
+        ("fee_amount", old.fee_amount, new.fee_amount),
+    ] {
+        check(field, a != b, amount(a), amount(b));
+    }
+    for (field, a, b) in [
+        ("buy_currency", &old.buy_currency, &new.buy_currency),
+        ("sell_currency", &old.sell_currency, &new.sell_currency),
+        ("fee_currency", &old.fee_currency, &new.fee_currency),
+        ("exchange", &old.exchange, &new.exchange),
+        ("comment", &old.comment, &new.comment),
+    ] {
+        check(field, a != b, a.clone(), b.clone());
+    }
+    check(
+        "group",
+        old.group != new.group,
+        group(&old.group),
+        group(&new.group),
+    );
+    check(
+        "date",
+        old.time != new.time,
+        time_utils::format_time_ms(old.time),
+        time_utils::format_time_ms(new.time),
+    );
+    changes
+}
+
+/// Remove records with the same fingerprint as an earlier one, returns the
+/// number removed. The sources of removed records are recorded as merged
+/// into the one kept.
+pub fn dedup(recs: &mut Vec<TokenTaxRec>) -> usize {
+    let mut kept = HashMap::new();
+    let mut removed = HashSet::new();
+    for idx in 0..recs.len() {
+        let keep = *kept.entry(recs[idx].fingerprint()).or_insert(idx);
+        if keep == idx {
+            continue;
+        }
+        removed.insert(idx);
+        if let Some(source) = recs[idx].provenance.source.clone() {
+            let steps = &mut recs[keep].provenance.steps;
+            match steps.last_mut() {
+                Some(step) if step.kind == AuditKind::Merge => step.merged.push(source),
+                _ => steps.push(AuditStep {
+                    kind: AuditKind::Merge,
+                    rule: None,
+                    changes: Vec::new(),
+                    merged: vec![source],
+                }),
+            }
+        }
+    }
+    let mut idx = 0;
+    recs.retain(|_| {
+        idx += 1;
+        !removed.contains(&(idx - 1))
+    });
+    removed.len()
+}
+
+/// Read the records of a TokenTax csv file
+pub fn read_csv<R: std::io::Read>(rdr: R) -> Result<Vec<TokenTaxRec>, error::Error> {
+    let mut reader = csv::Reader::from_reader(rdr);
+    let mut recs = Vec::new();
+    for entry in reader.deserialize() {
+        recs.push(entry?);
+    }
+    Ok(recs)
+}
+
+/// Header of a TokenTax csv file
+pub const CSV_HEADER: [&str; 11] = [
+    "Type",
+    "BuyAmount",
+    "BuyCurrency",
+    "SellAmount",
+    "SellCurrency",
+    "FeeAmount",
+    "FeeCurrency",
+    "Exchange",
+    "Group",
+    "Comment",
+    "Date",
+];
+
+/// Write `recs` as a TokenTax csv file which `read_csv` reads back unchanged
+pub fn write_csv<'a, W, I>(recs: I, wtr: W) -> Result<(), error::Error>
+where
+    W: std::io::Write,
+    I: IntoIterator<Item = &'a TokenTaxRec>,
+{
+    let mut writer = csv::Writer::from_writer(wtr);
+    writer.write_record(CSV_HEADER)?;
+    let amount = |a: Option<Decimal>| a.map(|a| a.to_string()).unwrap_or_default();
+    for rec in recs {
+        writer.write_record([
+            rec.type_txs.to_string(),
+            amount(rec.buy_amount),
+            rec.buy_currency.clone(),
+            amount(rec.sell_amount),
+            rec.sell_currency.clone(),
+            amount(rec.fee_amount),
+            rec.fee_currency.clone(),
+            rec.exchange.clone(),
+            match rec.group {
+                Some(GroupType::Margin) => "margin".to_owned(),
+                None => "".to_owned(),
+            },
+            rec.comment.clone(),
+            time_utils::format_time_ms(rec.time),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn leg(amount: Option<Decimal>, currency: &str) -> Option<Amount> {
+    match amount {
+        Some(amount) if !currency.is_empty() => Some(Amount::new(amount, currency)),
+        _ => None,
+    }
 }
 
 impl Default for TokenTaxRec {
@@ -193,6 +475,7 @@ impl PartialEq for TokenTaxRec {
     }
 }
 
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for TokenTaxRec {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match self.time.partial_cmp(&other.time) {
@@ -249,6 +532,26 @@ impl Ord for TokenTaxRec {
     }
 }
 
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::TokenTaxRec;
+
+    pub fn recs_from_csv(csv: &str) -> Vec<TokenTaxRec> {
+        let mut reader = csv::Reader::from_reader(csv.trim_start().as_bytes());
+        reader
+            .deserialize()
+            .map(|entry| entry.expect("Bad csv record"))
+            .collect()
+    }
+
+    pub fn time_ms(s: &str) -> i64 {
+        chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+            .expect("Bad time")
+            .and_utc()
+            .timestamp_millis()
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -542,6 +845,105 @@ mod test {
         assert_eq!(tbr.get_quantity(), dec!(1));
     }
 
+    #[test]
+    fn test_type_from_str() {
+        for t in [TokenTaxRecType::Trade, TokenTaxRecType::Stolen] {
+            assert_eq!(t.to_string().parse::<TokenTaxRecType>(), Ok(t));
+        }
+        assert!("trade".parse::<TokenTaxRecType>().is_err());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let csv = "Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3123.5,USD,0.00124,BNB,binance.us,margin,,2021-01-01 00:00:00
+Income,0.001,BNB,,,,,binance.us,,\"Referral, Commission\",2021-01-01 00:00:00.250
+";
+        let recs = read_csv(csv.as_bytes()).unwrap();
+        let mut buf = Vec::new();
+        write_csv(&recs, &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), csv);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let recs = test_utils::recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,,,2021-01-01 00:00:00
+Trade,1.0,eth,3123,usd,0.001240,bnb,binance.us,,,2021-01-01 00:00:00
+Trade,1,ETH,3123,USD,0.00124,BNB,binance.us,margin,,2021-01-01 00:00:00
+Trade,1,ETH,3123,USD,0.00124,BNB,binance.us,,,2021-01-01 00:00:01
+",
+        );
+        let fp = recs[0].fingerprint();
+        assert_eq!(fp.len(), 64);
+        assert_eq!(fp, recs[1].fingerprint());
+        assert_ne!(fp, recs[2].fingerprint());
+        assert_ne!(fp, recs[3].fingerprint());
+
+        // Moving text between fields changes the fingerprint
+        let mut a = TokenTaxRec::new();
+        a.exchange = "ab".to_owned();
+        let mut b = a.clone();
+        b.exchange = "a".to_owned();
+        b.comment = "b".to_owned();
+        assert_ne!(a.fingerprint(), b.fingerprint());
+
+        let mut recs = recs;
+        assert_eq!(dedup(&mut recs), 1);
+        assert_eq!(recs.len(), 3);
+    }
+
+    #[test]
+    fn test_field_changes() {
+        let old = TokenTaxRec::deposit(Amount::new(dec!(1.50), "ETH"))
+            .fee(Amount::new(dec!(0.00124), "ETH"))
+            .exchange("coinbase")
+            .at(0)
+            .unwrap();
+        let mut new = old.clone();
+        new.buy_amount = Some(dec!(1.5));
+        assert_eq!(field_changes(&old, &new), vec![]);
+
+        new.type_txs = TokenTaxRecType::Income;
+        new.fee_amount = Some(dec!(0.00125));
+        new.group = Some(GroupType::Margin);
+        let changes: Vec<String> = field_changes(&old, &new)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "type Deposit -> Income",
+                "fee_amount 0.00124 -> 0.00125",
+                "group  -> margin"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_legs() {
+        let mut tbr = TokenTaxRec::new();
+        assert_eq!(tbr.buy_leg(), None);
+        assert_eq!(tbr.sell_leg(), None);
+        assert_eq!(tbr.fee_leg(), None);
+
+        tbr.buy_amount = Some(dec!(1));
+        assert_eq!(tbr.buy_leg(), None);
+        tbr.buy_currency = "ETH".to_owned();
+        assert_eq!(tbr.buy_leg(), Some(Amount::new(dec!(1), "ETH")));
+
+        tbr.sell_amount = Some(dec!(2));
+        tbr.sell_currency = "USD".to_owned();
+        assert_eq!(tbr.sell_leg(), Some(Amount::new(dec!(2), "USD")));
+
+        tbr.fee_amount = Some(dec!(3));
+        tbr.fee_currency = "BNB".to_owned();
+        assert_eq!(tbr.fee_leg(), Some(Amount::new(dec!(3), "BNB")));
+    }
+
     #[test]
     fn test_deserialize_from_csv() {
         let csv = "
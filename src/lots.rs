@@ -0,0 +1,307 @@
+//! Lot tracking, each acquisition is a lot and disposals consume lots in
+//! the order given by the cost basis method.
+use std::collections::BTreeMap;
+
+use chrono::{Months, TimeZone, Utc};
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::events::{TaxEvent, TaxEventKind};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CostBasisMethod {
+    /// First in first out
+    Fifo,
+
+    /// Last in first out
+    Lifo,
+
+    /// Highest unit cost first
+    Hifo,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lot {
+    pub asset: String,
+
+    /// Remaining quantity
+    pub quantity: Decimal,
+
+    /// Remaining cost including fees
+    pub cost: Decimal,
+
+    pub time: i64,
+    pub rec_idx: usize,
+}
+
+impl Lot {
+    /// Cost of a unit, zero for an empty lot
+    pub fn unit_cost(&self) -> Decimal {
+        if self.quantity.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.cost / self.quantity
+        }
+    }
+}
+
+/// The part of a disposal matched with a single lot
+#[derive(Clone, Debug, PartialEq)]
+pub struct LotDisposal {
+    pub asset: String,
+    pub quantity: Decimal,
+    pub cost: Decimal,
+    pub proceeds: Decimal,
+    pub fee: Decimal,
+    pub acquired_time: i64,
+    pub acquired_rec_idx: usize,
+    pub disposed_time: i64,
+    pub rec_idx: usize,
+}
+
+impl LotDisposal {
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.cost - self.fee
+    }
+
+    pub fn holding_period_ms(&self) -> i64 {
+        self.disposed_time - self.acquired_time
+    }
+
+    /// True if disposed on a UTC date after the calendar anniversary
+    /// `months` after acquisition, a disposal on the anniversary itself
+    /// isn't
+    pub fn held_longer_than(&self, months: u32) -> bool {
+        let date = |time: i64| Utc.timestamp_millis_opt(time).unwrap().date_naive();
+        let anniversary = date(self.acquired_time)
+            .checked_add_months(Months::new(months))
+            .expect("SNH");
+        date(self.disposed_time) > anniversary
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LotEngine {
+    pub method: CostBasisMethod,
+    lots: BTreeMap<String, Vec<Lot>>,
+}
+
+impl LotEngine {
+    pub fn new(method: CostBasisMethod) -> LotEngine {
+        LotEngine {
+            method,
+            lots: BTreeMap::new(),
+        }
+    }
+
+    /// Open lots of `asset` in acquisition order
+    pub fn lots(&self, asset: &str) -> &[Lot] {
+        self.lots.get(asset).map_or(&[], |lots| lots.as_slice())
+    }
+
+    /// Apply `events`, which are sorted by time before use, returning the
+    /// lot disposals they cause. Removals consume lots without a disposal
+    /// and acquisitions of nothing open no lot.
+    pub fn process(&mut self, events: &[TaxEvent]) -> Result<Vec<LotDisposal>, Error> {
+        let mut events: Vec<&TaxEvent> = events.iter().collect();
+        events.sort_by_key(|e| e.time);
+
+        let mut disposals = Vec::new();
+        for e in events {
+            match e.kind {
+                TaxEventKind::Acquire if e.quantity.is_zero() => {}
+                TaxEventKind::Acquire => self.lots.entry(e.asset.clone()).or_default().push(Lot {
+                    asset: e.asset.clone(),
+                    quantity: e.quantity,
+                    cost: e.value + e.fee,
+                    time: e.time,
+                    rec_idx: e.rec_idx,
+                }),
+                TaxEventKind::Dispose => self.dispose(e, &mut disposals)?,
+                TaxEventKind::Remove => self.dispose(e, &mut Vec::new())?,
+            }
+        }
+
+        Ok(disposals)
+    }
+
+    fn dispose(&mut self, e: &TaxEvent, disposals: &mut Vec<LotDisposal>) -> Result<(), Error> {
+        let lots = self.lots.entry(e.asset.clone()).or_default();
+        let held: Decimal = lots.iter().map(|l| l.quantity).sum();
+        if e.quantity > held {
+            return Err(Error::InsufficientHoldings {
+                asset: e.asset.clone(),
+                rec_idx: e.rec_idx,
+            });
+        }
+
+        let mut remaining = e.quantity;
+        while remaining > Decimal::ZERO {
+            let idx = match self.method {
+                CostBasisMethod::Fifo => 0,
+                CostBasisMethod::Lifo => lots.len() - 1,
+                CostBasisMethod::Hifo => {
+                    let mut idx = 0;
+                    for (i, lot) in lots.iter().enumerate() {
+                        if lot.unit_cost() > lots[idx].unit_cost() {
+                            idx = i;
+                        }
+                    }
+                    idx
+                }
+            };
+
+            let lot = &mut lots[idx];
+            let qty = remaining.min(lot.quantity);
+            let cost = if qty == lot.quantity {
+                lot.cost
+            } else {
+                lot.cost * qty / lot.quantity
+            };
+            disposals.push(LotDisposal {
+                asset: e.asset.clone(),
+                quantity: qty,
+                cost,
+                proceeds: e.value * qty / e.quantity,
+                fee: e.fee * qty / e.quantity,
+                acquired_time: lot.time,
+                acquired_rec_idx: lot.rec_idx,
+                disposed_time: e.time,
+                rec_idx: e.rec_idx,
+            });
+
+            lot.quantity -= qty;
+            lot.cost -= cost;
+            remaining -= qty;
+            if lot.quantity.is_zero() {
+                lots.remove(idx);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::time_ms;
+    use rust_decimal_macros::dec;
+
+    fn event(kind: TaxEventKind, quantity: Decimal, value: Decimal, time: i64) -> TaxEvent {
+        TaxEvent {
+            kind,
+            asset: "ETH".to_owned(),
+            quantity,
+            value,
+            fee: Decimal::ZERO,
+            time,
+            rec_idx: time as usize,
+        }
+    }
+
+    fn events() -> Vec<TaxEvent> {
+        vec![
+            event(TaxEventKind::Acquire, dec!(1), dec!(100), 0),
+            event(TaxEventKind::Acquire, dec!(1), dec!(300), 1),
+            event(TaxEventKind::Acquire, dec!(1), dec!(200), 2),
+            event(TaxEventKind::Dispose, dec!(1.5), dec!(600), 3),
+        ]
+    }
+
+    #[test]
+    fn test_fifo() {
+        let mut engine = LotEngine::new(CostBasisMethod::Fifo);
+        let disposals = engine.process(&events()).unwrap();
+        assert_eq!(disposals.len(), 2);
+        assert_eq!(disposals[0].acquired_rec_idx, 0);
+        assert_eq!(disposals[0].quantity, dec!(1));
+        assert_eq!(disposals[0].cost, dec!(100));
+        assert_eq!(disposals[0].proceeds, dec!(400));
+        assert_eq!(disposals[1].acquired_rec_idx, 1);
+        assert_eq!(disposals[1].quantity, dec!(0.5));
+        assert_eq!(disposals[1].cost, dec!(150));
+        assert_eq!(disposals[1].gain(), dec!(50));
+        assert_eq!(disposals[1].holding_period_ms(), 2);
+        assert!(!disposals[1].held_longer_than(12));
+
+        let lots = engine.lots("ETH");
+        assert_eq!(lots.len(), 2);
+        assert_eq!(lots[0].quantity, dec!(0.5));
+        assert_eq!(lots[0].cost, dec!(150));
+    }
+
+    #[test]
+    fn test_lifo() {
+        let mut engine = LotEngine::new(CostBasisMethod::Lifo);
+        let disposals = engine.process(&events()).unwrap();
+        assert_eq!(disposals[0].acquired_rec_idx, 2);
+        assert_eq!(disposals[1].acquired_rec_idx, 1);
+        assert_eq!(disposals[1].cost, dec!(150));
+    }
+
+    #[test]
+    fn test_hifo() {
+        let mut engine = LotEngine::new(CostBasisMethod::Hifo);
+        let disposals = engine.process(&events()).unwrap();
+        assert_eq!(disposals[0].acquired_rec_idx, 1);
+        assert_eq!(disposals[1].acquired_rec_idx, 2);
+        assert_eq!(disposals[1].cost, dec!(100));
+    }
+
+    #[test]
+    fn test_held_longer_than() {
+        let mut d = LotDisposal {
+            asset: "ETH".to_owned(),
+            quantity: dec!(1),
+            cost: dec!(1),
+            proceeds: dec!(1),
+            fee: dec!(0),
+            acquired_time: time_ms("2020-02-29 12:00:00"),
+            acquired_rec_idx: 0,
+            disposed_time: time_ms("2021-02-28 12:00:00"),
+            rec_idx: 1,
+        };
+        assert!(!d.held_longer_than(12));
+        d.disposed_time = time_ms("2021-02-28 23:59:59");
+        assert!(!d.held_longer_than(12));
+        d.disposed_time = time_ms("2021-03-01 00:00:00");
+        assert!(d.held_longer_than(12));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut engine = LotEngine::new(CostBasisMethod::Fifo);
+        let mut events = events();
+        events[3].kind = TaxEventKind::Remove;
+        assert!(engine.process(&events).unwrap().is_empty());
+        assert_eq!(engine.lots("ETH").len(), 2);
+        assert_eq!(engine.lots("ETH")[0].quantity, dec!(0.5));
+    }
+
+    #[test]
+    fn test_zero_quantity() {
+        let mut events = events();
+        events.push(event(TaxEventKind::Acquire, dec!(0), dec!(50), 3));
+        events.push(event(TaxEventKind::Dispose, dec!(0), dec!(0), 4));
+        events.push(event(TaxEventKind::Dispose, dec!(1), dec!(400), 5));
+        let mut engine = LotEngine::new(CostBasisMethod::Hifo);
+        let disposals = engine.process(&events).unwrap();
+        assert_eq!(disposals.len(), 4);
+        assert_eq!(disposals[2].cost, dec!(100));
+        assert_eq!(disposals[3].cost, dec!(50));
+        assert_eq!(engine.lots("ETH").len(), 1);
+    }
+
+    #[test]
+    fn test_insufficient_holdings() {
+        let mut engine = LotEngine::new(CostBasisMethod::Fifo);
+        let mut events = events();
+        events[3].quantity = dec!(4);
+        assert!(matches!(
+            engine.process(&events),
+            Err(Error::InsufficientHoldings { rec_idx: 3, .. })
+        ));
+        assert!(engine.lots("BTC").is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::io::{stdout, BufReader};
+use std::process::exit;
+
+use tokentaxrec::audit::{read_csv_with_source, write_audit_log};
+use tokentaxrec::convert::etherscan::Etherscan;
+use tokentaxrec::convert::{cointracker, cointracking, converter, koinly, CONVERTERS};
+use tokentaxrec::diff::diff;
+use tokentaxrec::error::Error;
+use tokentaxrec::filter::Filter;
+use tokentaxrec::rules::RuleSet;
+use tokentaxrec::{write_csv, TokenTaxRec};
+
+const USAGE: &str = "Usage:
+  tokentaxrec convert <FORMAT> <FILE>     Write FILE, an export in FORMAT, as
+                                         TokenTax csv
+  tokentaxrec diff <OLD.csv> <NEW.csv>    List the records added, removed and
+                                         modified in NEW
+  tokentaxrec export <FORMAT> <FILE.csv>  Write FILE in FORMAT, one of koinly,
+                                         cointracker or cointracking
+  tokentaxrec filter <EXPR> <FILE.csv>    Write the records of FILE matching EXPR
+  tokentaxrec reclassify [--dry-run] [--audit-log <LOG.csv>] <RULES.toml> <FILE.csv>
+                                         Write FILE with RULES applied, or with
+                                         --dry-run list the changes each rule
+                                         makes. --audit-log writes each record's
+                                         source and changes to LOG
+  tokentaxrec serve [--public] [--prices <LAYOUT> <ASSET>/<CURRENCY> <PRICES>]...
+                  <ADDR> <JURISDICTION> <FILE>
+                                         Serve the records of FILE, TokenTax csv
+                                         or with the sqlite feature a record
+                                         store, as a JSON API on ADDR, needs the
+                                         server feature. ADDR must be loopback
+                                         without --public. --prices values
+                                         ASSET in CURRENCY by the OHLCV file
+                                         PRICES in LAYOUT, one of binance,
+                                         cryptodatadownload, coingecko or generic
+  tokentaxrec wallet <ADDRESS,...> <FILE>...
+                                         Write the Etherscan exports FILEs of our
+                                         own ADDRESSes as TokenTax csv";
+
+/// Read a TokenTax csv file recording where each record came from
+fn read_file(path: &str) -> Result<Vec<TokenTaxRec>, Error> {
+    read_csv_with_source(BufReader::new(File::open(path)?), path)
+}
+
+fn convert(args: &[String]) -> Result<(), Error> {
+    let [format, path] = args else {
+        usage();
+    };
+    let Some(converter) = converter(format) else {
+        eprintln!(
+            "Unknown format {format}, expected one of {}",
+            CONVERTERS.join(", ")
+        );
+        exit(2);
+    };
+    let recs = converter.convert(&mut BufReader::new(File::open(path)?), path)?;
+    write_csv(&recs, stdout().lock())
+}
+
+fn diff_files(args: &[String]) -> Result<(), Error> {
+    let [old_path, new_path] = args else {
+        usage();
+    };
+    let old = read_file(old_path)?;
+    let new = read_file(new_path)?;
+    println!("{}", diff(&old, &new));
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<(), Error> {
+    let [format, path] = args else {
+        usage();
+    };
+    let recs = read_file(path)?;
+    let wtr = stdout().lock();
+    match format.to_lowercase().as_str() {
+        "koinly" => koinly::write_csv(&recs, wtr),
+        "cointracker" => {
+            let dropped = cointracker::write_csv(&recs, wtr)?;
+            if !dropped.is_empty() {
+                eprintln!(
+                    "Warning: CoinTracker has no exchange or comment, {} records lost theirs",
+                    dropped.len()
+                );
+            }
+            Ok(())
+        }
+        "cointracking" => cointracking::write_csv(&recs, wtr),
+        _ => {
+            eprintln!("Unknown format {format}, expected one of koinly, cointracker, cointracking");
+            exit(2);
+        }
+    }
+}
+
+fn filter(args: &[String]) -> Result<(), Error> {
+    let [expr, path] = args else {
+        usage();
+    };
+    let filter = Filter::parse(expr)?;
+    let recs = read_file(path)?;
+    write_csv(filter.apply(&recs), stdout().lock())
+}
+
+fn reclassify(args: &[String]) -> Result<(), Error> {
+    let mut dry_run = false;
+    let mut audit_log = None;
+    let mut args = args;
+    loop {
+        match args {
+            [flag, rest @ ..] if flag == "--dry-run" => {
+                dry_run = true;
+                args = rest;
+            }
+            [flag, log_path, rest @ ..] if flag == "--audit-log" => {
+                audit_log = Some(log_path);
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    let [rules_path, path] = args else {
+        usage();
+    };
+    let rules = RuleSet::parse(&std::fs::read_to_string(rules_path)?)?;
+    let mut recs = read_file(path)?;
+    if dry_run {
+        for change in rules.dry_run(&recs)? {
+            println!("{change}");
+        }
+        Ok(())
+    } else {
+        for change in rules.apply(&mut recs)? {
+            eprintln!("{change}");
+        }
+        if let Some(log_path) = audit_log {
+            write_audit_log(&recs, File::create(log_path)?)?;
+        }
+        write_csv(&recs, stdout().lock())
+    }
+}
+
+/// The records of `path`, a TokenTax csv file or with the `sqlite`
+/// feature a record store
+#[cfg(feature = "server")]
+fn read_records(path: &str) -> Result<Vec<TokenTaxRec>, Error> {
+    use std::io::Read;
+
+    let mut magic = [0; 16];
+    let is_sqlite = File::open(path)?
+        .read_exact(&mut magic)
+        .is_ok_and(|_| &magic == b"SQLite format 3\0");
+    if !is_sqlite {
+        return read_file(path);
+    }
+    #[cfg(feature = "sqlite")]
+    return tokentaxrec::storage::RecordStore::open(path)?.records();
+    #[cfg(not(feature = "sqlite"))]
+    {
+        eprintln!("{path} is a record store, which needs the sqlite feature");
+        exit(2);
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &[String]) -> Result<(), Error> {
+    use tokentaxrec::price_store::{ohlcv_layout, PriceStore, OHLCV_LAYOUTS};
+
+    let mut allow_remote = false;
+    let mut prices = PriceStore::new();
+    let mut args = args;
+    loop {
+        match args {
+            [flag, rest @ ..] if flag == "--public" => {
+                allow_remote = true;
+                args = rest;
+            }
+            [flag, layout, pair, prices_path, rest @ ..] if flag == "--prices" => {
+                let Some(layout) = ohlcv_layout(layout) else {
+                    eprintln!(
+                        "Unknown layout {layout}, expected one of {}",
+                        OHLCV_LAYOUTS.join(", ")
+                    );
+                    exit(2);
+                };
+                let Some((asset, currency)) = pair.split_once('/') else {
+                    usage();
+                };
+                let rdr = BufReader::new(File::open(prices_path)?);
+                prices.import(rdr, layout, asset, currency)?;
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    let [addr, code, path] = args else {
+        usage();
+    };
+    let Some(jurisdiction) = tokentaxrec::jurisdiction::jurisdiction(code) else {
+        eprintln!("Unknown jurisdiction {code}");
+        exit(2);
+    };
+    let api = tokentaxrec::server::Api::new(read_records(path)?, jurisdiction, Box::new(prices));
+    eprintln!("Serving {path} on http://{addr}");
+    api.serve(addr, allow_remote)
+}
+
+fn wallet(args: &[String]) -> Result<(), Error> {
+    let [addresses, paths @ ..] = args else {
+        usage();
+    };
+    if paths.is_empty() {
+        usage();
+    }
+    let addresses: Vec<&str> = addresses.split(',').collect();
+    let mut etherscan = Etherscan::new(&addresses);
+    for path in paths {
+        etherscan.read(&mut BufReader::new(File::open(path)?), path)?;
+    }
+    write_csv(&etherscan.records()?, stdout().lock())
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("convert") => convert(&args[1..]),
+        Some("diff") => diff_files(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("filter") => filter(&args[1..]),
+        Some("reclassify") => reclassify(&args[1..]),
+        #[cfg(feature = "server")]
+        Some("serve") => serve(&args[1..]),
+        Some("wallet") => wallet(&args[1..]),
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        exit(1);
+    }
+}
@@ -0,0 +1,215 @@
+//! Stablecoins assumed to trade 1:1 with a fiat currency.
+//!
+//! `Pegged` wraps a `PriceOracle` so a pegged asset is priced as its fiat
+//! currency, except within a configured de-peg range where the wrapped
+//! oracle's prices are used.
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::price::PriceOracle;
+use crate::time_utils::parse_time_ms;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Peg {
+    pub fiat: String,
+
+    /// Start, inclusive, and end, exclusive, of periods off the peg
+    pub depegs: Vec<(i64, i64)>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PegTable {
+    pegs: BTreeMap<String, Peg>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PegRow {
+    asset: String,
+    fiat: String,
+    depeg_start: Option<String>,
+    depeg_end: Option<String>,
+}
+
+impl PegTable {
+    pub fn new() -> PegTable {
+        PegTable::default()
+    }
+
+    /// Common USD stablecoins
+    pub fn with_defaults() -> PegTable {
+        let mut pegs = PegTable::new();
+        for asset in ["USDT", "USDC", "BUSD", "DAI", "TUSD", "USDP", "GUSD"] {
+            pegs.add(asset, "USD");
+        }
+        pegs
+    }
+
+    /// Read a table with the header `Asset,Fiat,DepegStart,DepegEnd`, an
+    /// asset with several de-peg ranges has a row for each.
+    pub fn from_csv<R: Read>(rdr: R) -> Result<PegTable, Error> {
+        let mut pegs = PegTable::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(rdr);
+        for (idx, row) in reader.deserialize().enumerate() {
+            let row: PegRow = row?;
+            let line = idx + 2;
+            pegs.add(&row.asset, &row.fiat);
+
+            let time = |s: &Option<String>| match s.as_deref() {
+                None | Some("") => Ok(None),
+                Some(s) => parse_time_ms(s).map(Some).ok_or_else(|| Error::Parse {
+                    line,
+                    msg: format!("Bad time {s}"),
+                }),
+            };
+            match (time(&row.depeg_start)?, time(&row.depeg_end)?) {
+                (Some(start), Some(end)) => pegs.add_depeg(&row.asset, start, end),
+                (None, None) => {}
+                _ => {
+                    return Err(Error::Parse {
+                        line,
+                        msg: "DepegStart and DepegEnd must both be set".to_owned(),
+                    })
+                }
+            }
+        }
+        Ok(pegs)
+    }
+
+    pub fn add(&mut self, asset: &str, fiat: &str) {
+        self.pegs.entry(asset.to_owned()).or_insert_with(|| Peg {
+            fiat: fiat.to_owned(),
+            depegs: Vec::new(),
+        });
+    }
+
+    pub fn add_depeg(&mut self, asset: &str, start: i64, end: i64) {
+        if let Some(peg) = self.pegs.get_mut(asset) {
+            peg.depegs.push((start, end));
+        }
+    }
+
+    pub fn get(&self, asset: &str) -> Option<&Peg> {
+        self.pegs.get(asset)
+    }
+
+    /// The fiat currency `asset` is pegged to at `time`
+    pub fn pegged_to(&self, asset: &str, time: i64) -> Option<&str> {
+        let peg = self.pegs.get(asset)?;
+        if peg
+            .depegs
+            .iter()
+            .any(|&(start, end)| start <= time && time < end)
+        {
+            None
+        } else {
+            Some(&peg.fiat)
+        }
+    }
+}
+
+pub struct Pegged<'a, O: PriceOracle + ?Sized> {
+    pub pegs: &'a PegTable,
+    pub oracle: &'a O,
+}
+
+impl<'a, O: PriceOracle + ?Sized> Pegged<'a, O> {
+    pub fn new(pegs: &'a PegTable, oracle: &'a O) -> Pegged<'a, O> {
+        Pegged { pegs, oracle }
+    }
+}
+
+impl<'a, O: PriceOracle + ?Sized> PriceOracle for Pegged<'a, O> {
+    fn price(&self, asset: &str, currency: &str, time: i64) -> Option<Decimal> {
+        let asset_fiat = self.pegs.pegged_to(asset, time).unwrap_or(asset);
+        let currency_fiat = self.pegs.pegged_to(currency, time).unwrap_or(currency);
+        if asset_fiat == currency_fiat {
+            Some(Decimal::ONE)
+        } else {
+            self.oracle
+                .price(asset_fiat, currency_fiat, time)
+                .or_else(|| self.oracle.price(asset, currency, time))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::tax_events;
+    use crate::jurisdiction::UnitedStates;
+    use crate::price::PriceTable;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_pegged() {
+        let mut pegs = PegTable::with_defaults();
+        pegs.add_depeg(
+            "USDC",
+            time_ms("2023-03-10 00:00:00"),
+            time_ms("2023-03-14 00:00:00"),
+        );
+        let mut pt = PriceTable::new();
+        pt.insert("USD", "EUR", 0, dec!(0.9));
+        pt.insert("USDC", "USD", time_ms("2023-03-11 00:00:00"), dec!(0.88));
+        let oracle = Pegged::new(&pegs, &pt);
+
+        let t = time_ms("2023-01-01 00:00:00");
+        assert_eq!(oracle.price("USDT", "USD", t), Some(dec!(1)));
+        assert_eq!(oracle.price("USD", "USDC", t), Some(dec!(1)));
+        assert_eq!(oracle.price("USDT", "BUSD", t), Some(dec!(1)));
+        assert_eq!(oracle.price("USDC", "EUR", t), Some(dec!(0.9)));
+        assert_eq!(oracle.price("ETH", "USD", t), None);
+
+        let t = time_ms("2023-03-12 00:00:00");
+        assert_eq!(pegs.pegged_to("USDC", t), None);
+        assert_eq!(oracle.price("USDC", "USD", t), Some(dec!(0.88)));
+        let t = time_ms("2023-03-14 00:00:00");
+        assert_eq!(oracle.price("USDC", "USD", t), Some(dec!(1)));
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let csv = "Asset,Fiat,DepegStart,DepegEnd
+USDC,USD,2023-03-10,2023-03-14
+USDC,USD,2023-05-01,2023-05-02
+EURS,EUR,,
+";
+        let pegs = PegTable::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(pegs.get("USDC").unwrap().depegs.len(), 2);
+        assert_eq!(pegs.get("EURS").unwrap().fiat, "EUR");
+        assert_eq!(pegs.get("USDT"), None);
+
+        let csv = "Asset,Fiat,DepegStart,DepegEnd\nUSDC,USD,2023-03-10,\n";
+        assert!(matches!(
+            PegTable::from_csv(csv.as_bytes()),
+            Err(Error::Parse { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_trade_valued_by_peg() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,2000,USDT,,,binance.us,,,2021-01-01 00:00:00
+",
+        );
+        let pegs = PegTable::with_defaults();
+        let pt = PriceTable::new();
+        assert!(tax_events(&recs, &UnitedStates::new(), &pt).is_err());
+
+        let events = tax_events(&recs, &UnitedStates::new(), &Pegged::new(&pegs, &pt)).unwrap();
+        assert_eq!(events[0].asset, "ETH");
+        assert_eq!(events[0].value, dec!(2000));
+        assert_eq!(events[1].asset, "USDT");
+        assert_eq!(events[1].value, dec!(2000));
+    }
+}
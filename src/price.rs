@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+
+pub trait PriceOracle {
+    /// Price of one unit of `asset` in `currency` at `time`, if known
+    fn price(&self, asset: &str, currency: &str, time: i64) -> Option<Decimal>;
+}
+
+/// In memory prices, a lookup returns the most recent price at or
+/// before the requested time.
+#[derive(Clone, Debug, Default)]
+pub struct PriceTable {
+    prices: HashMap<(String, String), Vec<(i64, Decimal)>>,
+}
+
+impl PriceTable {
+    pub fn new() -> PriceTable {
+        PriceTable::default()
+    }
+
+    pub fn insert(&mut self, asset: &str, currency: &str, time: i64, price: Decimal) {
+        let series = self
+            .prices
+            .entry((asset.to_owned(), currency.to_owned()))
+            .or_default();
+        match series.binary_search_by_key(&time, |(t, _)| *t) {
+            Ok(idx) => series[idx].1 = price,
+            Err(idx) => series.insert(idx, (time, price)),
+        }
+    }
+}
+
+impl PriceOracle for PriceTable {
+    fn price(&self, asset: &str, currency: &str, time: i64) -> Option<Decimal> {
+        let series = self.prices.get(&(asset.to_owned(), currency.to_owned()))?;
+        let idx = series.partition_point(|(t, _)| *t <= time);
+        if idx == 0 {
+            None
+        } else {
+            Some(series[idx - 1].1)
+        }
+    }
+}
+
+/// Value of `amount` of `asset` in `currency` at `time`
+pub fn value_of<O: PriceOracle + ?Sized>(
+    oracle: &O,
+    amount: Decimal,
+    asset: &str,
+    currency: &str,
+    time: i64,
+) -> Result<Decimal, Error> {
+    if asset == currency {
+        return Ok(amount);
+    }
+
+    match oracle.price(asset, currency, time) {
+        Some(price) => Ok(amount * price),
+        None => Err(Error::MissingPrice {
+            asset: asset.to_owned(),
+            currency: currency.to_owned(),
+            time,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_price_table() {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 2000, dec!(3000));
+        pt.insert("ETH", "USD", 1000, dec!(2000));
+
+        assert_eq!(pt.price("ETH", "USD", 999), None);
+        assert_eq!(pt.price("ETH", "USD", 1000), Some(dec!(2000)));
+        assert_eq!(pt.price("ETH", "USD", 1999), Some(dec!(2000)));
+        assert_eq!(pt.price("ETH", "USD", 5000), Some(dec!(3000)));
+        assert_eq!(pt.price("USD", "ETH", 5000), None);
+
+        pt.insert("ETH", "USD", 2000, dec!(3100));
+        assert_eq!(pt.price("ETH", "USD", 2000), Some(dec!(3100)));
+    }
+
+    #[test]
+    fn test_value_of() {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(2000));
+
+        assert_eq!(value_of(&pt, dec!(2), "USD", "USD", 0).unwrap(), dec!(2));
+        assert_eq!(value_of(&pt, dec!(2), "ETH", "USD", 0).unwrap(), dec!(4000));
+        assert!(matches!(
+            value_of(&pt, dec!(2), "BTC", "USD", 0),
+            Err(Error::MissingPrice { .. })
+        ));
+    }
+}
@@ -0,0 +1,561 @@
+//! A local store of OHLCV bars imported from price files.
+//!
+//! Lookups between bars are filled using the store's `GapFill` strategy
+//! and a price whose bars are further away than the staleness limit is
+//! flagged as stale, which the `PriceOracle` implementation rejects.
+use std::collections::HashMap;
+use std::io::Read;
+
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::price::PriceOracle;
+use crate::time_utils::parse_time_ms;
+use crate::TokenTaxRec;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OhlcvLayout {
+    /// Binance kline files, no header: open time, open, high, low, close,
+    /// volume, ... Spot files have times in µs since 2025.
+    Binance,
+
+    /// CryptoDataDownload files, a url line then a header of unix, date,
+    /// symbol, open, high, low, close, volumes
+    CryptoDataDownload,
+
+    /// CoinGecko exports: snapped_at, price, market_cap, total_volume
+    CoinGecko,
+
+    /// A header naming the time, open, high, low, close and volume columns
+    Generic,
+}
+
+/// The layout called `name`, ignoring case
+pub fn ohlcv_layout(name: &str) -> Option<OhlcvLayout> {
+    match name.to_lowercase().as_str() {
+        "binance" => Some(OhlcvLayout::Binance),
+        "cryptodatadownload" => Some(OhlcvLayout::CryptoDataDownload),
+        "coingecko" => Some(OhlcvLayout::CoinGecko),
+        "generic" => Some(OhlcvLayout::Generic),
+        _ => None,
+    }
+}
+
+/// Names of the layouts `ohlcv_layout` knows
+pub const OHLCV_LAYOUTS: &[&str] = &["binance", "cryptodatadownload", "coingecko", "generic"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bar {
+    /// Open time
+    pub time: i64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GapFill {
+    /// Close of the last bar before the time
+    Previous,
+
+    /// Close of the bar nearest the time
+    Nearest,
+
+    /// Linear interpolation between the closes of the surrounding bars
+    Interpolate,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PriceQuality {
+    /// A bar covers the time
+    Exact,
+
+    /// No bar covers the time so it was filled from nearby bars
+    Filled,
+
+    /// Filled from bars further away than the staleness limit
+    Stale,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriceLookup {
+    pub price: Decimal,
+    pub quality: PriceQuality,
+
+    /// Open times of the bars used
+    pub bar_times: Vec<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Series {
+    bars: Vec<Bar>,
+
+    /// Smallest gap between bars, the period each bar covers
+    interval: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PriceStore {
+    series: HashMap<(String, String), Series>,
+    pub gap_fill: GapFill,
+
+    /// Maximum distance, in ms, from the time to a bar used for filling
+    pub staleness_limit: i64,
+}
+
+impl PriceStore {
+    pub fn new() -> PriceStore {
+        PriceStore {
+            series: HashMap::new(),
+            gap_fill: GapFill::Previous,
+            staleness_limit: 7 * DAY_MS,
+        }
+    }
+
+    pub fn insert(&mut self, asset: &str, currency: &str, bar: Bar) {
+        let series = self
+            .series
+            .entry((asset.to_owned(), currency.to_owned()))
+            .or_default();
+        let idx = match series.bars.binary_search_by_key(&bar.time, |b| b.time) {
+            Ok(idx) => {
+                series.bars[idx] = bar;
+                return;
+            }
+            Err(idx) => {
+                series.bars.insert(idx, bar);
+                idx
+            }
+        };
+
+        // A new bar only splits a gap, so the interval can only shrink to
+        // one of the gaps to its neighbours
+        let bars = &series.bars;
+        let time = bars[idx].time;
+        let gap = [
+            idx.checked_sub(1).map(|prev| time - bars[prev].time),
+            bars.get(idx + 1).map(|next| next.time - time),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        series.interval = match (bars.len(), gap) {
+            (2, Some(gap)) => gap,
+            (_, Some(gap)) => series.interval.min(gap),
+            (_, None) => DAY_MS,
+        };
+    }
+
+    pub fn bars(&self, asset: &str, currency: &str) -> &[Bar] {
+        self.series
+            .get(&(asset.to_owned(), currency.to_owned()))
+            .map_or(&[], |s| s.bars.as_slice())
+    }
+
+    /// Import bars for `asset` priced in `currency`, returning the number
+    /// of bars imported
+    pub fn import<R: Read>(
+        &mut self,
+        mut rdr: R,
+        layout: OhlcvLayout,
+        asset: &str,
+        currency: &str,
+    ) -> Result<usize, Error> {
+        let mut text = String::new();
+        rdr.read_to_string(&mut text)?;
+
+        let mut first_line = 1;
+        let mut body = text.as_str();
+        if layout == OhlcvLayout::CryptoDataDownload {
+            body = body.split_once('\n').map_or("", |(_, rest)| rest);
+            first_line = 2;
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(layout != OhlcvLayout::Binance)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(body.as_bytes());
+
+        let columns = match layout {
+            OhlcvLayout::Binance => Columns {
+                time: 0,
+                open: Some(1),
+                high: Some(2),
+                low: Some(3),
+                close: 4,
+                volume: Some(5),
+            },
+            _ => Columns::from_headers(reader.headers()?).ok_or_else(|| Error::Parse {
+                line: first_line,
+                msg: "Missing time or close column".to_owned(),
+            })?,
+        };
+
+        let mut count = 0;
+        for (idx, record) in reader.records().enumerate() {
+            let record = record?;
+            let line = first_line + idx + usize::from(layout != OhlcvLayout::Binance);
+            let bar = columns.bar(&record).ok_or_else(|| Error::Parse {
+                line,
+                msg: format!("Bad bar {:?}", record),
+            })?;
+            self.insert(asset, currency, bar);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Price of `asset` in `currency` at `time` and how it was found
+    pub fn lookup(&self, asset: &str, currency: &str, time: i64) -> Option<PriceLookup> {
+        let series = self.series.get(&(asset.to_owned(), currency.to_owned()))?;
+        let bars = &series.bars;
+
+        let idx = bars.partition_point(|b| b.time <= time);
+        let prev = if idx > 0 { Some(&bars[idx - 1]) } else { None };
+        let next = bars.get(idx);
+
+        if let Some(prev) = prev {
+            if time < prev.time + series.interval {
+                return Some(PriceLookup {
+                    price: prev.close,
+                    quality: PriceQuality::Exact,
+                    bar_times: vec![prev.time],
+                });
+            }
+        }
+
+        let used: Vec<&Bar> = match (self.gap_fill, prev, next) {
+            (_, None, None) => return None,
+            (GapFill::Previous, None, _) => return None,
+            (GapFill::Previous, Some(prev), _) => vec![prev],
+            (_, Some(prev), None) => vec![prev],
+            (_, None, Some(next)) => vec![next],
+            (GapFill::Nearest, Some(prev), Some(next)) => {
+                if time - prev.time <= next.time - time {
+                    vec![prev]
+                } else {
+                    vec![next]
+                }
+            }
+            (GapFill::Interpolate, Some(prev), Some(next)) => vec![prev, next],
+        };
+
+        let price = if used.len() == 2 {
+            let (prev, next) = (used[0], used[1]);
+            let frac = Decimal::from(time - prev.time) / Decimal::from(next.time - prev.time);
+            prev.close + (next.close - prev.close) * frac
+        } else {
+            used[0].close
+        };
+        let stale = used
+            .iter()
+            .any(|b| (time - b.time).abs() > self.staleness_limit);
+
+        Some(PriceLookup {
+            price,
+            quality: if stale {
+                PriceQuality::Stale
+            } else {
+                PriceQuality::Filled
+            },
+            bar_times: used.iter().map(|b| b.time).collect(),
+        })
+    }
+}
+
+impl Default for PriceStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriceOracle for PriceStore {
+    fn price(&self, asset: &str, currency: &str, time: i64) -> Option<Decimal> {
+        match self.lookup(asset, currency, time) {
+            Some(l) if l.quality != PriceQuality::Stale => Some(l.price),
+            _ => None,
+        }
+    }
+}
+
+struct Columns {
+    time: usize,
+    open: Option<usize>,
+    high: Option<usize>,
+    low: Option<usize>,
+    close: usize,
+    volume: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &csv::StringRecord) -> Option<Columns> {
+        let find = |names: &[&str]| {
+            headers
+                .iter()
+                .position(|h| names.contains(&h.to_lowercase().as_str()))
+        };
+        let volume = headers
+            .iter()
+            .position(|h| h.to_lowercase().starts_with("volume"))
+            .or_else(|| find(&["total_volume"]));
+        Some(Columns {
+            time: find(&[
+                "unix",
+                "timestamp",
+                "time",
+                "open_time",
+                "snapped_at",
+                "date",
+            ])?,
+            open: find(&["open"]),
+            high: find(&["high"]),
+            low: find(&["low"]),
+            close: find(&["close", "price"])?,
+            volume,
+        })
+    }
+
+    fn bar(&self, record: &csv::StringRecord) -> Option<Bar> {
+        let dec = |idx: usize| Decimal::from_str(record.get(idx)?).ok();
+        let close = dec(self.close)?;
+        Some(Bar {
+            time: parse_time_ms(record.get(self.time)?)?,
+            open: self.open.map_or(Some(close), dec)?,
+            high: self.high.map_or(Some(close), dec)?,
+            low: self.low.map_or(Some(close), dec)?,
+            close,
+            volume: self.volume.and_then(dec).unwrap_or_default(),
+        })
+    }
+}
+
+/// A record valued with a filled or stale price, or with no price
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValuationIssue {
+    pub rec_idx: usize,
+    pub currency: String,
+
+    /// None if no price was found
+    pub quality: Option<PriceQuality>,
+
+    pub bar_times: Vec<i64>,
+}
+
+/// List every record whose buy, sell or fee currency was valued in
+/// `home_currency` using a filled or stale price, or couldn't be valued
+pub fn valuation_report(
+    recs: &[TokenTaxRec],
+    store: &PriceStore,
+    home_currency: &str,
+) -> Vec<ValuationIssue> {
+    let mut issues = Vec::new();
+    for (rec_idx, rec) in recs.iter().enumerate() {
+        let mut currencies: Vec<String> = Vec::new();
+        for leg in [rec.buy_leg(), rec.sell_leg(), rec.fee_leg()]
+            .into_iter()
+            .flatten()
+        {
+            if leg.currency != home_currency && !currencies.contains(&leg.currency) {
+                currencies.push(leg.currency);
+            }
+        }
+        for currency in currencies {
+            let lookup = store.lookup(&currency, home_currency, rec.time);
+            let quality = lookup.as_ref().map(|l| l.quality);
+            if quality == Some(PriceQuality::Exact) {
+                continue;
+            }
+            issues.push(ValuationIssue {
+                rec_idx,
+                currency,
+                quality,
+                bar_times: lookup.map(|l| l.bar_times).unwrap_or_default(),
+            });
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_import_layouts() {
+        let mut store = PriceStore::new();
+
+        let binance = "1609459200000,29000.0,29600.0,28800.0,29400.0,100.5,1609545599999,0,0,0,0,0
+1609545600000000,29400.0,33000.0,29000.0,32200.0,200.5,1609631999999999,0,0,0,0,0
+";
+        assert_eq!(ohlcv_layout("Binance"), Some(OhlcvLayout::Binance));
+        assert_eq!(ohlcv_layout("kraken"), None);
+        let n = store
+            .import(binance.as_bytes(), OhlcvLayout::Binance, "BTC", "USDT")
+            .unwrap();
+        assert_eq!(n, 2);
+        let bars = store.bars("BTC", "USDT");
+        assert_eq!(bars[0].time, time_ms("2021-01-01 00:00:00"));
+        assert_eq!(bars[1].time, time_ms("2021-01-02 00:00:00"));
+        assert_eq!(bars[1].close, dec!(32200));
+        assert_eq!(bars[1].volume, dec!(200.5));
+
+        let cdd = "https://www.CryptoDataDownload.com
+unix,date,symbol,open,high,low,close,Volume ETH,Volume USD
+1609545600,2021-01-02 00:00:00,ETH/USD,730,790,715,775,1000,775000
+1609459200,2021-01-01 00:00:00,ETH/USD,737,749,714,730,1200,876000
+";
+        let n = store
+            .import(
+                cdd.as_bytes(),
+                OhlcvLayout::CryptoDataDownload,
+                "ETH",
+                "USD",
+            )
+            .unwrap();
+        assert_eq!(n, 2);
+        let bars = store.bars("ETH", "USD");
+        assert_eq!(bars[0].close, dec!(730));
+        assert_eq!(bars[1].time, time_ms("2021-01-02 00:00:00"));
+
+        let gecko = "snapped_at,price,market_cap,total_volume
+2021-01-01 00:00:00 UTC,0.2,1000,100
+";
+        store
+            .import(gecko.as_bytes(), OhlcvLayout::CoinGecko, "XRP", "USD")
+            .unwrap();
+        let bar = &store.bars("XRP", "USD")[0];
+        assert_eq!(bar.open, dec!(0.2));
+        assert_eq!(bar.volume, dec!(100));
+
+        let generic = "Date,Open,High,Low,Close,Volume
+2021-01-01,1,2,0.5,1.5,10
+";
+        store
+            .import(generic.as_bytes(), OhlcvLayout::Generic, "ADA", "USD")
+            .unwrap();
+        assert_eq!(store.bars("ADA", "USD")[0].low, dec!(0.5));
+
+        let bad = "Date,Open\n2021-01-01,1\n";
+        assert!(matches!(
+            store.import(bad.as_bytes(), OhlcvLayout::Generic, "X", "USD"),
+            Err(Error::Parse { line: 1, .. })
+        ));
+        let bad = "Date,Close\n2021-01-01,1\nxyz,2\n";
+        assert!(matches!(
+            store.import(bad.as_bytes(), OhlcvLayout::Generic, "X", "USD"),
+            Err(Error::Parse { line: 3, .. })
+        ));
+    }
+
+    fn daily(store: &mut PriceStore, day: &str, close: Decimal) {
+        store.insert(
+            "ETH",
+            "USD",
+            Bar {
+                time: time_ms(&format!("{day} 00:00:00")),
+                open: close,
+                high: close,
+                low: close,
+                close,
+                volume: dec!(0),
+            },
+        );
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut store = PriceStore::new();
+        daily(&mut store, "2021-01-10", dec!(100));
+        assert_eq!(store.series[&("ETH".into(), "USD".into())].interval, DAY_MS);
+        daily(&mut store, "2021-01-01", dec!(100));
+        daily(&mut store, "2021-01-04", dec!(100));
+        daily(&mut store, "2021-01-05", dec!(100));
+        daily(&mut store, "2021-01-04", dec!(120));
+        assert_eq!(store.series[&("ETH".into(), "USD".into())].interval, DAY_MS);
+
+        // Three days between bars with nothing closer
+        let mut store = PriceStore::new();
+        daily(&mut store, "2021-01-07", dec!(100));
+        daily(&mut store, "2021-01-01", dec!(100));
+        daily(&mut store, "2021-01-04", dec!(100));
+        let l = store
+            .lookup("ETH", "USD", time_ms("2021-01-06 00:00:00"))
+            .unwrap();
+        assert_eq!(l.quality, PriceQuality::Exact);
+    }
+
+    #[test]
+    fn test_gap_fill() {
+        let mut store = PriceStore::new();
+        daily(&mut store, "2021-01-01", dec!(100));
+        daily(&mut store, "2021-01-02", dec!(110));
+        daily(&mut store, "2021-01-06", dec!(150));
+        daily(&mut store, "2021-03-01", dec!(300));
+
+        let l = store
+            .lookup("ETH", "USD", time_ms("2021-01-02 12:00:00"))
+            .unwrap();
+        assert_eq!(l.quality, PriceQuality::Exact);
+        assert_eq!(l.price, dec!(110));
+
+        let t = time_ms("2021-01-05 00:00:00");
+        let l = store.lookup("ETH", "USD", t).unwrap();
+        assert_eq!(l.quality, PriceQuality::Filled);
+        assert_eq!(l.price, dec!(110));
+
+        store.gap_fill = GapFill::Nearest;
+        assert_eq!(store.lookup("ETH", "USD", t).unwrap().price, dec!(150));
+
+        store.gap_fill = GapFill::Interpolate;
+        let l = store.lookup("ETH", "USD", t).unwrap();
+        assert_eq!(l.price, dec!(140));
+        assert_eq!(l.bar_times.len(), 2);
+
+        let t = time_ms("2021-02-01 00:00:00");
+        assert_eq!(
+            store.lookup("ETH", "USD", t).unwrap().quality,
+            PriceQuality::Stale
+        );
+        assert_eq!(store.price("ETH", "USD", t), None);
+
+        store.gap_fill = GapFill::Previous;
+        assert_eq!(
+            store.lookup("ETH", "USD", time_ms("2020-12-31 00:00:00")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_valuation_report() {
+        let mut store = PriceStore::new();
+        daily(&mut store, "2021-01-01", dec!(100));
+        daily(&mut store, "2021-01-02", dec!(110));
+
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1,ETH,100,USD,0.001,ETH,kraken,,,2021-01-01 10:00:00
+Trade,1,ETH,100,USD,,,kraken,,,2021-01-05 00:00:00
+Trade,1,ETH,100,USD,,,kraken,,,2021-02-05 00:00:00
+Income,1,BNB,,,,,kraken,,,2021-01-01 00:00:00
+",
+        );
+        let issues = valuation_report(&recs, &store, "USD");
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[0].rec_idx, 1);
+        assert_eq!(issues[0].quality, Some(PriceQuality::Filled));
+        assert_eq!(issues[1].rec_idx, 2);
+        assert_eq!(issues[1].quality, Some(PriceQuality::Stale));
+        assert_eq!(issues[2].rec_idx, 3);
+        assert_eq!(issues[2].currency, "BNB");
+        assert_eq!(issues[2].quality, None);
+    }
+}
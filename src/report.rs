@@ -0,0 +1,195 @@
+//! Realized gains for any jurisdiction, using the jurisdiction's cost
+//! basis, holding period and tax year.
+use std::collections::BTreeMap;
+
+use rust_decimal::prelude::*;
+use serde::Serialize;
+
+use crate::acb::AcbCalculator;
+use crate::error::Error;
+use crate::events::tax_events;
+use crate::jurisdiction::{CostBasis, Jurisdiction};
+use crate::lots::LotEngine;
+use crate::price::PriceOracle;
+use crate::uk::UkCalculator;
+use crate::TokenTaxRec;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Realized {
+    pub asset: String,
+    pub quantity: Decimal,
+    pub proceeds: Decimal,
+
+    /// Cost including fees and any adjustments such as denied losses
+    pub cost: Decimal,
+
+    /// None when the cost came from a pool
+    pub acquired_time: Option<i64>,
+
+    pub disposed_time: i64,
+    pub long_term: bool,
+    pub tax_year: i32,
+    pub rec_idx: usize,
+}
+
+impl Realized {
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.cost
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct YearGains {
+    pub proceeds: Decimal,
+    pub cost: Decimal,
+    pub short_term_gain: Decimal,
+    pub long_term_gain: Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GainsReport {
+    pub realized: Vec<Realized>,
+    pub years: BTreeMap<i32, YearGains>,
+}
+
+pub fn realized_gains<J: Jurisdiction + ?Sized, O: PriceOracle + ?Sized>(
+    recs: &[TokenTaxRec],
+    jurisdiction: &J,
+    oracle: &O,
+) -> Result<GainsReport, Error> {
+    let realized: Vec<Realized> = match jurisdiction.cost_basis() {
+        CostBasis::Lots(method) => {
+            let events = tax_events(recs, jurisdiction, oracle)?;
+            LotEngine::new(method)
+                .process(&events)?
+                .into_iter()
+                .map(|lot| Realized {
+                    long_term: jurisdiction
+                        .long_term_months()
+                        .is_some_and(|months| lot.held_longer_than(months)),
+                    tax_year: jurisdiction.tax_year(lot.disposed_time),
+                    cost: lot.cost + lot.fee,
+                    proceeds: lot.proceeds,
+                    acquired_time: Some(lot.acquired_time),
+                    disposed_time: lot.disposed_time,
+                    quantity: lot.quantity,
+                    rec_idx: lot.rec_idx,
+                    asset: lot.asset,
+                })
+                .collect()
+        }
+        CostBasis::AverageCost => AcbCalculator::with_jurisdiction(jurisdiction)
+            .calculate(recs, oracle)?
+            .rows
+            .into_iter()
+            .map(|row| Realized {
+                tax_year: jurisdiction.tax_year(row.time),
+                cost: row.acb + row.outlays - row.superficial_loss,
+                proceeds: row.proceeds,
+                acquired_time: None,
+                disposed_time: row.time,
+                long_term: false,
+                quantity: row.quantity,
+                rec_idx: row.rec_idx,
+                asset: row.asset,
+            })
+            .collect(),
+        CostBasis::SharePooling => UkCalculator::with_jurisdiction(jurisdiction)
+            .calculate(recs, oracle)?
+            .disposals
+            .into_iter()
+            .map(|d| Realized {
+                tax_year: jurisdiction.tax_year(d.time()),
+                cost: d.allowable_cost(),
+                proceeds: d.proceeds,
+                acquired_time: None,
+                disposed_time: d.time(),
+                long_term: false,
+                quantity: d.quantity,
+                rec_idx: d.rec_idxs[0],
+                asset: d.asset,
+            })
+            .collect(),
+    };
+
+    let mut report = GainsReport::default();
+    for r in realized {
+        let year = report.years.entry(r.tax_year).or_default();
+        year.proceeds += r.proceeds;
+        year.cost += r.cost;
+        if r.long_term {
+            year.long_term_gain += r.gain();
+        } else {
+            year.short_term_gain += r.gain();
+        }
+        report.realized.push(r);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jurisdiction::{jurisdiction, Canada, UnitedKingdom};
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    const CSV: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,2,ETH,2000,USD,,,kraken,,,2020-01-01 00:00:00
+Trade,1,ETH,1000,USD,,,kraken,,,2021-01-01 00:00:00
+Trade,3000,USD,1,ETH,,,kraken,,,2021-06-01 00:00:00
+Gift,,,1,ETH,,,,,,2021-07-01 00:00:00
+Trade,1500,USD,1,ETH,,,kraken,,,2021-08-01 00:00:00
+";
+
+    #[test]
+    fn test_realized_gains_lots() {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(2000));
+
+        let us = jurisdiction("US").unwrap();
+        let report = realized_gains(&recs_from_csv(CSV), us.as_ref(), &pt).unwrap();
+
+        // The gift removes the second 2020 ETH without a gain
+        assert_eq!(report.realized.len(), 2);
+        assert!(report.realized[0].long_term);
+        assert_eq!(report.realized[0].gain(), dec!(2000));
+        assert!(!report.realized[1].long_term);
+        assert_eq!(report.realized[1].gain(), dec!(500));
+        assert_eq!(report.realized[1].rec_idx, 4);
+        assert_eq!(
+            report.years[&2021],
+            YearGains {
+                proceeds: dec!(4500),
+                cost: dec!(2000),
+                short_term_gain: dec!(500),
+                long_term_gain: dec!(2000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_realized_gains_pools() {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(2000));
+
+        let mut ca = Canada::new();
+        ca.home_currency = "USD".to_owned();
+        let report = realized_gains(&recs_from_csv(CSV), &ca, &pt).unwrap();
+
+        // Average cost is 1000 and the gift is a disposal at market value
+        assert_eq!(report.realized.len(), 3);
+        assert_eq!(report.realized[0].acquired_time, None);
+        assert_eq!(report.years[&2021].short_term_gain, dec!(3500));
+
+        let mut uk = UnitedKingdom::new();
+        uk.home_currency = "USD".to_owned();
+        let report = realized_gains(&recs_from_csv(CSV), &uk, &pt).unwrap();
+        assert_eq!(report.realized.len(), 3);
+        assert_eq!(report.realized[0].tax_year, 2021);
+        assert_eq!(report.years[&2021].short_term_gain, dec!(3500));
+    }
+}
@@ -0,0 +1,337 @@
+//! Reclassification of records with rules read from a toml file, e.g.
+//!
+//! ```toml
+//! [[rule]]
+//! name = "coinbase staking"
+//! when = "type = Deposit and exchange = coinbase"
+//! comment = "(?i)staking reward"
+//! set_type = "Income"
+//!
+//! [[rule]]
+//! name = "card spends"
+//! comment = "^Card purchase: (.*)$"
+//! set_type = "Spend"
+//! set_comment = "$1"
+//! ```
+//!
+//! A rule matches a record if its `when` filter expression, see
+//! `crate::filter`, and its `comment` regex both match. It then sets any of
+//! `set_type`, `set_exchange`, `set_group` and `set_comment`, `set_group`
+//! is `margin` or empty and `set_comment` may refer to the groups captured
+//! by `comment`. Rules are applied in order so a rule sees the changes made
+//! by the rules before it.
+use std::fmt::Display;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::audit::AuditKind;
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::{FieldChange, GroupType, TokenTaxRec, TokenTaxRecType};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDef {
+    name: Option<String>,
+    when: Option<String>,
+    comment: Option<String>,
+    set_type: Option<String>,
+    set_exchange: Option<String>,
+    set_group: Option<String>,
+    set_comment: Option<String>,
+}
+
+pub struct Rule {
+    pub name: String,
+    when: Option<Filter>,
+    comment: Option<Regex>,
+    set_type: Option<TokenTaxRecType>,
+    set_exchange: Option<String>,
+    set_group: Option<Option<GroupType>>,
+    set_comment: Option<String>,
+}
+
+impl Rule {
+    fn from_def(idx: usize, def: RuleDef) -> Result<Rule, Error> {
+        let rule_error = |msg: String| Error::Rule { rule: idx + 1, msg };
+        let rule = Rule {
+            name: def.name.unwrap_or_else(|| format!("rule {}", idx + 1)),
+            when: def
+                .when
+                .map(|w| Filter::parse(&w))
+                .transpose()
+                .map_err(|e| rule_error(format!("when: {e}")))?,
+            comment: def
+                .comment
+                .map(|c| Regex::new(&c))
+                .transpose()
+                .map_err(|e| rule_error(format!("comment: {e}")))?,
+            set_type: def
+                .set_type
+                .map(|t| TokenTaxRecType::from_str_ignore_case(&t))
+                .transpose()
+                .map_err(|e| rule_error(format!("set_type: {e}")))?,
+            set_exchange: def.set_exchange,
+            set_group: match def.set_group.as_deref() {
+                None => None,
+                Some("") => Some(None),
+                Some("margin") => Some(Some(GroupType::Margin)),
+                Some(g) => return Err(rule_error(format!("set_group: Unknown group {g}"))),
+            },
+            set_comment: def.set_comment,
+        };
+        if rule.set_type.is_none()
+            && rule.set_exchange.is_none()
+            && rule.set_group.is_none()
+            && rule.set_comment.is_none()
+        {
+            return Err(rule_error("Nothing to set".to_owned()));
+        }
+        Ok(rule)
+    }
+
+    /// Apply the rule to `rec`, returns true if it matched
+    pub fn apply(&self, rec: &mut TokenTaxRec) -> bool {
+        if let Some(when) = &self.when {
+            if !when.matches(rec) {
+                return false;
+            }
+        }
+        let captures = match &self.comment {
+            Some(re) => match re.captures(&rec.comment) {
+                Some(captures) => Some(captures),
+                None => return false,
+            },
+            None => None,
+        };
+        let comment = self.set_comment.as_ref().map(|template| match &captures {
+            Some(captures) => {
+                let mut comment = String::new();
+                captures.expand(template, &mut comment);
+                comment
+            }
+            None => template.clone(),
+        });
+
+        if let Some(t) = &self.set_type {
+            rec.type_txs = t.clone();
+        }
+        if let Some(exchange) = &self.set_exchange {
+            rec.exchange = exchange.clone();
+        }
+        if let Some(group) = &self.set_group {
+            rec.group = group.clone();
+        }
+        if let Some(comment) = comment {
+            rec.comment = comment;
+        }
+        true
+    }
+}
+
+/// The changes a rule made to a record
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleChange {
+    pub rec_idx: usize,
+    pub rule: String,
+    pub changes: Vec<FieldChange>,
+}
+
+impl Display for RuleChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Record {} [{}]:", self.rec_idx, self.rule)?;
+        for (idx, change) in self.changes.iter().enumerate() {
+            let sep = if idx == 0 { " " } else { ", " };
+            write!(f, "{sep}{change}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet::default()
+    }
+
+    pub fn parse(s: &str) -> Result<RuleSet, Error> {
+        let file: RulesFile = toml::from_str(s).map_err(|e| Error::Parse {
+            line: e
+                .span()
+                .map_or(0, |span| s[..span.start].matches('\n').count() + 1),
+            msg: e.message().to_owned(),
+        })?;
+        let rules = file
+            .rule
+            .into_iter()
+            .enumerate()
+            .map(|(idx, def)| Rule::from_def(idx, def))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RuleSet { rules })
+    }
+
+    /// Apply the rules to `recs`, returns the changes each rule made.
+    /// Matching rules that changed nothing aren't reported. The changes are
+    /// also recorded in each record's provenance. A rule that gives a
+    /// record a type its legs don't fit, e.g. a Deposit made a Spend, is an
+    /// error and leaves `recs` unchanged.
+    pub fn apply(&self, recs: &mut [TokenTaxRec]) -> Result<Vec<RuleChange>, Error> {
+        let mut edited = recs.to_vec();
+        let changes = self.apply_all(&mut edited)?;
+        recs.clone_from_slice(&edited);
+        Ok(changes)
+    }
+
+    /// The changes `apply` would make, leaving `recs` untouched
+    pub fn dry_run(&self, recs: &[TokenTaxRec]) -> Result<Vec<RuleChange>, Error> {
+        self.apply_all(&mut recs.to_vec())
+    }
+
+    /// Apply the rules to `recs` stopping at the first error
+    fn apply_all(&self, recs: &mut [TokenTaxRec]) -> Result<Vec<RuleChange>, Error> {
+        let mut changes = Vec::new();
+        for (rec_idx, rec) in recs.iter_mut().enumerate() {
+            for (idx, rule) in self.rules.iter().enumerate() {
+                let kind = AuditKind::Reclassification;
+                let type_txs = rec.type_txs.clone();
+                if rec.edit(kind, Some(&rule.name), |rec| {
+                    rule.apply(rec);
+                }) {
+                    if rec.type_txs != type_txs {
+                        rec.validate(rec_idx).map_err(|e| Error::Rule {
+                            rule: idx + 1,
+                            msg: format!("set_type {}: {e}", rec.type_txs),
+                        })?;
+                    }
+                    let step = rec.provenance.steps.last().expect("SNH");
+                    changes.push(RuleChange {
+                        rec_idx,
+                        rule: rule.name.clone(),
+                        changes: step.changes.clone(),
+                    });
+                }
+            }
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::recs_from_csv;
+
+    const RULES: &str = r#"
+[[rule]]
+name = "coinbase staking"
+when = "type = Deposit and exchange = coinbase"
+comment = "(?i)staking reward"
+set_type = "Income"
+
+[[rule]]
+name = "card spends"
+comment = "^Card purchase: (.*)$"
+set_type = "Spend"
+set_comment = "Card: $1"
+
+[[rule]]
+when = "exchange = cb"
+set_exchange = "coinbase"
+"#;
+
+    fn recs() -> Vec<TokenTaxRec> {
+        recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,0.01,ETH,,,,,coinbase,,\"ETH Staking Reward\",2022-01-01 00:00:00
+Deposit,1,ETH,,,,,coinbase,,,2022-01-02 00:00:00
+Withdrawal,,,50,USD,,,cb,,\"Card purchase: Coffee shop\",2022-01-03 00:00:00
+Deposit,0.01,ETH,,,,,binance.us,,\"staking reward\",2022-01-04 00:00:00
+",
+        )
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let rules = RuleSet::parse(RULES).unwrap();
+        let recs = recs();
+        let changes = rules.dry_run(&recs).unwrap();
+        assert_eq!(recs, self::recs());
+
+        let changes: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "Record 0 [coinbase staking]: type Deposit -> Income",
+                "Record 2 [card spends]: type Withdrawal -> Spend, comment Card purchase: Coffee shop -> Card: Coffee shop",
+                "Record 2 [rule 3]: exchange cb -> coinbase",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let rules = RuleSet::parse(RULES).unwrap();
+        let mut recs = recs();
+        assert_eq!(rules.apply(&mut recs).unwrap().len(), 3);
+        assert_eq!(recs[0].type_txs, TokenTaxRecType::Income);
+        assert_eq!(recs[1].type_txs, TokenTaxRecType::Deposit);
+        assert_eq!(recs[2].type_txs, TokenTaxRecType::Spend);
+        assert_eq!(recs[2].exchange, "coinbase");
+        assert_eq!(recs[3].type_txs, TokenTaxRecType::Deposit);
+
+        // Already applied so nothing changes
+        assert_eq!(rules.apply(&mut recs).unwrap(), vec![]);
+
+        // A Deposit has no sell leg to spend, no record is changed
+        let rules = RuleSet::parse(
+            "[[rule]]\nset_comment = \"Checked\"\n\n\
+             [[rule]]\nwhen = \"exchange = binance.us\"\nset_type = \"spend\"",
+        )
+        .unwrap();
+        let before = recs.clone();
+        assert_eq!(
+            rules.apply(&mut recs).unwrap_err().to_string(),
+            "Rule 2: set_type Spend: Record 3: missing sell_amount"
+        );
+        assert_eq!(recs, before);
+        assert!(recs
+            .iter()
+            .zip(before.iter())
+            .all(|(r, b)| r.provenance == b.provenance));
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        let err = |s: &str| RuleSet::parse(s).err().unwrap().to_string();
+        assert_eq!(
+            err("[[rule]]\nwhen = \"asset = ETH\""),
+            "Rule 1: Nothing to set"
+        );
+        assert_eq!(
+            err("[[rule]]\nset_type = \"Income\"\n[[rule]]\nset_type = \"Swap\""),
+            "Rule 2: set_type: Unknown record type Swap"
+        );
+        assert!(err("[[rule]]\nwhen = \"asset ETH\"\nset_type = \"Income\"")
+            .starts_with("Rule 1: when:"));
+        assert!(
+            err("[[rule]]\ncomment = \"(\"\nset_type = \"Income\"").starts_with("Rule 1: comment:")
+        );
+        assert!(matches!(
+            RuleSet::parse("[[rule]]\nset_type = \"Income\"\nsett_type = 1"),
+            Err(Error::Parse { line: 3, .. })
+        ));
+    }
+}
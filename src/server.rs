@@ -0,0 +1,335 @@
+//! A local REST API over a record set, with the `server` feature.
+//!
+//! - `GET /records?filter=EXPR&offset=N&limit=N` the records matching a
+//!   filter expression, all by default
+//! - `GET /balances?time=TIME` the balance of each currency after the
+//!   records at or before TIME, after all by default
+//! - `GET /gains?year=YEAR` realized gains by tax year
+//! - `POST /validate` checks a TokenTax csv body, returning the number of
+//!   valid records and the problems by line
+//!
+//! Responses are JSON, records as their serde serialization and failures
+//! as `{"error": MSG}` with a 4xx status.
+//!
+//! There's no authentication so only loopback addresses are served unless
+//! `allow_remote` is given, and request bodies are capped at `MAX_BODY`.
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::net::ToSocketAddrs;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::holdings::balances_at;
+use crate::jurisdiction::Jurisdiction;
+use crate::price::PriceOracle;
+use crate::report::realized_gains;
+use crate::time_utils::parse_time_ms;
+use crate::TokenTaxRec;
+
+/// Largest request body read, 16 MiB
+pub const MAX_BODY: u64 = 16 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Response {
+        match serde_json::to_string(value) {
+            Ok(body) => Response { status: 200, body },
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, msg: &str) -> Response {
+        Response {
+            status,
+            body: json!({ "error": msg }).to_string(),
+        }
+    }
+}
+
+/// Decode `%XX` escapes and `+` of a query string component
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'+' => decoded.push(b' '),
+            b'%' if idx + 2 < bytes.len() => {
+                match u8::from_str_radix(s.get(idx + 1..idx + 3).unwrap_or(""), 16) {
+                    Ok(b) => {
+                        decoded.push(b);
+                        idx += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        idx += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn query(url: &str) -> (&str, BTreeMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (key, value) = p.split_once('=').unwrap_or((p, ""));
+            (decode(key), decode(value))
+        })
+        .collect();
+    (path, params)
+}
+
+pub struct Api {
+    pub recs: Vec<TokenTaxRec>,
+    pub jurisdiction: Box<dyn Jurisdiction>,
+    pub oracle: Box<dyn PriceOracle>,
+}
+
+impl Api {
+    pub fn new(
+        recs: Vec<TokenTaxRec>,
+        jurisdiction: Box<dyn Jurisdiction>,
+        oracle: Box<dyn PriceOracle>,
+    ) -> Api {
+        Api {
+            recs,
+            jurisdiction,
+            oracle,
+        }
+    }
+
+    /// Answer a request for `url`, the path and query
+    pub fn handle(&self, method: &str, url: &str, body: &[u8]) -> Response {
+        let (path, params) = query(url);
+        let result = match (method, path) {
+            ("GET", "/records") => self.records(&params),
+            ("GET", "/balances") => self.balances(&params),
+            ("GET", "/gains") => self.gains(&params),
+            ("POST", "/validate") => Ok(Response::json(&validate(body))),
+            (_, "/records" | "/balances" | "/gains" | "/validate") => {
+                Err((405, format!("{method} not allowed on {path}")))
+            }
+            _ => Err((404, format!("No such endpoint {path}"))),
+        };
+        result.unwrap_or_else(|(status, msg)| Response::error(status, &msg))
+    }
+
+    fn records(&self, params: &BTreeMap<String, String>) -> Result<Response, (u16, String)> {
+        let number = |name: &str| -> Result<Option<usize>, (u16, String)> {
+            params
+                .get(name)
+                .map(|v| v.parse().map_err(|_| (400, format!("Bad {name} {v}"))))
+                .transpose()
+        };
+        let offset = number("offset")?.unwrap_or(0);
+        let limit = number("limit")?.unwrap_or(usize::MAX);
+        let recs: Vec<&TokenTaxRec> = match params.get("filter") {
+            Some(expr) => Filter::parse(expr)
+                .map_err(|e| (400, e.to_string()))?
+                .apply(&self.recs),
+            None => self.recs.iter().collect(),
+        };
+        let page: Vec<&TokenTaxRec> = recs.into_iter().skip(offset).take(limit).collect();
+        Ok(Response::json(&page))
+    }
+
+    fn balances(&self, params: &BTreeMap<String, String>) -> Result<Response, (u16, String)> {
+        let time = match params.get("time") {
+            Some(t) => parse_time_ms(t).ok_or_else(|| (400, format!("Bad time {t}")))?,
+            None => i64::MAX,
+        };
+        Ok(Response::json(&balances_at(&self.recs, time)))
+    }
+
+    fn gains(&self, params: &BTreeMap<String, String>) -> Result<Response, (u16, String)> {
+        let year = match params.get("year") {
+            Some(y) => Some(
+                y.parse::<i32>()
+                    .map_err(|_| (400, format!("Bad year {y}")))?,
+            ),
+            None => None,
+        };
+        let mut report =
+            realized_gains(&self.recs, self.jurisdiction.as_ref(), self.oracle.as_ref())
+                .map_err(|e| (422, e.to_string()))?;
+        if let Some(year) = year {
+            report.years.retain(|y, _| *y == year);
+        }
+        Ok(Response::json(&report.years))
+    }
+
+    /// Serve requests on `addr`, e.g. `127.0.0.1:8080`, until the process
+    /// ends. Addresses other than loopback ones need `allow_remote`.
+    pub fn serve(&self, addr: &str, allow_remote: bool) -> Result<(), Error> {
+        if !allow_remote {
+            let mut addrs = addr.to_socket_addrs()?;
+            if !addrs.all(|a| a.ip().is_loopback()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{addr} isn't a loopback address, the API has no authentication"),
+                )
+                .into());
+            }
+        }
+        let server = tiny_http::Server::http(addr).map_err(std::io::Error::other)?;
+        let content_type =
+            tiny_http::Header::from_bytes("Content-Type", "application/json").expect("SNH");
+        for mut request in server.incoming_requests() {
+            let mut body = Vec::new();
+            let read = request
+                .as_reader()
+                .take(MAX_BODY + 1)
+                .read_to_end(&mut body);
+            let response = match read {
+                Ok(_) if body.len() as u64 > MAX_BODY => {
+                    Response::error(413, &format!("Body over {MAX_BODY} bytes"))
+                }
+                Ok(_) => self.handle(request.method().as_str(), request.url(), &body),
+                Err(e) => Response::error(400, &e.to_string()),
+            };
+            let response = tiny_http::Response::from_string(response.body)
+                .with_status_code(response.status)
+                .with_header(content_type.clone());
+
+            // A client hanging up doesn't stop the server
+            let _ = request.respond(response);
+        }
+        Ok(())
+    }
+}
+
+/// The number of valid records of a TokenTax csv file and the problems
+/// with the others by line
+fn validate(body: &[u8]) -> Value {
+    let mut reader = csv::Reader::from_reader(body);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return json!({ "records": 0, "errors": [{ "line": 1, "error": e.to_string() }] }),
+    };
+    let mut valid = 0;
+    let mut errors = Vec::new();
+    let mut record = csv::StringRecord::new();
+    let mut rec_idx = 0;
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                let line = record.position().map_or(0, |p| p.line());
+                let checked = record
+                    .deserialize::<TokenTaxRec>(Some(&headers))
+                    .map_err(|e| e.to_string())
+                    .and_then(|rec| rec.validate(rec_idx).map_err(|e| e.to_string()));
+                match checked {
+                    Ok(()) => valid += 1,
+                    Err(error) => errors.push(json!({ "line": line, "error": error })),
+                }
+                rec_idx += 1;
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                errors.push(json!({ "line": line, "error": e.to_string() }));
+                rec_idx += 1;
+            }
+        }
+    }
+    json!({ "records": valid, "errors": errors })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jurisdiction::jurisdiction;
+    use crate::price::PriceTable;
+    use crate::test_utils::recs_from_csv;
+    use rust_decimal_macros::dec;
+
+    const CSV: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5000,USD,,,,,kraken,,,2020-01-01 00:00:00
+Trade,2,ETH,2000,USD,,,kraken,,,2020-01-02 00:00:00
+Trade,3000,USD,1,ETH,0.01,ETH,kraken,,,2021-06-01 00:00:00
+Spend,,,100,USD,,,coinbase,,Coffee,2021-07-01 00:00:00
+";
+
+    fn api() -> Api {
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(1000));
+        Api::new(
+            recs_from_csv(CSV),
+            jurisdiction("US").unwrap(),
+            Box::new(pt),
+        )
+    }
+
+    fn get(url: &str) -> Response {
+        api().handle("GET", url, b"")
+    }
+
+    #[test]
+    fn test_api() {
+        let recs = recs_from_csv(CSV);
+        let response = get("/records?filter=exchange+%3D+kraken&offset=1&limit=1");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, serde_json::to_string(&[&recs[1]]).unwrap());
+        assert_eq!(get("/records").body, serde_json::to_string(&recs).unwrap());
+        assert_eq!(get("/records?filter=(").status, 400);
+
+        let response = get("/balances?time=2020-01-02%2000:00:00");
+        assert_eq!(
+            serde_json::from_str::<Value>(&response.body).unwrap(),
+            json!({ "ETH": dec!(2), "USD": dec!(3000) })
+        );
+
+        // The ETH fee is a disposal too
+        assert_eq!(
+            get("/gains?year=2021").body,
+            r#"{"2021":{"proceeds":"3010.00","cost":"1020.00","short_term_gain":"0","long_term_gain":"1990.00"}}"#
+        );
+
+        assert_eq!(get("/nothing").status, 404);
+        assert_eq!(api().handle("DELETE", "/records", b"").status, 405);
+        assert_eq!(
+            get("/balances?time=soon").body,
+            r#"{"error":"Bad time soon"}"#
+        );
+
+        let err = api().serve("0.0.0.0:0", false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "0.0.0.0:0 isn't a loopback address, the API has no authentication"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let body = "Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5000,USD,,,,,kraken,,,2020-01-01 00:00:00
+Trade,2,ETH,,,,,kraken,,,2020-01-02 00:00:00
+Trade,2,ETH,2000,USD,,,kraken,,,2020-01-02
+Swap,2,ETH,2000,USD,,,kraken,,,2020-01-02 00:00:00
+";
+        let response = api().handle("POST", "/validate", body.as_bytes());
+        let value: Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(value["records"], json!(1));
+        let errors = value["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0],
+            json!({ "line": 3, "error": "Record 1: missing sell_amount" })
+        );
+        assert_eq!(errors[1]["line"], json!(4));
+        assert_eq!(errors[2]["line"], json!(5));
+    }
+}
@@ -0,0 +1,569 @@
+//! Spreadsheets of record sets, lot reports and gain reports, written as
+//! XLSX with the `xlsx` feature or ODS with the `ods` feature.
+//!
+//! Each report section is a sheet with a frozen header row. Amounts are
+//! numeric cells, times are date cells in UTC and the sheets of reports end
+//! in a totals row.
+#[cfg(feature = "ods")]
+use std::fmt::Write as _;
+#[cfg(any(feature = "ods", feature = "xlsx"))]
+use std::io::Write;
+
+use rust_decimal::prelude::*;
+
+#[cfg(any(feature = "ods", feature = "xlsx"))]
+use crate::error::Error;
+use crate::lots::{Lot, LotDisposal};
+use crate::report::GainsReport;
+use crate::{GroupType, TokenTaxRec};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Empty,
+    Text(String),
+    Number(Decimal),
+    Integer(i64),
+
+    /// Milliseconds since the epoch
+    Time(i64),
+}
+
+impl Cell {
+    fn text(s: &str) -> Cell {
+        Cell::Text(s.to_owned())
+    }
+
+    fn amount(amount: Option<Decimal>) -> Cell {
+        amount.map_or(Cell::Empty, Cell::Number)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sheet {
+    pub name: String,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<Cell>>,
+
+    /// Written after the rows in bold
+    pub totals: Option<Vec<Cell>>,
+}
+
+impl Sheet {
+    pub fn new(name: &str, header: &[&str]) -> Sheet {
+        Sheet {
+            name: name.to_owned(),
+            header: header.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+            totals: None,
+        }
+    }
+
+    /// Set the totals row to the sums of the numbers in `columns`
+    pub fn total(&mut self, columns: &[usize]) {
+        let mut totals = vec![Cell::Empty; self.header.len()];
+        totals[0] = Cell::text("Total");
+        for &col in columns {
+            let sum = self
+                .rows
+                .iter()
+                .filter_map(|row| match row.get(col) {
+                    Some(Cell::Number(n)) => Some(*n),
+                    _ => None,
+                })
+                .sum();
+            totals[col] = Cell::Number(sum);
+        }
+        self.totals = Some(totals);
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Workbook {
+    pub sheets: Vec<Sheet>,
+}
+
+impl Workbook {
+    pub fn new() -> Workbook {
+        Workbook::default()
+    }
+
+    /// Add a sheet `name` of `recs` in the TokenTax csv columns
+    pub fn add_records(&mut self, name: &str, recs: &[TokenTaxRec]) {
+        let mut sheet = Sheet::new(
+            name,
+            &[
+                "Type",
+                "BuyAmount",
+                "BuyCurrency",
+                "SellAmount",
+                "SellCurrency",
+                "FeeAmount",
+                "FeeCurrency",
+                "Exchange",
+                "Group",
+                "Comment",
+                "Date",
+            ],
+        );
+        for rec in recs {
+            sheet.rows.push(vec![
+                Cell::Text(rec.type_txs.to_string()),
+                Cell::amount(rec.buy_amount),
+                Cell::text(&rec.buy_currency),
+                Cell::amount(rec.sell_amount),
+                Cell::text(&rec.sell_currency),
+                Cell::amount(rec.fee_amount),
+                Cell::text(&rec.fee_currency),
+                Cell::text(&rec.exchange),
+                match rec.group {
+                    Some(GroupType::Margin) => Cell::text("margin"),
+                    None => Cell::Empty,
+                },
+                Cell::text(&rec.comment),
+                Cell::Time(rec.time),
+            ]);
+        }
+        self.sheets.push(sheet);
+    }
+
+    /// Add the "Lot disposals" and "Open lots" sheets of a lot report
+    pub fn add_lots(&mut self, disposals: &[LotDisposal], open: &[Lot]) {
+        let mut sheet = Sheet::new(
+            "Lot disposals",
+            &[
+                "Asset",
+                "Quantity",
+                "Acquired",
+                "Disposed",
+                "Cost",
+                "Fee",
+                "Proceeds",
+                "Gain",
+                "Acquired record",
+                "Record",
+            ],
+        );
+        for d in disposals {
+            sheet.rows.push(vec![
+                Cell::text(&d.asset),
+                Cell::Number(d.quantity),
+                Cell::Time(d.acquired_time),
+                Cell::Time(d.disposed_time),
+                Cell::Number(d.cost),
+                Cell::Number(d.fee),
+                Cell::Number(d.proceeds),
+                Cell::Number(d.gain()),
+                Cell::Integer(d.acquired_rec_idx as i64),
+                Cell::Integer(d.rec_idx as i64),
+            ]);
+        }
+        sheet.total(&[4, 5, 6, 7]);
+        self.sheets.push(sheet);
+
+        let mut sheet = Sheet::new(
+            "Open lots",
+            &[
+                "Asset",
+                "Quantity",
+                "Cost",
+                "Unit cost",
+                "Acquired",
+                "Record",
+            ],
+        );
+        for lot in open {
+            sheet.rows.push(vec![
+                Cell::text(&lot.asset),
+                Cell::Number(lot.quantity),
+                Cell::Number(lot.cost),
+                Cell::Number(lot.unit_cost()),
+                Cell::Time(lot.time),
+                Cell::Integer(lot.rec_idx as i64),
+            ]);
+        }
+        sheet.total(&[2]);
+        self.sheets.push(sheet);
+    }
+
+    /// Add the "Realized gains" and "Gains by year" sheets of `report`
+    pub fn add_gains(&mut self, report: &GainsReport) {
+        let mut sheet = Sheet::new(
+            "Realized gains",
+            &[
+                "Asset", "Quantity", "Acquired", "Disposed", "Proceeds", "Cost", "Gain", "Term",
+                "Tax year", "Record",
+            ],
+        );
+        for r in &report.realized {
+            sheet.rows.push(vec![
+                Cell::text(&r.asset),
+                Cell::Number(r.quantity),
+                r.acquired_time.map_or(Cell::Empty, Cell::Time),
+                Cell::Time(r.disposed_time),
+                Cell::Number(r.proceeds),
+                Cell::Number(r.cost),
+                Cell::Number(r.gain()),
+                Cell::text(if r.long_term { "Long" } else { "Short" }),
+                Cell::Integer(r.tax_year as i64),
+                Cell::Integer(r.rec_idx as i64),
+            ]);
+        }
+        sheet.total(&[4, 5, 6]);
+        self.sheets.push(sheet);
+
+        let mut sheet = Sheet::new(
+            "Gains by year",
+            &[
+                "Tax year",
+                "Proceeds",
+                "Cost",
+                "Short term gain",
+                "Long term gain",
+                "Gain",
+            ],
+        );
+        for (year, gains) in &report.years {
+            sheet.rows.push(vec![
+                Cell::Integer(*year as i64),
+                Cell::Number(gains.proceeds),
+                Cell::Number(gains.cost),
+                Cell::Number(gains.short_term_gain),
+                Cell::Number(gains.long_term_gain),
+                Cell::Number(gains.short_term_gain + gains.long_term_gain),
+            ]);
+        }
+        sheet.total(&[1, 2, 3, 4, 5]);
+        self.sheets.push(sheet);
+    }
+
+    /// Write the workbook as XLSX, numbers lose precision beyond the 15
+    /// significant digits a spreadsheet keeps
+    #[cfg(feature = "xlsx")]
+    pub fn write_xlsx(&self, wtr: &mut dyn Write) -> Result<(), Error> {
+        use rust_xlsxwriter::Format;
+
+        let mut book = rust_xlsxwriter::Workbook::new();
+        let formats = [Format::new(), Format::new().set_bold()].map(|text| {
+            let number = text.clone().set_num_format("#,##0.00########");
+            let integer = text.clone().set_num_format("0");
+            let date = text.clone().set_num_format("yyyy-mm-dd hh:mm:ss");
+            (text, number, integer, date)
+        });
+        for sheet in &self.sheets {
+            let ws = book.add_worksheet();
+            ws.set_name(&sheet.name)?;
+            for (col, name) in sheet.header.iter().enumerate() {
+                ws.write_string_with_format(0, col as u16, name, &formats[1].0)?;
+            }
+            let rows = sheet.rows.iter().map(|row| (row, &formats[0]));
+            let totals = sheet.totals.iter().map(|row| (row, &formats[1]));
+            for (row_idx, (row, (text, number, integer, date))) in rows.chain(totals).enumerate() {
+                let row_idx = row_idx as u32 + 1;
+                for (col, cell) in row.iter().enumerate() {
+                    let col = col as u16;
+                    match cell {
+                        Cell::Empty => ws.write_blank(row_idx, col, text)?,
+                        Cell::Text(s) => ws.write_string_with_format(row_idx, col, s, text)?,
+                        Cell::Number(n) => {
+                            let n = n.to_f64().expect("SNH");
+                            ws.write_number_with_format(row_idx, col, n, number)?
+                        }
+                        Cell::Integer(i) => {
+                            ws.write_number_with_format(row_idx, col, *i as f64, integer)?
+                        }
+                        Cell::Time(t) => {
+                            // Days since 1899-12-30, the epoch being day 25569
+                            let days = 25569.0 + *t as f64 / 86_400_000.0;
+                            ws.write_number_with_format(row_idx, col, days, date)?
+                        }
+                    };
+                }
+            }
+            ws.set_freeze_panes(1, 0)?;
+            ws.autofit();
+        }
+        wtr.write_all(&book.save_to_buffer()?)?;
+        Ok(())
+    }
+
+    /// Write the workbook as ODS, numbers keep their full precision
+    #[cfg(feature = "ods")]
+    pub fn write_ods(&self, wtr: &mut dyn Write) -> Result<(), Error> {
+        use zip::write::SimpleFileOptions;
+        use zip::CompressionMethod;
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default();
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet")?;
+        zip.start_file("META-INF/manifest.xml", deflated)?;
+        zip.write_all(ODS_MANIFEST.as_bytes())?;
+        zip.start_file("content.xml", deflated)?;
+        zip.write_all(self.ods_content().as_bytes())?;
+        zip.start_file("settings.xml", deflated)?;
+        zip.write_all(self.ods_settings().as_bytes())?;
+        wtr.write_all(&zip.finish()?.into_inner())?;
+        Ok(())
+    }
+
+    #[cfg(feature = "ods")]
+    fn ods_content(&self) -> String {
+        let mut xml = String::from(ODS_CONTENT_HEAD);
+        for sheet in &self.sheets {
+            write!(xml, r#"<table:table table:name="{}">"#, escape(&sheet.name)).unwrap();
+            xml.push_str("<table:table-header-rows><table:table-row>");
+            for name in &sheet.header {
+                ods_cell(&mut xml, &Cell::Text(name.clone()), true);
+            }
+            xml.push_str("</table:table-row></table:table-header-rows>");
+            let rows = sheet.rows.iter().map(|row| (row, false));
+            for (row, bold) in rows.chain(sheet.totals.iter().map(|row| (row, true))) {
+                xml.push_str("<table:table-row>");
+                for cell in row {
+                    ods_cell(&mut xml, cell, bold);
+                }
+                xml.push_str("</table:table-row>");
+            }
+            xml.push_str("</table:table>");
+        }
+        xml.push_str("</office:spreadsheet></office:body></office:document-content>");
+        xml
+    }
+
+    /// View settings freezing the header row of each sheet
+    #[cfg(feature = "ods")]
+    fn ods_settings(&self) -> String {
+        let mut xml = String::from(ODS_SETTINGS_HEAD);
+        for sheet in &self.sheets {
+            write!(
+                xml,
+                r#"<config:config-item-map-entry config:name="{}">"#,
+                escape(&sheet.name)
+            )
+            .unwrap();
+            for (name, kind, value) in [
+                ("VerticalSplitMode", "short", 2),
+                ("VerticalSplitPosition", "int", 1),
+                ("ActiveSplitRange", "short", 2),
+                ("PositionTop", "int", 0),
+                ("PositionBottom", "int", 1),
+            ] {
+                write!(
+                    xml,
+                    r#"<config:config-item config:name="{name}" config:type="{kind}">{value}</config:config-item>"#
+                )
+                .unwrap();
+            }
+            xml.push_str("</config:config-item-map-entry>");
+        }
+        xml.push_str(
+            "</config:config-item-map-named></config:config-item-map-entry>\
+             </config:config-item-map-indexed></config:config-item-set>\
+             </office:settings></office:document-settings>",
+        );
+        xml
+    }
+}
+
+#[cfg(feature = "ods")]
+const ODS_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+ <manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+ <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+ <manifest:file-entry manifest:full-path="settings.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+#[cfg(feature = "ods")]
+const ODS_CONTENT_HEAD: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<office:document-content"#,
+    r#" xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0""#,
+    r#" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0""#,
+    r#" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0""#,
+    r#" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0""#,
+    r#" xmlns:number="urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0""#,
+    r#" xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0""#,
+    r#" office:version="1.2"><office:automatic-styles>"#,
+    r#"<number:number-style style:name="N1"><number:number number:decimal-places="2""#,
+    r#" number:min-decimal-places="2" number:min-integer-digits="1" number:grouping="true"/>"#,
+    r#"</number:number-style>"#,
+    r#"<number:date-style style:name="N2"><number:year number:style="long"/>"#,
+    r#"<number:text>-</number:text><number:month number:style="long"/>"#,
+    r#"<number:text>-</number:text><number:day number:style="long"/>"#,
+    r#"<number:text> </number:text><number:hours number:style="long"/>"#,
+    r#"<number:text>:</number:text><number:minutes number:style="long"/>"#,
+    r#"<number:text>:</number:text><number:seconds number:style="long"/>"#,
+    r#"</number:date-style>"#,
+    r#"<style:style style:name="bold" style:family="table-cell">"#,
+    r#"<style:text-properties fo:font-weight="bold"/></style:style>"#,
+    r#"<style:style style:name="total" style:family="table-cell" style:data-style-name="N1">"#,
+    r#"<style:text-properties fo:font-weight="bold"/></style:style>"#,
+    r#"<style:style style:name="date" style:family="table-cell" style:data-style-name="N2"/>"#,
+    r#"</office:automatic-styles><office:body><office:spreadsheet>"#,
+);
+
+#[cfg(feature = "ods")]
+const ODS_SETTINGS_HEAD: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+    r#"<office:document-settings"#,
+    r#" xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0""#,
+    r#" xmlns:config="urn:oasis:names:tc:opendocument:xmlns:config:1.0""#,
+    r#" office:version="1.2"><office:settings>"#,
+    r#"<config:config-item-set config:name="ooo:view-settings">"#,
+    r#"<config:config-item-map-indexed config:name="Views"><config:config-item-map-entry>"#,
+    r#"<config:config-item-map-named config:name="Tables">"#,
+);
+
+#[cfg(feature = "ods")]
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(feature = "ods")]
+fn ods_cell(xml: &mut String, cell: &Cell, bold: bool) {
+    let text_style = if bold {
+        r#" table:style-name="bold""#
+    } else {
+        ""
+    };
+    match cell {
+        Cell::Empty => xml.push_str("<table:table-cell/>"),
+        Cell::Text(s) => write!(
+            xml,
+            r#"<table:table-cell office:value-type="string"{text_style}><text:p>{}</text:p></table:table-cell>"#,
+            escape(s)
+        )
+        .unwrap(),
+        Cell::Number(n) => {
+            let style = if bold { r#" table:style-name="total""# } else { "" };
+            let n = n.normalize();
+            write!(
+                xml,
+                r#"<table:table-cell office:value-type="float" office:value="{n}"{style}><text:p>{n}</text:p></table:table-cell>"#
+            )
+            .unwrap()
+        }
+        Cell::Integer(i) => write!(
+            xml,
+            r#"<table:table-cell office:value-type="float" office:value="{i}"{text_style}><text:p>{i}</text:p></table:table-cell>"#
+        )
+        .unwrap(),
+        Cell::Time(t) => {
+            let time = chrono::DateTime::from_timestamp_millis(*t).expect("SNH");
+            write!(
+                xml,
+                r#"<table:table-cell office:value-type="date" office:date-value="{}" table:style-name="date"><text:p>{}</text:p></table:table-cell>"#,
+                time.format("%Y-%m-%dT%H:%M:%S%.3f"),
+                time.format("%Y-%m-%d %H:%M:%S"),
+            )
+            .unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jurisdiction::jurisdiction;
+    use crate::price::PriceTable;
+    use crate::report::realized_gains;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    const CSV: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,2,ETH,2000,USD,,,kraken,,,2020-01-01 00:00:00
+Trade,1,ETH,1000,USD,,,kraken,,,2021-01-01 00:00:00
+Trade,3000,USD,1,ETH,,,kraken,,,2021-06-01 00:00:00
+Trade,1500,USD,1,ETH,,,kraken,margin,\"Sell <all>\",2022-08-01 00:00:00
+";
+
+    fn workbook() -> Workbook {
+        let recs = recs_from_csv(CSV);
+        let mut pt = PriceTable::new();
+        pt.insert("ETH", "USD", 0, dec!(2000));
+        let us = jurisdiction("US").unwrap();
+        let report = realized_gains(&recs, us.as_ref(), &pt).unwrap();
+
+        let mut book = Workbook::new();
+        book.add_records("Records", &recs);
+        book.add_gains(&report);
+        book
+    }
+
+    #[test]
+    fn test_sheets() {
+        let book = workbook();
+        let names: Vec<&str> = book.sheets.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["Records", "Realized gains", "Gains by year"]);
+
+        let records = &book.sheets[0];
+        assert_eq!(records.rows.len(), 4);
+        assert_eq!(records.rows[3][8], Cell::text("margin"));
+        assert_eq!(
+            records.rows[3][10],
+            Cell::Time(time_ms("2022-08-01 00:00:00"))
+        );
+        assert_eq!(records.totals, None);
+
+        let realized = &book.sheets[1];
+        assert_eq!(realized.rows[0][7], Cell::text("Long"));
+        let totals = realized.totals.as_ref().unwrap();
+        assert_eq!(totals[0], Cell::text("Total"));
+        assert_eq!(totals[4], Cell::Number(dec!(4500)));
+        assert_eq!(totals[6], Cell::Number(dec!(2500)));
+        assert_eq!(totals[1], Cell::Empty);
+
+        let years = &book.sheets[2];
+        assert_eq!(years.rows.len(), 2);
+        assert_eq!(years.rows[1][0], Cell::Integer(2022));
+        assert_eq!(years.totals.as_ref().unwrap()[5], Cell::Number(dec!(2500)));
+    }
+
+    #[cfg(feature = "ods")]
+    #[test]
+    fn test_write_ods() {
+        use std::io::Read;
+
+        let mut buf = Vec::new();
+        workbook().write_ods(&mut buf).unwrap();
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(buf)).unwrap();
+        let mut mimetype = String::new();
+        zip.by_index(0)
+            .unwrap()
+            .read_to_string(&mut mimetype)
+            .unwrap();
+        assert_eq!(mimetype, "application/vnd.oasis.opendocument.spreadsheet");
+
+        let mut content = String::new();
+        zip.by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(r#"<table:table table:name="Gains by year">"#));
+        assert!(content.contains("<text:p>Sell &lt;all&gt;</text:p>"));
+        assert!(content.contains(r#"office:date-value="2022-08-01T00:00:00.000""#));
+        assert!(content.contains(
+            r#"<table:table-cell office:value-type="float" office:value="4500" table:style-name="total">"#
+        ));
+        let mut settings = String::new();
+        zip.by_name("settings.xml")
+            .unwrap()
+            .read_to_string(&mut settings)
+            .unwrap();
+        assert!(settings.contains(r#"<config:config-item-map-entry config:name="Records">"#));
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn test_write_xlsx() {
+        let mut buf = Vec::new();
+        workbook().write_xlsx(&mut buf).unwrap();
+        assert!(buf.starts_with(b"PK"));
+    }
+}
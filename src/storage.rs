@@ -0,0 +1,508 @@
+//! Persistence of `TokenTaxRec`s in a SQLite database.
+//!
+//! Records are keyed by `TokenTaxRec::fingerprint` so importing the same
+//! file twice doesn't duplicate them. Amounts are stored as text so
+//! they are read back with the same scale, which keeps `export_csv` output
+//! identical to the csv it was imported from. A record's provenance, its
+//! source and audit steps, is kept in tables keyed by the fingerprint.
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use rust_decimal::prelude::*;
+
+use crate::audit::{AuditKind, AuditStep, Provenance, Source};
+use crate::error::Error;
+use crate::{FieldChange, GroupType, TokenTaxRec, TokenTaxRecType};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    fingerprint TEXT NOT NULL UNIQUE,
+    type TEXT NOT NULL,
+    buy_amount TEXT,
+    buy_currency TEXT NOT NULL,
+    sell_amount TEXT,
+    sell_currency TEXT NOT NULL,
+    fee_amount TEXT,
+    fee_currency TEXT NOT NULL,
+    exchange TEXT NOT NULL,
+    grp TEXT,
+    comment TEXT NOT NULL,
+    time INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS records_time ON records (time);
+CREATE INDEX IF NOT EXISTS records_buy_currency ON records (buy_currency);
+CREATE INDEX IF NOT EXISTS records_sell_currency ON records (sell_currency);
+CREATE INDEX IF NOT EXISTS records_exchange ON records (exchange);
+
+-- Step 0 is the record's own source, others are merged into that step
+CREATE TABLE IF NOT EXISTS sources (
+    fingerprint TEXT NOT NULL,
+    step INTEGER NOT NULL,
+    file TEXT NOT NULL,
+    line INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS audit_steps (
+    fingerprint TEXT NOT NULL,
+    step INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    rule TEXT,
+    PRIMARY KEY (fingerprint, step)
+);
+CREATE TABLE IF NOT EXISTS audit_changes (
+    fingerprint TEXT NOT NULL,
+    step INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    old TEXT NOT NULL,
+    new TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sources_fingerprint ON sources (fingerprint);
+CREATE INDEX IF NOT EXISTS audit_changes_fingerprint ON audit_changes (fingerprint);
+";
+
+const PROVENANCE_TABLES: [&str; 3] = ["sources", "audit_steps", "audit_changes"];
+
+/// Names of the fields `crate::field_changes` reports
+const FIELD_NAMES: [&str; 11] = [
+    "type",
+    "buy_amount",
+    "sell_amount",
+    "fee_amount",
+    "buy_currency",
+    "sell_currency",
+    "fee_currency",
+    "exchange",
+    "comment",
+    "group",
+    "date",
+];
+
+const COLUMNS: &str = "type, buy_amount, buy_currency, sell_amount, sell_currency, \
+    fee_amount, fee_currency, exchange, grp, comment, time";
+
+/// Whether `upsert` added a record or updated an existing one
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Upsert {
+    Inserted,
+    Updated,
+}
+
+pub struct RecordStore {
+    conn: Connection,
+}
+
+impl RecordStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RecordStore, Error> {
+        RecordStore::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<RecordStore, Error> {
+        RecordStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<RecordStore, Error> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(RecordStore { conn })
+    }
+
+    pub fn len(&self) -> Result<usize, Error> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn upsert(&mut self, rec: &TokenTaxRec) -> Result<Upsert, Error> {
+        let tx = self.conn.transaction()?;
+        let upserted = upsert(&tx, rec)?;
+        tx.commit()?;
+        Ok(upserted)
+    }
+
+    /// Upsert all of `recs` in one transaction, returns the number inserted
+    pub fn upsert_all(&mut self, recs: &[TokenTaxRec]) -> Result<usize, Error> {
+        let tx = self.conn.transaction()?;
+        let mut inserted = 0;
+        for rec in recs.iter() {
+            if upsert(&tx, rec)? == Upsert::Inserted {
+                inserted += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Result<Option<TokenTaxRec>, Error> {
+        let sql = format!("SELECT {COLUMNS} FROM records WHERE fingerprint = ?1");
+        let rec = self
+            .conn
+            .query_row(&sql, [fingerprint], from_row)
+            .optional()?;
+        rec.map(|rec| with_provenance(&self.conn, rec)).transpose()
+    }
+
+    pub fn remove(&mut self, fingerprint: &str) -> Result<bool, Error> {
+        let tx = self.conn.transaction()?;
+        let count = tx.execute("DELETE FROM records WHERE fingerprint = ?1", [fingerprint])?;
+        remove_provenance(&tx, fingerprint)?;
+        tx.commit()?;
+        Ok(count > 0)
+    }
+
+    /// All records ordered by time then insertion order
+    pub fn records(&self) -> Result<Vec<TokenTaxRec>, Error> {
+        self.query("1 = 1", [])
+    }
+
+    /// Records with `start <= time < end`
+    pub fn records_between(&self, start: i64, end: i64) -> Result<Vec<TokenTaxRec>, Error> {
+        self.query("time >= ?1 AND time < ?2", [start, end])
+    }
+
+    /// Records buying or selling `asset`
+    pub fn records_for_asset(&self, asset: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        self.query("buy_currency = ?1 OR sell_currency = ?1", [asset])
+    }
+
+    pub fn records_for_exchange(&self, exchange: &str) -> Result<Vec<TokenTaxRec>, Error> {
+        self.query("exchange = ?1", [exchange])
+    }
+
+    fn query<P: rusqlite::Params>(
+        &self,
+        condition: &str,
+        params: P,
+    ) -> Result<Vec<TokenTaxRec>, Error> {
+        let sql = format!("SELECT {COLUMNS} FROM records WHERE {condition} ORDER BY time, id");
+        let mut stmt = self.conn.prepare(&sql)?;
+        let recs = stmt
+            .query_map(params, from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        recs.into_iter()
+            .map(|rec| with_provenance(&self.conn, rec))
+            .collect()
+    }
+
+    /// Write all records as TokenTax csv, returns the number written
+    pub fn export_csv<W: Write>(&self, wtr: W) -> Result<usize, Error> {
+        let recs = self.records()?;
+        crate::write_csv(&recs, wtr)?;
+        Ok(recs.len())
+    }
+}
+
+fn upsert(conn: &Connection, rec: &TokenTaxRec) -> Result<Upsert, Error> {
+    let fingerprint = rec.fingerprint();
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM records WHERE fingerprint = ?1",
+            [&fingerprint],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    let amount = |a: Option<Decimal>| a.map(|a| a.to_string());
+    conn.execute(
+        &format!(
+            "INSERT INTO records (fingerprint, {COLUMNS})
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT (fingerprint) DO UPDATE SET
+                 type = excluded.type,
+                 buy_amount = excluded.buy_amount,
+                 buy_currency = excluded.buy_currency,
+                 sell_amount = excluded.sell_amount,
+                 sell_currency = excluded.sell_currency,
+                 fee_amount = excluded.fee_amount,
+                 fee_currency = excluded.fee_currency,
+                 exchange = excluded.exchange,
+                 grp = excluded.grp,
+                 comment = excluded.comment,
+                 time = excluded.time"
+        ),
+        params![
+            fingerprint,
+            rec.type_txs.to_string(),
+            amount(rec.buy_amount),
+            rec.buy_currency,
+            amount(rec.sell_amount),
+            rec.sell_currency,
+            amount(rec.fee_amount),
+            rec.fee_currency,
+            rec.exchange,
+            rec.group.as_ref().map(|_| "margin"),
+            rec.comment,
+            rec.time,
+        ],
+    )?;
+    remove_provenance(conn, &fingerprint)?;
+    insert_provenance(conn, &fingerprint, &rec.provenance)?;
+    Ok(if exists {
+        Upsert::Updated
+    } else {
+        Upsert::Inserted
+    })
+}
+
+fn remove_provenance(conn: &Connection, fingerprint: &str) -> Result<(), Error> {
+    for table in PROVENANCE_TABLES {
+        conn.execute(
+            &format!("DELETE FROM {table} WHERE fingerprint = ?1"),
+            [fingerprint],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_provenance(
+    conn: &Connection,
+    fingerprint: &str,
+    provenance: &Provenance,
+) -> Result<(), Error> {
+    let insert_source = |step: usize, source: &Source| {
+        conn.execute(
+            "INSERT INTO sources (fingerprint, step, file, line) VALUES (?1, ?2, ?3, ?4)",
+            params![fingerprint, step, source.file, source.line],
+        )
+    };
+    if let Some(source) = &provenance.source {
+        insert_source(0, source)?;
+    }
+    for (idx, step) in provenance.steps.iter().enumerate() {
+        let step_no = idx + 1;
+        conn.execute(
+            "INSERT INTO audit_steps (fingerprint, step, kind, rule) VALUES (?1, ?2, ?3, ?4)",
+            params![fingerprint, step_no, step.kind.to_string(), step.rule],
+        )?;
+        for change in step.changes.iter() {
+            conn.execute(
+                "INSERT INTO audit_changes (fingerprint, step, field, old, new)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![fingerprint, step_no, change.field, change.old, change.new],
+            )?;
+        }
+        for source in step.merged.iter() {
+            insert_source(step_no, source)?;
+        }
+    }
+    Ok(())
+}
+
+/// `rec` with the provenance stored for its fingerprint, the statements
+/// are cached as this runs for every record queried
+fn with_provenance(conn: &Connection, mut rec: TokenTaxRec) -> Result<TokenTaxRec, Error> {
+    let fingerprint = rec.fingerprint();
+    let conversion_error = |idx, msg: String| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, msg.into())
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT kind, rule FROM audit_steps WHERE fingerprint = ?1 ORDER BY step",
+    )?;
+    let steps = stmt
+        .query_map([&fingerprint], |row| {
+            let kind: String = row.get(0)?;
+            Ok(AuditStep {
+                kind: AuditKind::from_str(&kind).map_err(|e| conversion_error(0, e))?,
+                rule: row.get(1)?,
+                changes: Vec::new(),
+                merged: Vec::new(),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rec.provenance.steps = steps;
+
+    // Steps are numbered from 1 in the order they were made
+    let step_idx = |step: usize, steps: &[AuditStep]| {
+        step.checked_sub(1)
+            .filter(|idx| *idx < steps.len())
+            .ok_or_else(|| conversion_error(0, format!("No audit step {step}")))
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT step, field, old, new FROM audit_changes WHERE fingerprint = ?1 ORDER BY rowid",
+    )?;
+    let mut rows = stmt.query([&fingerprint])?;
+    while let Some(row) = rows.next()? {
+        let idx = step_idx(row.get(0)?, &rec.provenance.steps)?;
+        let field: String = row.get(1)?;
+        let field = FIELD_NAMES
+            .into_iter()
+            .find(|f| *f == field)
+            .ok_or_else(|| conversion_error(1, format!("Unknown field {field}")))?;
+        rec.provenance.steps[idx].changes.push(FieldChange {
+            field,
+            old: row.get(2)?,
+            new: row.get(3)?,
+        });
+    }
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT step, file, line FROM sources WHERE fingerprint = ?1 ORDER BY rowid",
+    )?;
+    let mut rows = stmt.query([&fingerprint])?;
+    while let Some(row) = rows.next()? {
+        let source = Source {
+            file: row.get(1)?,
+            line: row.get(2)?,
+        };
+        match row.get(0)? {
+            0 => rec.provenance.source = Some(source),
+            step => {
+                let idx = step_idx(step, &rec.provenance.steps)?;
+                rec.provenance.steps[idx].merged.push(source);
+            }
+        }
+    }
+    Ok(rec)
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<TokenTaxRec> {
+    let conversion_error = |idx, msg: String| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, msg.into())
+    };
+    let amount = |idx: usize| -> rusqlite::Result<Option<Decimal>> {
+        row.get::<_, Option<String>>(idx)?
+            .map(|s| Decimal::from_str(&s).map_err(|e| conversion_error(idx, e.to_string())))
+            .transpose()
+    };
+    let type_txs: String = row.get(0)?;
+    let group: Option<String> = row.get(8)?;
+    Ok(TokenTaxRec {
+        type_txs: TokenTaxRecType::from_str(&type_txs).map_err(|e| conversion_error(0, e))?,
+        buy_amount: amount(1)?,
+        buy_currency: row.get(2)?,
+        sell_amount: amount(3)?,
+        sell_currency: row.get(4)?,
+        fee_amount: amount(5)?,
+        fee_currency: row.get(6)?,
+        exchange: row.get(7)?,
+        group: match group.as_deref() {
+            None => None,
+            Some("margin") => Some(GroupType::Margin),
+            Some(g) => return Err(conversion_error(8, format!("Unknown group {g}"))),
+        },
+        comment: row.get(9)?,
+        time: row.get(10)?,
+        provenance: Provenance::default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::read_csv_with_source;
+    use crate::dedup;
+    use crate::rules::RuleSet;
+    use crate::test_utils::recs_from_csv;
+
+    const CSV: &str = "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Deposit,5125,USD,,,,,binance.us,,,1970-01-01 00:00:02
+Trade,1,ETH,3123.00,USD,0.00124,BNB,binance.us,,,1970-01-01 00:00:03
+Trade,1,ETH,312.00,USD,0.00124,BNB,binance.us,margin,,1970-01-01 00:00:01
+Withdrawal,,,100,USD,,,some bank,,\"AccountId: 123456\",1970-01-01 00:00:04
+";
+
+    #[test]
+    fn test_upsert() {
+        let recs = recs_from_csv(CSV);
+        let mut store = RecordStore::open_in_memory().unwrap();
+        assert!(store.is_empty().unwrap());
+        assert_eq!(store.upsert_all(&recs).unwrap(), 4);
+        assert_eq!(store.upsert_all(&recs).unwrap(), 0);
+        assert_eq!(store.upsert(&recs[0]).unwrap(), Upsert::Updated);
+        assert_eq!(store.len().unwrap(), 4);
+
+        let mut rec = recs[0].clone();
+        rec.buy_amount = Some(Decimal::from_str("5125.000").unwrap());
+        assert_eq!(store.upsert(&rec).unwrap(), Upsert::Updated);
+        assert_eq!(store.len().unwrap(), 4);
+
+        let stored = store.records().unwrap();
+        assert_eq!(stored[0], recs[2]);
+        assert_eq!(stored[0].group, Some(GroupType::Margin));
+        assert_eq!(stored[1].buy_amount.unwrap().to_string(), "5125.000");
+        assert_eq!(stored[3].comment, "AccountId: 123456");
+
+        assert!(store.remove(&recs[3].fingerprint()).unwrap());
+        assert!(!store.remove(&recs[3].fingerprint()).unwrap());
+        assert_eq!(
+            store.get(&recs[1].fingerprint()).unwrap(),
+            Some(recs[1].clone())
+        );
+        assert_eq!(store.get(&recs[3].fingerprint()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let mut recs = read_csv_with_source(CSV.trim_start().as_bytes(), "binance.csv").unwrap();
+        recs.push(recs[3].clone());
+        recs[4].provenance.source.as_mut().unwrap().line = 7;
+        dedup(&mut recs);
+        let rules = RuleSet::parse(
+            "[[rule]]\nname = \"bank\"\nwhen = \"exchange = \\\"some bank\\\"\"\nset_comment = \"Bank\"",
+        )
+        .unwrap();
+        rules.apply(&mut recs).unwrap();
+        let rec = &recs[3];
+        assert_eq!(rec.provenance.steps.len(), 2);
+
+        let mut store = RecordStore::open_in_memory().unwrap();
+        store.upsert_all(&recs).unwrap();
+        let stored = store.get(&rec.fingerprint()).unwrap().unwrap();
+        assert_eq!(stored.provenance, rec.provenance);
+        let stored = store.records().unwrap();
+        assert_eq!(stored[0].provenance.source, recs[2].provenance.source);
+        assert!(stored[0].provenance.steps.is_empty());
+
+        // An update replaces the history, a removal drops it
+        let mut plain = rec.clone();
+        plain.provenance = Provenance::default();
+        store.upsert(&plain).unwrap();
+        let stored = store.get(&rec.fingerprint()).unwrap().unwrap();
+        assert_eq!(stored.provenance, Provenance::default());
+        store.upsert(rec).unwrap();
+        store.remove(&rec.fingerprint()).unwrap();
+        store.upsert(&plain).unwrap();
+        let stored = store.get(&rec.fingerprint()).unwrap().unwrap();
+        assert_eq!(stored.provenance, Provenance::default());
+    }
+
+    #[test]
+    fn test_queries() {
+        let recs = recs_from_csv(CSV);
+        let mut store = RecordStore::open_in_memory().unwrap();
+        store.upsert_all(&recs).unwrap();
+
+        assert_eq!(store.records_between(2000, 4000).unwrap().len(), 2);
+        assert_eq!(store.records_for_asset("ETH").unwrap().len(), 2);
+        assert_eq!(store.records_for_asset("USD").unwrap().len(), 4);
+        assert_eq!(store.records_for_exchange("some bank").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let recs = recs_from_csv(CSV);
+        let mut store = RecordStore::open_in_memory().unwrap();
+        store.upsert_all(&recs).unwrap();
+
+        let mut buf = Vec::new();
+        assert_eq!(store.export_csv(&mut buf).unwrap(), 4);
+        let csv = String::from_utf8(buf).unwrap();
+        let mut sorted = recs.clone();
+        sorted.sort();
+        assert_eq!(recs_from_csv(&csv), sorted);
+
+        // Exporting what was read back is unchanged
+        let mut store = RecordStore::open_in_memory().unwrap();
+        store.upsert_all(&recs_from_csv(&csv)).unwrap();
+        let mut buf = Vec::new();
+        store.export_csv(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), csv);
+    }
+}
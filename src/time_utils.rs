@@ -0,0 +1,92 @@
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Parse the time formats found in exchange and price exports into ms
+/// since the epoch, UTC is assumed.
+///
+/// Integers are unix times, in ns from 10^17, µs from 10^14, ms if larger
+/// than 10^11 otherwise in seconds, and `None` if out of chrono's range. Dates may have a time with optional fractional seconds, a
+/// `T` or space separator and a trailing `Z` or ` UTC`. US `%m/%d/%Y` and
+/// European `%d.%m.%Y` dates with a time are accepted too.
+pub fn parse_time_ms(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<i64>() {
+        let ms = match n.unsigned_abs() {
+            100_000_000_000_000_000.. => n / 1_000_000,
+            100_000_000_000_000.. => n / 1000,
+            100_000_000_001.. => n,
+            _ => n * 1000,
+        };
+        return chrono::DateTime::from_timestamp_millis(ms).map(|_| ms);
+    }
+
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Some(dt.timestamp_millis());
+    }
+
+    let s = s
+        .strip_suffix(" UTC")
+        .or_else(|| s.strip_suffix('Z'))
+        .unwrap_or(s);
+    for fmt in [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%m/%d/%Y %H:%M:%S",
+        "%m/%d/%Y %H:%M",
+        "%d.%m.%Y %H:%M:%S",
+        "%d.%m.%Y %H:%M",
+    ] {
+        if let Ok(ndt) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Some(ndt.and_utc().timestamp_millis());
+        }
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp_millis())
+}
+
+/// Format as `%Y-%m-%d %H:%M:%S`, the form TokenTax csv files use, with
+/// milliseconds only if non-zero.
+pub fn format_time_ms(time: i64) -> String {
+    let dt = chrono::DateTime::from_timestamp_millis(time).expect("SNH");
+    if time.rem_euclid(1000) == 0 {
+        dt.format("%Y-%m-%d %H:%M:%S").to_string()
+    } else {
+        dt.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_ms() {
+        let expected = Some(1609459200000);
+        assert_eq!(parse_time_ms("1609459200"), expected);
+        assert_eq!(parse_time_ms("1609459200000"), expected);
+        assert_eq!(parse_time_ms("1609459200000000"), expected);
+        assert_eq!(parse_time_ms("1609459200000000000"), expected);
+        assert_eq!(parse_time_ms("-1609459200000000"), Some(-1609459200000));
+        assert_eq!(parse_time_ms("2021-01-01"), expected);
+        assert_eq!(parse_time_ms("2021-01-01 00:00"), expected);
+        assert_eq!(parse_time_ms("2021-01-01 00:00:00"), expected);
+        assert_eq!(parse_time_ms("2021-01-01 00:00:00.000"), expected);
+        assert_eq!(parse_time_ms("2021-01-01T00:00:00Z"), expected);
+        assert_eq!(parse_time_ms("2021-01-01T01:00:00+01:00"), expected);
+        assert_eq!(parse_time_ms("2021-01-01 00:00:00 UTC"), expected);
+        assert_eq!(parse_time_ms(" 2021-01-01 00:00:00 "), expected);
+        assert_eq!(parse_time_ms("01/01/2021 00:00:00"), expected);
+        assert_eq!(parse_time_ms("01.01.2021 00:00"), expected);
+        assert_eq!(parse_time_ms("Jan 1 2021"), None);
+    }
+
+    #[test]
+    fn test_format_time_ms() {
+        assert_eq!(format_time_ms(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time_ms(1609459200123), "2021-01-01 00:00:00.123");
+        for t in [1609459200000, 1609459200123, -1] {
+            assert_eq!(parse_time_ms(&format_time_ms(t)), Some(t));
+        }
+    }
+}
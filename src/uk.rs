@@ -0,0 +1,400 @@
+//! UK share matching as HMRC applies it to cryptoassets.
+//!
+//! All acquisitions and disposals of an asset on the same day are treated
+//! as one. A disposal is matched first with acquisitions on the same day,
+//! then with acquisitions in the following 30 days (bed and breakfast),
+//! earliest first, and any remainder comes from the Section 104 pool at
+//! its average cost. Days are UTC days and the tax year runs from 6 April
+//! to 5 April.
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, TimeZone, Utc};
+use rust_decimal::prelude::*;
+
+use crate::error::Error;
+use crate::events::{tax_events, TaxEventKind};
+use crate::jurisdiction::{Jurisdiction, UnitedKingdom};
+use crate::price::PriceOracle;
+use crate::TokenTaxRec;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+const BED_AND_BREAKFAST_DAYS: i64 = 30;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchRule {
+    SameDay,
+    BedAndBreakfast,
+    Section104,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub rule: MatchRule,
+    pub quantity: Decimal,
+    pub cost: Decimal,
+
+    /// Day of the matched acquisition, None for the Section 104 pool
+    pub acquired_day: Option<i64>,
+}
+
+/// A day's disposals of an asset, amounts are in the home currency
+#[derive(Clone, Debug, PartialEq)]
+pub struct UkDisposal {
+    /// Days since the epoch
+    pub day: i64,
+    pub asset: String,
+    pub quantity: Decimal,
+    pub proceeds: Decimal,
+    pub fees: Decimal,
+    pub matches: Vec<Match>,
+    pub rec_idxs: Vec<usize>,
+}
+
+impl UkDisposal {
+    pub fn allowable_cost(&self) -> Decimal {
+        self.matches.iter().map(|m| m.cost).sum::<Decimal>() + self.fees
+    }
+
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.allowable_cost()
+    }
+
+    /// Start of the day of the disposal
+    pub fn time(&self) -> i64 {
+        self.day * DAY_MS
+    }
+
+    pub fn tax_year(&self) -> i32 {
+        tax_year(self.time())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Section104Pool {
+    pub quantity: Decimal,
+    pub cost: Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaxYearSummary {
+    pub disposals: usize,
+    pub proceeds: Decimal,
+    pub allowable_costs: Decimal,
+    pub gains: Decimal,
+    pub losses: Decimal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UkReport {
+    pub disposals: Vec<UkDisposal>,
+    pub pools: BTreeMap<String, Section104Pool>,
+}
+
+impl UkReport {
+    pub fn disposals_for_tax_year(&self, year: i32) -> impl Iterator<Item = &UkDisposal> {
+        self.disposals.iter().filter(move |d| d.tax_year() == year)
+    }
+
+    pub fn summary(&self, year: i32) -> TaxYearSummary {
+        let mut summary = TaxYearSummary::default();
+        for d in self.disposals_for_tax_year(year) {
+            let gain = d.gain();
+            summary.disposals += 1;
+            summary.proceeds += d.proceeds;
+            summary.allowable_costs += d.allowable_cost();
+            if gain >= Decimal::ZERO {
+                summary.gains += gain;
+            } else {
+                summary.losses -= gain;
+            }
+        }
+        summary
+    }
+}
+
+/// The tax year containing `time`, identified by the year it starts in,
+/// so 2021 is 6 April 2021 to 5 April 2022.
+pub fn tax_year(time: i64) -> i32 {
+    let dt = Utc.timestamp_millis_opt(time).unwrap();
+    if (dt.month(), dt.day()) < (4, 6) {
+        dt.year() - 1
+    } else {
+        dt.year()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Day {
+    acquired: Decimal,
+    cost: Decimal,
+    unmatched: Decimal,
+    disposal: Option<UkDisposal>,
+
+    /// Removed from the pool without a disposal
+    removed: Decimal,
+    removed_rec_idx: usize,
+}
+
+impl Day {
+    fn unit_cost(&self) -> Decimal {
+        self.cost / self.acquired
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UkCalculator<J: Jurisdiction = UnitedKingdom> {
+    pub jurisdiction: J,
+}
+
+impl UkCalculator<UnitedKingdom> {
+    pub fn new() -> UkCalculator<UnitedKingdom> {
+        UkCalculator {
+            jurisdiction: UnitedKingdom::new(),
+        }
+    }
+}
+
+impl<J: Jurisdiction> UkCalculator<J> {
+    pub fn with_jurisdiction(jurisdiction: J) -> UkCalculator<J> {
+        UkCalculator { jurisdiction }
+    }
+
+    pub fn calculate<O: PriceOracle + ?Sized>(
+        &self,
+        recs: &[TokenTaxRec],
+        oracle: &O,
+    ) -> Result<UkReport, Error> {
+        let events = tax_events(recs, &self.jurisdiction, oracle)?;
+
+        // Combine each day's acquisitions and disposals per asset
+        let mut assets: BTreeMap<String, BTreeMap<i64, Day>> = BTreeMap::new();
+        for e in events.iter() {
+            let day_idx = e.time.div_euclid(DAY_MS);
+            let day = assets
+                .entry(e.asset.clone())
+                .or_default()
+                .entry(day_idx)
+                .or_default();
+            match e.kind {
+                TaxEventKind::Acquire => {
+                    day.acquired += e.quantity;
+                    day.unmatched += e.quantity;
+                    day.cost += e.value + e.fee;
+                }
+                TaxEventKind::Dispose => {
+                    let d = day.disposal.get_or_insert_with(|| UkDisposal {
+                        day: day_idx,
+                        asset: e.asset.clone(),
+                        quantity: Decimal::ZERO,
+                        proceeds: Decimal::ZERO,
+                        fees: Decimal::ZERO,
+                        matches: Vec::new(),
+                        rec_idxs: Vec::new(),
+                    });
+                    d.quantity += e.quantity;
+                    d.proceeds += e.value;
+                    d.fees += e.fee;
+                    if !d.rec_idxs.contains(&e.rec_idx) {
+                        d.rec_idxs.push(e.rec_idx);
+                    }
+                }
+                TaxEventKind::Remove => {
+                    day.removed += e.quantity;
+                    day.removed_rec_idx = e.rec_idx;
+                }
+            }
+        }
+
+        let mut report = UkReport::default();
+        for (asset, mut days) in assets {
+            let day_idxs: Vec<i64> = days.keys().copied().collect();
+
+            // Same day
+            for (&day_idx, day) in days.iter_mut() {
+                let qty = match &day.disposal {
+                    Some(d) => d.quantity.min(day.unmatched),
+                    None => continue,
+                };
+                if qty > Decimal::ZERO {
+                    let cost = qty * day.unit_cost();
+                    day.unmatched -= qty;
+                    day.disposal.as_mut().unwrap().matches.push(Match {
+                        rule: MatchRule::SameDay,
+                        quantity: qty,
+                        cost,
+                        acquired_day: Some(day_idx),
+                    });
+                }
+            }
+
+            // Bed and breakfast, earliest disposal and acquisition first
+            for (i, &day_idx) in day_idxs.iter().enumerate() {
+                let mut remaining = match &days[&day_idx].disposal {
+                    Some(d) => d.quantity - matched(d),
+                    None => continue,
+                };
+                let mut matches = Vec::new();
+                for &acq_idx in day_idxs[i + 1..]
+                    .iter()
+                    .take_while(|&&idx| idx - day_idx <= BED_AND_BREAKFAST_DAYS)
+                {
+                    if remaining <= Decimal::ZERO {
+                        break;
+                    }
+                    let acq = days.get_mut(&acq_idx).unwrap();
+                    let qty = remaining.min(acq.unmatched);
+                    if qty > Decimal::ZERO {
+                        acq.unmatched -= qty;
+                        remaining -= qty;
+                        matches.push(Match {
+                            rule: MatchRule::BedAndBreakfast,
+                            quantity: qty,
+                            cost: qty * acq.unit_cost(),
+                            acquired_day: Some(acq_idx),
+                        });
+                    }
+                }
+                let d = days.get_mut(&day_idx).unwrap().disposal.as_mut().unwrap();
+                d.matches.append(&mut matches);
+            }
+
+            // Section 104 pool
+            let mut pool = Section104Pool::default();
+            for day in days.into_values() {
+                if day.unmatched > Decimal::ZERO {
+                    pool.quantity += day.unmatched;
+                    pool.cost += day.unmatched * day.unit_cost();
+                }
+
+                if day.removed > Decimal::ZERO {
+                    if day.removed > pool.quantity {
+                        return Err(Error::InsufficientHoldings {
+                            asset: asset.clone(),
+                            rec_idx: day.removed_rec_idx,
+                        });
+                    }
+                    pool.cost -= pool.cost * day.removed / pool.quantity;
+                    pool.quantity -= day.removed;
+                }
+
+                let mut d = match day.disposal {
+                    Some(d) => d,
+                    None => continue,
+                };
+                let qty = d.quantity - matched(&d);
+                if qty > Decimal::ZERO {
+                    if qty > pool.quantity {
+                        return Err(Error::InsufficientHoldings {
+                            asset: asset.clone(),
+                            rec_idx: d.rec_idxs[0],
+                        });
+                    }
+                    let cost = pool.cost * qty / pool.quantity;
+                    pool.quantity -= qty;
+                    pool.cost -= cost;
+                    d.matches.push(Match {
+                        rule: MatchRule::Section104,
+                        quantity: qty,
+                        cost,
+                        acquired_day: None,
+                    });
+                }
+                report.disposals.push(d);
+            }
+            report.pools.insert(asset, pool);
+        }
+        report.disposals.sort_by_key(|d| d.day);
+
+        Ok(report)
+    }
+}
+
+impl Default for UkCalculator<UnitedKingdom> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn matched(d: &UkDisposal) -> Decimal {
+    d.matches.iter().map(|m| m.quantity).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::price::PriceTable;
+    use crate::test_utils::{recs_from_csv, time_ms};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_tax_year() {
+        assert_eq!(tax_year(time_ms("2022-04-05 23:59:59")), 2021);
+        assert_eq!(tax_year(time_ms("2022-04-06 00:00:00")), 2022);
+        assert_eq!(tax_year(time_ms("2022-12-31 00:00:00")), 2022);
+        assert_eq!(tax_year(time_ms("2023-01-01 00:00:00")), 2022);
+    }
+
+    #[test]
+    fn test_uk_matching() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,100,BTC,1000,GBP,,,kraken,,,2021-01-01 10:00:00
+Trade,50,BTC,125000,GBP,,,kraken,,,2021-06-01 10:00:00
+Trade,300000,GBP,50,BTC,,,kraken,,,2021-08-30 10:00:00
+Trade,25,BTC,50000,GBP,,,kraken,,,2021-08-30 12:00:00
+Trade,15,BTC,31000,GBP,,,kraken,,,2021-09-05 10:00:00
+",
+        );
+        let report = UkCalculator::new()
+            .calculate(&recs, &PriceTable::new())
+            .unwrap();
+
+        assert_eq!(report.disposals.len(), 1);
+        let d = &report.disposals[0];
+        assert_eq!(d.quantity, dec!(50));
+        assert_eq!(d.proceeds, dec!(300000));
+        assert_eq!(d.rec_idxs, vec![2]);
+        assert_eq!(d.matches.len(), 3);
+        assert_eq!(d.matches[0].rule, MatchRule::SameDay);
+        assert_eq!(d.matches[0].quantity, dec!(25));
+        assert_eq!(d.matches[0].cost, dec!(50000));
+        assert_eq!(d.matches[1].rule, MatchRule::BedAndBreakfast);
+        assert_eq!(d.matches[1].quantity, dec!(15));
+        assert_eq!(d.matches[1].cost, dec!(31000));
+        assert_eq!(d.matches[2].rule, MatchRule::Section104);
+        assert_eq!(d.matches[2].quantity, dec!(10));
+        assert_eq!(d.matches[2].cost, dec!(8400));
+        assert_eq!(d.gain(), dec!(210600));
+        assert_eq!(d.tax_year(), 2021);
+
+        assert_eq!(
+            report.pools["BTC"],
+            Section104Pool {
+                quantity: dec!(140),
+                cost: dec!(117600),
+            }
+        );
+
+        let summary = report.summary(2021);
+        assert_eq!(summary.disposals, 1);
+        assert_eq!(summary.gains, dec!(210600));
+        assert_eq!(summary.losses, dec!(0));
+        assert_eq!(report.summary(2020), TaxYearSummary::default());
+    }
+
+    #[test]
+    fn test_uk_insufficient_holdings() {
+        let recs = recs_from_csv(
+            "
+Type,BuyAmount,BuyCurrency,SellAmount,SellCurrency,FeeAmount,FeeCurrency,Exchange,Group,Comment,Date
+Trade,1000,GBP,1,ETH,,,kraken,,,2021-01-01 00:00:00
+Trade,1,ETH,1000,GBP,,,kraken,,,2021-03-01 00:00:00
+",
+        );
+        assert!(matches!(
+            UkCalculator::new().calculate(&recs, &PriceTable::new()),
+            Err(Error::InsufficientHoldings { rec_idx: 0, .. })
+        ));
+    }
+}